@@ -0,0 +1,147 @@
+use noodles_bam::{self as bam, bai};
+use noodles_bgzf as bgzf;
+use noodles_csi as csi;
+use noodles_csi::binning_index::index::reference_sequence::bin::Chunk;
+use noodles_csi::binning_index::index::reference_sequence::index::{BinnedIndex, LinearIndex};
+use noodles_csi::binning_index::Indexer;
+use noodles_sam as sam;
+use noodles_sam::alignment::io::Write as _;
+use noodles_sam::alignment::Record;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The kind of index written next to a coordinate-sorted BAM output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum BamIndexFormat {
+    /// BAM index (.bai), for reference sequences up to 2^29 bases
+    Bai,
+    /// Coordinate-sorted index (.csi), for longer reference sequences
+    Csi,
+}
+
+impl BamIndexFormat {
+    /// The path of the index of the BAM file at `path` - e.g. out.bam -> out.bam.bai
+    pub fn index_path(self, path: &Path) -> PathBuf {
+        let mut path = OsString::from(path.as_os_str());
+        path.push(match self {
+            BamIndexFormat::Bai => ".bai",
+            BamIndexFormat::Csi => ".csi",
+        });
+        PathBuf::from(path)
+    }
+}
+
+enum BamIndexer {
+    Bai(Indexer<LinearIndex>),
+    Csi(Indexer<BinnedIndex>),
+}
+
+/// A BAM writer that indexes the records as they are written, and writes the index next to the
+/// BAM when it is finished. The records must be written in coordinate order.
+pub struct IndexedBamWriter {
+    writer: bam::io::Writer<bgzf::Writer<File>>,
+    indexer: BamIndexer,
+    index_path: PathBuf,
+    /// The position the next record starts at.
+    start: bgzf::VirtualPosition,
+}
+
+impl IndexedBamWriter {
+    pub fn create(path: &Path, format: BamIndexFormat) -> io::Result<Self> {
+        let writer = bam::io::Writer::new(File::create(path)?);
+        let indexer = match format {
+            BamIndexFormat::Bai => BamIndexer::Bai(Indexer::default()),
+            BamIndexFormat::Csi => BamIndexer::Csi(Indexer::default()),
+        };
+        Ok(IndexedBamWriter {
+            start: writer.get_ref().virtual_position(),
+            writer,
+            indexer,
+            index_path: format.index_path(path),
+        })
+    }
+
+    pub fn write_header(&mut self, header: &sam::Header) -> io::Result<()> {
+        self.writer.write_header(header)?;
+        self.start = self.writer.get_ref().virtual_position();
+        Ok(())
+    }
+
+    pub fn write_record<R: Record>(&mut self, header: &sam::Header, record: &R) -> io::Result<()> {
+        self.writer.write_alignment_record(header, record)?;
+        let end = self.writer.get_ref().virtual_position();
+        let context = match (
+            record.reference_sequence_id(header).transpose()?,
+            record.alignment_start().transpose()?,
+            record.alignment_end().transpose()?,
+        ) {
+            (Some(id), Some(start), Some(end)) => {
+                Some((id, start, end, !record.flags()?.is_unmapped()))
+            }
+            _ => None,
+        };
+        let chunk = Chunk::new(self.start, end);
+        match &mut self.indexer {
+            BamIndexer::Bai(indexer) => indexer.add_record(context, chunk)?,
+            BamIndexer::Csi(indexer) => indexer.add_record(context, chunk)?,
+        }
+        self.start = end;
+        Ok(())
+    }
+
+    /// Finish the BAM and write its index.
+    pub fn finish(&mut self, header: &sam::Header) -> io::Result<()> {
+        self.writer.try_finish()?;
+        let nb_references = header.reference_sequences().len();
+        match std::mem::replace(&mut self.indexer, BamIndexer::Bai(Indexer::default())) {
+            BamIndexer::Bai(indexer) => bai::write(&self.index_path, &indexer.build(nb_references)),
+            BamIndexer::Csi(indexer) => csi::write(&self.index_path, &indexer.build(nb_references)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn index_written_records() {
+        let dir = tempfile::tempdir().unwrap();
+        let text = "@HD\tVN:1.6\tSO:coordinate\n@SQ\tSN:chr1\tLN:1000\n@SQ\tSN:chr2\tLN:1000\n\
+            r0\t0\tchr1\t1\t60\t4M\t*\t0\t0\tACGT\t*\n\
+            r1\t0\tchr1\t500\t60\t4M\t*\t0\t0\tACGT\t*\n\
+            r2\t0\tchr2\t10\t60\t4M\t*\t0\t0\tACGT\t*\n\
+            r3\t4\t*\t0\t0\t*\t*\t0\t0\tACGT\t*\n";
+        let mut reader = sam::io::Reader::new(text.as_bytes());
+        let header = reader.read_header().unwrap();
+        let records = reader.records().collect::<io::Result<Vec<_>>>().unwrap();
+
+        for format in [BamIndexFormat::Bai, BamIndexFormat::Csi] {
+            let path = dir.path().join("out.bam");
+            let mut writer = IndexedBamWriter::create(&path, format).unwrap();
+            writer.write_header(&header).unwrap();
+            for record in &records {
+                writer.write_record(&header, record).unwrap();
+            }
+            writer.finish(&header).unwrap();
+
+            let index_path = format.index_path(&path);
+            let index: Box<dyn csi::BinningIndex> = match format {
+                BamIndexFormat::Bai => Box::new(bai::read(&index_path).unwrap()),
+                BamIndexFormat::Csi => Box::new(csi::read(&index_path).unwrap()),
+            };
+            assert_eq!(index.unplaced_unmapped_record_count(), Some(1));
+            let mut reader = bam::io::IndexedReader::new(File::open(&path).unwrap(), index);
+            let header = reader.read_header().unwrap();
+            let region = "chr1:400-600".parse().unwrap();
+            let names = reader
+                .query(&header, &region)
+                .unwrap()
+                .map(|record| record.unwrap().name().unwrap().as_bytes().to_vec())
+                .collect::<Vec<_>>();
+            assert_eq!(names, vec![b"r1".to_vec()]);
+        }
+    }
+}
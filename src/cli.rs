@@ -1,12 +1,14 @@
-use clap::Parser;
+use crate::bam_index::BamIndexFormat;
+use crate::io::FileFormat;
+use clap::{Args, Parser, Subcommand};
 use lazy_static::lazy_static;
-use ontime::DurationExt;
+use ontime::{parse_timestamp, parse_utc_offset, DurationExt, Overlap};
 use regex::{Regex, RegexBuilder};
 use std::ffi::OsStr;
+use std::num::NonZeroUsize;
 use std::path::{Path, PathBuf};
 use thiserror::Error;
-use time::format_description::well_known::Rfc3339;
-use time::{Duration, PrimitiveDateTime};
+use time::{Duration, UtcOffset};
 
 lazy_static! {
     pub static ref DURATION_RE: Regex = RegexBuilder::new(
@@ -21,29 +23,63 @@ lazy_static! {
 /// Extract subsets of ONT (Nanopore) reads based on time
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct Cli {
-    /// Input fastq/fasta/BAM/SAM file
-    #[clap(value_parser = check_path_exists, value_name = "FILE")]
-    pub input: PathBuf,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    /// Input fastq/fasta/BAM/SAM/CRAM file(s) or directories. Use - to read from stdin
+    ///
+    /// Directories are searched recursively for files matching --glob. Reads from all inputs are
+    /// written to a single output, and the first and last timestamps are taken across all inputs
+    #[clap(value_parser = check_path_exists, value_name = "FILE", required = true, num_args = 1..)]
+    pub input: Vec<PathBuf>,
+    /// Pattern that file names must match to be used when an input is a directory - e.g. '*.fastq.gz'
+    ///
+    /// By default, files with a fastq/fasta/SAM/BAM/CRAM extension (optionally compressed) are used
+    #[clap(long, value_name = "PATTERN")]
+    pub glob: Option<String>,
+    /// Format of the input, if it cannot be detected from its content
+    #[clap(long, value_enum, value_name = "FORMAT")]
+    pub input_format: Option<FileFormat>,
+    /// Reference fasta used to decode CRAM input and encode CRAM output
+    ///
+    /// An index (.fai) is used if there is one next to the reference. Missing MD5s (M5) in the
+    /// @SQ lines of the output header are filled in from the reference
+    #[clap(short = 'T', long, value_parser = check_path_exists, value_name = "FILE")]
+    pub reference: Option<PathBuf>,
     /// Output file name [default: stdout]
     ///
-    /// Note: you cannot output a fastq if a BAM/SAM input is given and vice versa. Use samtools for post-processing.
-    /// However, you can output SAM if the input is BAM and vice versa.
+    /// BAM/SAM/CRAM input can be written as fastq/fasta, with the st, ch, RG, MM, ML and qs tags
+    /// carried into the read header - e.g. @read st:Z:2022-12-12T12:00:00Z. fastq/fasta input can
+    /// be written as unaligned BAM/SAM/CRAM, with the start_time, ch, read and duration fields
+    /// turned into st, ch, rn and du tags, and a read group (RG) for each run. You can also convert
+    /// between SAM, BAM and CRAM. Use samtools for post-processing.
     #[clap(short, long, value_name = "FILE")]
     pub output: Option<PathBuf>,
-    /// (fastq/a output only) u: uncompressed; b: Bzip2; g: Gzip; l: Lzma
+    /// Format of the output, if it cannot be detected from its extension - e.g. fastx to write
+    /// BAM input to stdout as fastq
+    #[clap(long, value_enum, value_name = "FORMAT")]
+    pub output_format: Option<FileFormat>,
+    /// (fastq/a output only) u: uncompressed; b: Bzip2; g: Gzip; l: Lzma; bgzf: blocked Gzip
     ///
     /// ontime will attempt to infer the output compression format automatically from the output
-    /// extension. If writing to stdout, the default is uncompressed (u)
-    #[clap(short = 'O', long, value_name = "u|b|g|l", value_parser = parse_compression_format, ignore_case=true, hide_possible_values = true)]
-    pub output_type: Option<niffler::compression::Format>,
+    /// extension - .bgz is BGZF. If writing to stdout, the default is uncompressed (u)
+    #[clap(short = 'O', long, value_name = "u|b|g|l|bgzf", value_parser = parse_output_type, ignore_case=true, hide_possible_values = true)]
+    pub output_type: Option<OutputCompression>,
     /// Compression level to use if compressing fastq output
+    ///
+    /// With --threads, gzip and BGZF output is only compressed in parallel at the default level
+    /// (6). At any other level, it is compressed on a single thread
     #[clap(short = 'L', long, value_parser = parse_level, default_value="6", value_name = "1-21")]
     pub compress_level: niffler::Level,
+    /// Number of threads used to parse fastq/a, (de)compress BGZF (BAM), and compress gzip/zstd fastq/a output
+    #[clap(short = '@', long, default_value = "1", value_name = "INT")]
+    pub threads: NonZeroUsize,
     /// Earliest start time; otherwise the earliest time is used
     ///
-    /// This can be a timestamp - e.g. 2022-11-20T18:00:00 - or a duration from the start - e.g.
-    /// 2h30m (2 hours and 30 minutes from the start). See the docs for more examples
+    /// This can be a timestamp - e.g. 2022-11-20T18:00:00Z or 2022-11-21T04:00:00+10:00 - or a
+    /// duration from the start - e.g. 2h30m (2 hours and 30 minutes from the start). See the docs
+    /// for more examples
     #[clap(short = 'f', long = "from", value_parser = validate_time, value_name = "DATE/DURATION", allow_hyphen_values = true)]
     pub earliest: Option<String>,
     /// Latest start time; otherwise the latest time is used
@@ -51,9 +87,203 @@ pub struct Cli {
     /// See --from (and docs) for examples
     #[clap(short = 't', long = "to", value_parser = validate_time, value_name = "DATE/DURATION", allow_hyphen_values = true)]
     pub latest: Option<String>,
+    /// A time range to extract reads from - e.g. 0s..2h or 10h..12h. Can be given multiple times
+    ///
+    /// FROM and TO use the same format as --from/--to and either can be left empty to mean the
+    /// start or end of the run - e.g. ..2h or -1h.. - Overlapping ranges are merged and reads that
+    /// fall in any of the ranges are kept
+    #[clap(short, long = "range", value_parser = validate_range, value_name = "FROM..TO", conflicts_with_all = ["earliest", "latest", "windows", "until_bases", "until_depth"], allow_hyphen_values = true)]
+    pub ranges: Vec<String>,
+    /// How a read is matched against the timeframe
+    ///
+    /// start: the read starts in the timeframe; end: the read ends in the timeframe; contained:
+    /// the read starts and ends in the timeframe; overlaps: any part of the read is in the
+    /// timeframe. End times come from the read's duration - the du:f tag or duration= field
+    #[clap(
+        short = 'm',
+        long = "match",
+        value_enum,
+        default_value = "start",
+        value_name = "MODE",
+        hide_possible_values = true
+    )]
+    pub overlap: Overlap,
+    /// Invert the selection - i.e. keep the reads that are *not* in the timeframe
+    ///
+    /// Works with --from/--to and --range. For example, --exclude --to 10m drops the first 10
+    /// minutes of the run
+    #[clap(
+        short = 'x',
+        long,
+        visible_alias = "exclude",
+        conflicts_with = "windows"
+    )]
+    pub invert: bool,
+    /// Extract multiple cumulative windows in a single pass - e.g. 1h,2h,4h,8h
+    ///
+    /// Each window starts at --from (or the earliest start time) and ends at the given time, which
+    /// uses the same format as --to. One file is written per window, named using --output as a
+    /// template. The template can contain {stem} - the input file name without extensions - and
+    /// {window} - the window end as given here. For example, {stem}.{window}.fq.gz
+    #[clap(short, long, value_delimiter = ',', value_parser = validate_time, value_name = "DATE/DURATION", conflicts_with = "latest", requires = "output", allow_hyphen_values = true)]
+    pub windows: Vec<String>,
+    /// Keep reads, in start time order, until this many bases have been sequenced - e.g. 5Gb
+    ///
+    /// The start time at which the target is reached is used as --to. Accepts an optional
+    /// k/m/g/t suffix (case-insensitive) followed by an optional b or bp - e.g. 500Mb or 1.5G
+    #[clap(short = 'b', long, value_parser = parse_bases, value_name = "BASES", conflicts_with_all = ["latest", "windows"])]
+    pub until_bases: Option<u64>,
+    /// Keep reads, in start time order, until this depth of coverage is reached - e.g. 50x
+    ///
+    /// The depth is turned into a number of bases using --genome-size and behaves like
+    /// --until-bases
+    #[clap(short = 'd', long, value_parser = parse_depth, value_name = "DEPTH", requires = "genome_size", conflicts_with_all = ["latest", "windows", "until_bases"])]
+    pub until_depth: Option<f64>,
+    /// Genome size used to calculate depth of coverage - e.g. 5Mb
+    ///
+    /// When used with --show, the times at which each multiple of 10x depth was reached are
+    /// also printed
+    #[clap(short = 'g', long, value_parser = parse_bases, value_name = "BASES")]
+    pub genome_size: Option<u64>,
+    /// Each input is sorted by start time
+    ///
+    /// Lets ontime extract reads in a single pass when --from/--to (or --range) are timestamps or
+    /// (positive) durations from the start, and stop reading once the timeframe has passed.
+    /// Without this, a single pass is only possible when the timeframe is given as timestamps.
+    /// This does not sort anything - see --sort-output to write the reads in start time order
+    #[clap(long)]
+    pub sorted: bool,
+    /// Write the output in start time order
+    ///
+    /// Reads are sorted in memory, or with a merge sort using temporary files on disk when they
+    /// take up more than --sort-memory. Alignment outputs get an @HD SO:unknown
+    /// SS:unknown:start_time header line. This is not needed to tell ontime the input is sorted -
+    /// see --sorted for that
+    #[clap(long)]
+    pub sort_output: bool,
+    /// Memory used to sort reads before they are written to temporary files - e.g. 500M or 2G
+    #[clap(long, value_parser = parse_memory, default_value = "1G", value_name = "BYTES", requires = "sort_output")]
+    pub sort_memory: usize,
+    /// Only write the primary record of each read (BAM/SAM/CRAM input)
+    ///
+    /// By default, the secondary and supplementary records of a read are kept or dropped along
+    /// with its primary record, whose start time is used
+    #[clap(long)]
+    pub primary_only: bool,
+    /// Do not read or write start time indices (<FILE>.ontime)
+    ///
+    /// By default, the start times gathered from an input file are saved next to it, and later
+    /// runs on the same (unchanged) file read them instead of scanning the file again. The index is
+    /// not saved if it cannot be written, e.g. in a read-only directory. See also `ontime index`
+    #[clap(long)]
+    pub no_index: bool,
+    /// Write indices next to each output: .fai (fasta) or .fqi (fastq) and .gzi for BGZF, or
+    /// .bai/.csi for BAM
+    ///
+    /// fasta/fastq outputs must be uncompressed or BGZF (-O bgzf or a .bgz extension), so they can
+    /// be read with e.g. samtools faidx/fqidx. BAM outputs are indexed as they are written, with a
+    /// BAI (--write-index or --write-index=bai) or CSI (--write-index=csi) index, and must be
+    /// coordinate-sorted (@HD SO:coordinate) - i.e. come from coordinate-sorted input, without
+    /// --sort-output
+    #[clap(long, value_enum, value_name = "bai|csi", num_args = 0..=1, require_equals = true, default_missing_value = "bai", requires = "output", hide_possible_values = true)]
+    pub write_index: Option<BamIndexFormat>,
     /// Show the earliest and latest start times in the input and exit
     #[clap(short, long)]
     pub show: bool,
+    /// UTC offset to display timestamps in - e.g. +10:00 or -05:30
+    ///
+    /// All timestamps are normalised to UTC internally. This only changes how they are printed
+    #[clap(short = 'z', long, value_parser = parse_display_offset, default_value = "Z", value_name = "OFFSET", allow_hyphen_values = true)]
+    pub utc_offset: UtcOffset,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Split the reads into consecutive, non-overlapping time bins - e.g. hour 0-1, 1-2, 2-3
+    Split(Split),
+    /// Write a start time index (<FILE>.ontime) next to each input, which later runs read instead
+    /// of scanning the input
+    Index(Index),
+}
+
+#[derive(Args, Debug)]
+pub struct Split {
+    /// Input fastq/fasta/BAM/SAM/CRAM file. Use - to read from stdin
+    #[clap(value_parser = check_path_exists, value_name = "FILE")]
+    pub input: PathBuf,
+    /// Output file name template
+    ///
+    /// One file is written per bin. The template can contain {stem} - the input file name without
+    /// extensions - and {bin} - the (0-based) index of the bin. For example, {stem}.{bin}.fq.gz
+    #[clap(short, long, value_name = "TEMPLATE")]
+    pub output: PathBuf,
+    /// The length of each bin - e.g. 1h or 30m
+    ///
+    /// Bins are half-open, so a read starting exactly on a boundary is put in the later bin
+    #[clap(short, long, value_parser = parse_bin_size, value_name = "DURATION")]
+    pub every: Duration,
+    /// Format of the input, if it cannot be detected from its content
+    #[clap(long, value_enum, value_name = "FORMAT")]
+    pub input_format: Option<FileFormat>,
+    /// Reference fasta used to decode CRAM input and encode CRAM output
+    ///
+    /// An index (.fai) is used if there is one next to the reference. Missing MD5s (M5) in the
+    /// @SQ lines of the output header are filled in from the reference
+    #[clap(short = 'T', long, value_parser = check_path_exists, value_name = "FILE")]
+    pub reference: Option<PathBuf>,
+    /// Align bins to wall-clock boundaries - e.g. the top of the hour - instead of the earliest
+    /// start time
+    #[clap(long)]
+    pub wall_clock: bool,
+    /// Only write the primary record of each read (BAM/SAM/CRAM input)
+    ///
+    /// By default, the secondary and supplementary records of a read go in the bin of its primary
+    /// record
+    #[clap(long)]
+    pub primary_only: bool,
+    /// Do not read or write a start time index (<FILE>.ontime)
+    ///
+    /// By default, the start times gathered from the input file are saved next to it, and later
+    /// runs on the same (unchanged) file read them instead of scanning the file again. See
+    /// --no-index of the main command
+    #[clap(long)]
+    pub no_index: bool,
+    /// (fastq/a output only) u: uncompressed; b: Bzip2; g: Gzip; l: Lzma; bgzf: blocked Gzip
+    ///
+    /// ontime will attempt to infer the output compression format automatically from the output
+    /// extension - .bgz is BGZF
+    #[clap(short = 'O', long, value_name = "u|b|g|l|bgzf", value_parser = parse_output_type, ignore_case=true, hide_possible_values = true)]
+    pub output_type: Option<OutputCompression>,
+    /// Compression level to use if compressing fastq output
+    ///
+    /// With --threads, gzip and BGZF output is only compressed in parallel at the default level
+    /// (6). At any other level, it is compressed on a single thread
+    #[clap(short = 'L', long, value_parser = parse_level, default_value="6", value_name = "1-21")]
+    pub compress_level: niffler::Level,
+    /// Number of threads used to parse fastq/a, (de)compress BGZF (BAM), and compress gzip/zstd fastq/a output
+    #[clap(short = '@', long, default_value = "1", value_name = "INT")]
+    pub threads: NonZeroUsize,
+}
+
+#[derive(Args, Debug)]
+pub struct Index {
+    /// Input fastq/fasta/BAM/SAM/CRAM file(s) or directories
+    ///
+    /// Directories are searched recursively for files matching --glob
+    #[clap(value_parser = check_path_exists, value_name = "FILE", required = true, num_args = 1..)]
+    pub input: Vec<PathBuf>,
+    /// Pattern that file names must match to be used when an input is a directory - e.g. '*.fastq.gz'
+    #[clap(long, value_name = "PATTERN")]
+    pub glob: Option<String>,
+    /// Format of the input, if it cannot be detected from its content
+    #[clap(long, value_enum, value_name = "FORMAT")]
+    pub input_format: Option<FileFormat>,
+    /// Reference fasta used to decode CRAM input
+    #[clap(short = 'T', long, value_parser = check_path_exists, value_name = "FILE")]
+    pub reference: Option<PathBuf>,
+    /// Number of threads used to parse fastq/a and decompress BGZF (BAM)
+    #[clap(short = '@', long, default_value = "1", value_name = "INT")]
+    pub threads: NonZeroUsize,
 }
 
 /// A collection of custom errors relating to the command line interface for this package.
@@ -76,13 +306,32 @@ impl CompressionExt for niffler::compression::Format {
         match path.extension().map(|s| s.to_str()) {
             Some(Some("gz")) => Self::Gzip,
             Some(Some("bz") | Some("bz2")) => Self::Bzip,
-            Some(Some("lzma")) => Self::Lzma,
-            Some(Some("zst")) => Self::Zstd,
+            Some(Some("lzma" | "xz")) => Self::Lzma,
+            Some(Some("zst" | "zstd")) => Self::Zstd,
             _ => Self::No,
         }
     }
 }
 
+/// The compression of fasta/fastq output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputCompression {
+    /// A format niffler can write.
+    Niffler(niffler::compression::Format),
+    /// Blocked gzip, which can be indexed and read at random - e.g. by samtools faidx.
+    Bgzf,
+}
+
+impl CompressionExt for OutputCompression {
+    /// Infers the compression type from the file extension, where .bgz is BGZF.
+    fn from_path<S: AsRef<OsStr> + ?Sized>(p: &S) -> Self {
+        match Path::new(p).extension().and_then(|s| s.to_str()) {
+            Some("bgz") => Self::Bgzf,
+            _ => Self::Niffler(niffler::Format::from_path(p)),
+        }
+    }
+}
+
 /// A utility function to validate compression level is in allowed range between 1 and 21
 fn parse_level(s: &str) -> Result<niffler::Level, String> {
     let lvl = match s.parse::<u8>() {
@@ -123,10 +372,17 @@ fn parse_compression_format(s: &str) -> Result<niffler::compression::Format, Cli
     }
 }
 
-/// A utility function that allows the CLI to error if a path doesn't exist
+fn parse_output_type(s: &str) -> Result<OutputCompression, CliError> {
+    if s.eq_ignore_ascii_case("bgzf") {
+        return Ok(OutputCompression::Bgzf);
+    }
+    parse_compression_format(s).map(OutputCompression::Niffler)
+}
+
+/// A utility function that allows the CLI to error if a path doesn't exist. `-` (stdin) is allowed
 fn check_path_exists<S: AsRef<OsStr> + ?Sized>(s: &S) -> Result<PathBuf, String> {
     let path = PathBuf::from(s);
-    if path.exists() {
+    if path == Path::new("-") || path.exists() {
         Ok(path)
     } else {
         Err(format!("{:?} does not exist", path))
@@ -134,13 +390,72 @@ fn check_path_exists<S: AsRef<OsStr> + ?Sized>(s: &S) -> Result<PathBuf, String>
 }
 
 fn validate_time(s: &str) -> Result<String, String> {
-    if PrimitiveDateTime::parse(s, &Rfc3339).is_ok() || Duration::from_str(s).is_ok() {
+    if parse_timestamp(s).is_ok() || Duration::from_str(s).is_ok() {
         Ok(s.to_string())
     } else {
         Err(format!("{} is not a recognised time format", s))
     }
 }
 
+/// Parse a number of bases with an optional metric suffix - e.g. 5Gb, 500m, 1.5kbp, or 1000
+fn parse_bases(s: &str) -> Result<u64, String> {
+    let lower = s.trim().to_lowercase();
+    let num = lower
+        .strip_suffix("bp")
+        .or_else(|| lower.strip_suffix('b'))
+        .unwrap_or(&lower);
+    let (num, multiplier) = match num.chars().last() {
+        Some('k') => (&num[..num.len() - 1], 1e3),
+        Some('m') => (&num[..num.len() - 1], 1e6),
+        Some('g') => (&num[..num.len() - 1], 1e9),
+        Some('t') => (&num[..num.len() - 1], 1e12),
+        _ => (num, 1.0),
+    };
+    match num.parse::<f64>() {
+        Ok(n) if n.is_finite() && n > 0.0 => Ok((n * multiplier).round() as u64),
+        _ => Err(format!("{} is not a valid number of bases", s)),
+    }
+}
+
+/// Parse an amount of memory with an optional metric suffix - e.g. 2G or 500M
+fn parse_memory(s: &str) -> Result<usize, String> {
+    parse_bases(s)
+        .map(|n| n as usize)
+        .map_err(|_| format!("{} is not a valid amount of memory", s))
+}
+
+/// Parse a depth of coverage with an optional x suffix - e.g. 50x or 12.5
+fn parse_depth(s: &str) -> Result<f64, String> {
+    let num = s.trim().trim_end_matches(['x', 'X']);
+    match num.parse::<f64>() {
+        Ok(d) if d.is_finite() && d > 0.0 => Ok(d),
+        _ => Err(format!("{} is not a valid depth", s)),
+    }
+}
+
+fn parse_bin_size(s: &str) -> Result<Duration, String> {
+    match Duration::from_str(s) {
+        Ok(d) if d.is_positive() => Ok(d),
+        _ => Err(format!("{} is not a valid (positive) duration", s)),
+    }
+}
+
+fn validate_range(s: &str) -> Result<String, String> {
+    let (from, to) = s
+        .split_once("..")
+        .ok_or_else(|| format!("{} is not a range of the form FROM..TO", s))?;
+    for t in [from, to] {
+        if !t.is_empty() {
+            validate_time(t)?;
+        }
+    }
+    Ok(s.to_string())
+}
+
+fn parse_display_offset(s: &str) -> Result<UtcOffset, String> {
+    parse_utc_offset(s).map_err(|_| format!("{} is not a recognised UTC offset", s))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -158,6 +473,13 @@ mod tests {
         assert_eq!(actual, expected)
     }
 
+    #[test]
+    fn check_path_exists_stdin() {
+        let actual = check_path_exists(OsStr::new("-")).unwrap();
+        let expected = PathBuf::from("-");
+        assert_eq!(actual, expected)
+    }
+
     #[test]
     fn compression_format_from_str() {
         let mut s = "B";
@@ -221,6 +543,25 @@ mod tests {
         );
     }
 
+    #[test]
+    fn output_type_from_str_and_path() {
+        assert_eq!(parse_output_type("BGZF").unwrap(), OutputCompression::Bgzf);
+        assert_eq!(
+            parse_output_type("g").unwrap(),
+            OutputCompression::Niffler(niffler::Format::Gzip)
+        );
+        assert!(parse_output_type("bgz").is_err());
+
+        assert_eq!(
+            OutputCompression::from_path("reads.fq.bgz"),
+            OutputCompression::Bgzf
+        );
+        assert_eq!(
+            OutputCompression::from_path("reads.fq.gz"),
+            OutputCompression::Niffler(niffler::Format::Gzip)
+        );
+    }
+
     #[test]
     fn test_validate_time() {
         let valid_times = [
@@ -246,4 +587,67 @@ mod tests {
         ];
         assert!(invalid_times.iter().all(|s| validate_time(s).is_err()))
     }
+
+    #[test]
+    fn test_parse_bases() {
+        assert_eq!(parse_bases("1000").unwrap(), 1_000);
+        assert_eq!(parse_bases("5Gb").unwrap(), 5_000_000_000);
+        assert_eq!(parse_bases("500m").unwrap(), 500_000_000);
+        assert_eq!(parse_bases("1.5kbp").unwrap(), 1_500);
+        assert_eq!(parse_bases("2T").unwrap(), 2_000_000_000_000);
+        assert!(parse_bases("0").is_err());
+        assert!(parse_bases("-5G").is_err());
+        assert!(parse_bases("5x").is_err());
+        assert!(parse_bases("Gb").is_err());
+    }
+
+    #[test]
+    fn test_parse_depth() {
+        assert_eq!(parse_depth("50x").unwrap(), 50.0);
+        assert_eq!(parse_depth("12.5").unwrap(), 12.5);
+        assert!(parse_depth("0x").is_err());
+        assert!(parse_depth("x").is_err());
+        assert!(parse_depth("-5x").is_err());
+    }
+
+    #[test]
+    fn test_parse_memory() {
+        assert_eq!(parse_memory("2G").unwrap(), 2_000_000_000);
+        assert_eq!(parse_memory("500m").unwrap(), 500_000_000);
+        assert_eq!(parse_memory("1024").unwrap(), 1024);
+        assert!(parse_memory("lots").is_err());
+    }
+
+    #[test]
+    fn test_parse_bin_size() {
+        assert_eq!(parse_bin_size("1h").unwrap(), Duration::hours(1));
+        assert!(parse_bin_size("-1h").is_err());
+        assert!(parse_bin_size("0s").is_err());
+        assert!(parse_bin_size("foo").is_err());
+    }
+
+    #[test]
+    fn test_validate_range() {
+        let valid_ranges = [
+            "0s..2h",
+            "10h..12h",
+            "..2h",
+            "-1h..",
+            "-2h..-1h",
+            "2022-12-12T18:39:09Z..2022-12-12T19:39:09.5Z",
+            "..",
+        ];
+        for s in valid_ranges {
+            assert!(validate_range(s).is_ok(), "{}", s);
+        }
+        let invalid_ranges = ["2h", "1h-2h", "1h..2foo", "2022-12-12T18:39:09..1h"];
+        assert!(invalid_ranges.iter().all(|s| validate_range(s).is_err()))
+    }
+
+    #[test]
+    fn test_parse_display_offset() {
+        assert!(parse_display_offset("+10:00").is_ok());
+        assert!(parse_display_offset("Z").is_ok());
+        assert!(parse_display_offset("10h").is_err());
+    }
 }
@@ -0,0 +1,292 @@
+use crate::io::{build_gzi_index, is_bgzf, strip_compression_extension};
+use noodles_bgzf as bgzf;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+
+/// A line of a fasta (.fai) or fastq (.fqi) index, in the format of samtools faidx/fqidx.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Entry {
+    name: Vec<u8>,
+    length: u64,
+    /// The offset of the first base in the (uncompressed) file.
+    offset: u64,
+    line_bases: u64,
+    line_width: u64,
+    /// The offset of the first quality score, for fastq.
+    qual_offset: Option<u64>,
+}
+
+impl Entry {
+    fn new(header: &[u8], offset: u64) -> Self {
+        let name = header[1..]
+            .split(|b| b.is_ascii_whitespace())
+            .next()
+            .unwrap_or_default()
+            .to_vec();
+        Entry {
+            name,
+            length: 0,
+            offset,
+            line_bases: 0,
+            line_width: 0,
+            qual_offset: None,
+        }
+    }
+
+    fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&self.name)?;
+        write!(
+            writer,
+            "\t{}\t{}\t{}\t{}",
+            self.length, self.offset, self.line_bases, self.line_width
+        )?;
+        if let Some(qual_offset) = self.qual_offset {
+            write!(writer, "\t{}", qual_offset)?;
+        }
+        writeln!(writer)
+    }
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// The number of bases on a line, without its line ending.
+fn nb_bases(line: &[u8]) -> u64 {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    line.strip_suffix(b"\r").unwrap_or(line).len() as u64
+}
+
+/// Index a fasta file, whose records can span several lines as long as every line of a record but
+/// the last has the same length.
+fn index_fasta<R: BufRead>(mut reader: R) -> io::Result<Vec<Entry>> {
+    let mut entries: Vec<Entry> = vec![];
+    let mut line = vec![];
+    let mut pos = 0;
+    // whether the record has had a line shorter than the others, which must be its last
+    let mut short_line = false;
+    loop {
+        line.clear();
+        let len = reader.read_until(b'\n', &mut line)? as u64;
+        if len == 0 {
+            return Ok(entries);
+        }
+        pos += len;
+        if line[0] == b'>' {
+            entries.push(Entry::new(&line, pos));
+            short_line = false;
+            continue;
+        }
+        let entry = entries
+            .last_mut()
+            .ok_or_else(|| invalid("sequence before the first fasta header".to_string()))?;
+        let bases = nb_bases(&line);
+        if bases > 0 && entry.line_bases == 0 && !short_line {
+            entry.line_bases = bases;
+            entry.line_width = len;
+        } else if bases > 0 && (short_line || bases > entry.line_bases || len > entry.line_width) {
+            return Err(invalid(format!(
+                "different line lengths in fasta record {}",
+                String::from_utf8_lossy(&entry.name)
+            )));
+        }
+        short_line |= bases < entry.line_bases || bases == 0;
+        entry.length += bases;
+    }
+}
+
+/// Index a fastq file, whose records are four lines.
+fn index_fastq<R: BufRead>(mut reader: R) -> io::Result<Vec<Entry>> {
+    let mut entries = vec![];
+    let mut lines = [vec![], vec![], vec![], vec![]];
+    let mut pos = 0;
+    loop {
+        let start = pos;
+        let mut offsets = [0; 4];
+        for (line, offset) in lines.iter_mut().zip(offsets.iter_mut()) {
+            line.clear();
+            *offset = pos;
+            pos += reader.read_until(b'\n', line)? as u64;
+        }
+        let [header, seq, plus, qual] = &lines;
+        if header.iter().all(u8::is_ascii_whitespace) {
+            // trailing blank lines
+            if pos == start || [seq, plus, qual].iter().all(|l| l.is_empty()) {
+                return Ok(entries);
+            }
+        }
+        if header[0] != b'@' || !plus.starts_with(b"+") || qual.is_empty() {
+            return Err(invalid(format!(
+                "truncated or invalid fastq record at byte {}",
+                start
+            )));
+        }
+        let mut entry = Entry::new(header, offsets[1]);
+        entry.length = nb_bases(seq);
+        entry.line_bases = entry.length;
+        entry.line_width = seq.len() as u64;
+        entry.qual_offset = Some(offsets[3]);
+        entries.push(entry);
+    }
+}
+
+/// The path of `path` with `suffix` added - e.g. reads.fq.bgz -> reads.fq.bgz.gzi
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = OsString::from(path.as_os_str());
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+/// Write a GZ index in the format of bgzip/samtools, which leaves out the first block.
+fn write_gzi(index: &bgzf::gzi::Index, path: &Path) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    let entries = index.iter().filter(|&&entry| entry != (0, 0));
+    writer.write_all(&(entries.clone().count() as u64).to_le_bytes())?;
+    for (compressed, uncompressed) in entries {
+        writer.write_all(&compressed.to_le_bytes())?;
+        writer.write_all(&uncompressed.to_le_bytes())?;
+    }
+    writer.flush()
+}
+
+/// Write the indices of a fasta/fastq file next to it - a .fai (fasta) or .fqi (fastq), and a
+/// .gzi if it is BGZF-compressed - and return their paths. The file must be uncompressed or BGZF.
+pub fn write_indices(path: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut file = File::open(path)?;
+    let mut prefix = Vec::with_capacity(18);
+    (&mut file).take(18).read_to_end(&mut prefix)?;
+    file.rewind()?;
+
+    let mut written = vec![];
+    let mut reader: Box<dyn BufRead> = if is_bgzf(&prefix) {
+        let gzi_path = with_suffix(path, ".gzi");
+        write_gzi(&build_gzi_index(path)?, &gzi_path)?;
+        written.push(gzi_path);
+        Box::new(bgzf::Reader::new(file))
+    } else {
+        match niffler::sniff(Box::new(prefix.as_slice())) {
+            Ok((_, niffler::compression::Format::No)) | Err(niffler::Error::FileTooShort) => {
+                Box::new(BufReader::new(file))
+            }
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "only uncompressed or BGZF fasta/fastq can be indexed",
+                ))
+            }
+        }
+    };
+
+    let is_fastq = match reader.fill_buf()?.first() {
+        Some(b) => *b == b'@',
+        None => matches!(
+            strip_compression_extension(path)
+                .extension()
+                .and_then(|ext| ext.to_str()),
+            Some("fastq" | "fq")
+        ),
+    };
+    let (entries, suffix) = match is_fastq {
+        true => (index_fastq(reader)?, ".fqi"),
+        false => (index_fasta(reader)?, ".fai"),
+    };
+    let index_path = with_suffix(path, suffix);
+    let mut writer = BufWriter::new(File::create(&index_path)?);
+    for entry in &entries {
+        entry.write_to(&mut writer)?;
+    }
+    writer.flush()?;
+    written.push(index_path);
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index_lines(entries: &[Entry]) -> String {
+        let mut buf = vec![];
+        for entry in entries {
+            entry.write_to(&mut buf).unwrap();
+        }
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn index_fasta_records() {
+        let fasta = ">r0 start_time=2022-12-12T12:00:00Z\nACGT\nAC\n>r1\r\nACG\r\nA\r\n>r2\n";
+        let entries = index_fasta(fasta.as_bytes()).unwrap();
+
+        assert_eq!(
+            index_lines(&entries),
+            "r0\t6\t36\t4\t5\nr1\t4\t49\t3\t5\nr2\t0\t61\t0\t0\n"
+        );
+        assert_eq!(&fasta[36..40], "ACGT");
+        assert_eq!(&fasta[49..52], "ACG");
+    }
+
+    #[test]
+    fn index_fasta_with_uneven_lines() {
+        for fasta in [">r0\nAC\nACG\n", ">r0\nACG\nA\nA\n", ">r0\nACG\n\nACG\n"] {
+            assert!(index_fasta(fasta.as_bytes()).is_err());
+        }
+    }
+
+    #[test]
+    fn index_fastq_records() {
+        let fastq = "@r0 start_time=2022-12-12T12:00:00Z\nACGT\n+\n1234\n@r1\nA\n+r1\n!\n\n";
+        let entries = index_fastq(fastq.as_bytes()).unwrap();
+
+        assert_eq!(
+            index_lines(&entries),
+            "r0\t4\t36\t4\t5\t43\nr1\t1\t52\t1\t2\t58\n"
+        );
+        assert_eq!(&fastq[43..47], "1234");
+        assert_eq!(&fastq[58..59], "!");
+
+        assert!(index_fastq("@r0\nACGT\n+\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn write_bgzf_indices() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("reads.fq.bgz");
+        {
+            // small blocks so the file has more than one
+            let mut writer = bgzf::Writer::new(File::create(&path).unwrap());
+            for i in 0..5000 {
+                write!(writer, "@r{}\nACGT\n+\n!!!!\n", i).unwrap();
+                if i % 1000 == 0 {
+                    writer.flush().unwrap();
+                }
+            }
+        }
+
+        let written = write_indices(&path).unwrap();
+
+        let gzi_path = dir.path().join("reads.fq.bgz.gzi");
+        let fqi_path = dir.path().join("reads.fq.bgz.fqi");
+        assert_eq!(written, vec![gzi_path.clone(), fqi_path.clone()]);
+        let gzi = bgzf::gzi::read(&gzi_path).unwrap();
+        assert!(gzi.len() > 2);
+        assert_eq!(gzi, build_gzi_index(&path).unwrap());
+        let fqi = std::fs::read_to_string(fqi_path).unwrap();
+        assert!(fqi.starts_with("r0\t4\t4\t4\t5\t11\nr1\t4\t20\t4\t5\t27\n"));
+        assert_eq!(fqi.lines().count(), 5000);
+    }
+
+    #[test]
+    fn gzip_cannot_be_indexed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("reads.fa.gz");
+        {
+            let mut writer =
+                niffler::to_path(&path, niffler::Format::Gzip, niffler::Level::One).unwrap();
+            writer.write_all(b">r0\nACGT\n").unwrap();
+        }
+
+        assert!(write_indices(&path).is_err());
+    }
+}
@@ -0,0 +1,325 @@
+use crate::io::{
+    bam_read_times_and_offsets, is_bam, open_alignment, Fastx, FileFormat, IOError,
+    SecondaryRecords, TimeExt,
+};
+use crate::unaligned::ReadGroups;
+use noodles_fasta as fasta;
+use ontime::ReadTimes;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use tempfile::NamedTempFile;
+
+/// The first bytes of an index file.
+const MAGIC: &[u8; 8] = b"ONTIMEIX";
+/// The version of the index file format.
+const VERSION: u32 = 3;
+/// The extension added to the input path to get the path of its index.
+const EXTENSION: &str = "ontime";
+
+/// What the offsets in an index point into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OffsetKind {
+    /// The file has no offsets - e.g. SAM or CRAM.
+    None,
+    /// Byte offsets into the uncompressed fasta/fastq.
+    Byte,
+    /// BGZF virtual positions into a BAM file.
+    Virtual,
+}
+
+impl OffsetKind {
+    fn to_byte(self) -> u8 {
+        match self {
+            OffsetKind::None => 0,
+            OffsetKind::Byte => 1,
+            OffsetKind::Virtual => 2,
+        }
+    }
+
+    fn from_byte(b: u8) -> Option<Self> {
+        match b {
+            0 => Some(OffsetKind::None),
+            1 => Some(OffsetKind::Byte),
+            2 => Some(OffsetKind::Virtual),
+            _ => None,
+        }
+    }
+}
+
+/// The start time and length of each read in a file, and the offset of each record, which is
+/// stored next to the file (`<file>.ontime`) so the file does not need to be scanned again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimeIndex {
+    pub read_times: ReadTimes,
+    /// The secondary and supplementary records of an alignment file.
+    pub secondary: SecondaryRecords,
+    /// The read groups of a fasta/fastq file.
+    pub read_groups: ReadGroups,
+    /// The offset of each record, or empty if the offset kind is [`OffsetKind::None`].
+    pub offsets: Vec<u64>,
+    pub offset_kind: OffsetKind,
+}
+
+/// The size and modification time of a file, which an index is validated against. It is taken
+/// before the index is built, so an index is never saved for a file that changed while it was read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileStamp {
+    size: u64,
+    /// Nanoseconds since the Unix epoch.
+    mtime: i128,
+}
+
+impl FileStamp {
+    pub fn of(path: &Path) -> io::Result<Self> {
+        let metadata = path.metadata()?;
+        let mtime = match metadata.modified()?.duration_since(UNIX_EPOCH) {
+            Ok(d) => d.as_nanos() as i128,
+            Err(e) => -(e.duration().as_nanos() as i128),
+        };
+        Ok(FileStamp {
+            size: metadata.len(),
+            mtime,
+        })
+    }
+}
+
+/// Whether `path` is an index file.
+pub fn is_index_path(path: &Path) -> bool {
+    path.extension().map_or(false, |ext| ext == EXTENSION)
+}
+
+impl TimeIndex {
+    /// The path of the index of `input`.
+    pub fn path_for(input: &Path) -> PathBuf {
+        let mut path = OsString::from(input.as_os_str());
+        path.push(".");
+        path.push(EXTENSION);
+        PathBuf::from(path)
+    }
+
+    /// Build the index of `input` by reading every record.
+    pub fn build(
+        input: &Path,
+        format: FileFormat,
+        repository: &fasta::Repository,
+        threads: NonZeroUsize,
+    ) -> Result<Self, IOError> {
+        let read_error = |source: io::Error| IOError::ParseAlignmentError {
+            source: anyhow::Error::from(source),
+        };
+        match format {
+            FileFormat::Fastx => {
+                let (read_times, read_groups, offsets) =
+                    Fastx::from_path(input).read_times_and_offsets(threads)?;
+                Ok(TimeIndex {
+                    read_times,
+                    secondary: SecondaryRecords::default(),
+                    read_groups,
+                    offsets,
+                    offset_kind: OffsetKind::Byte,
+                })
+            }
+            FileFormat::Alignment if is_bam(input).map_err(read_error)? => {
+                let (read_times, secondary, offsets) = bam_read_times_and_offsets(input)?;
+                Ok(TimeIndex {
+                    read_times,
+                    secondary,
+                    read_groups: ReadGroups::default(),
+                    offsets,
+                    offset_kind: OffsetKind::Virtual,
+                })
+            }
+            FileFormat::Alignment => {
+                let file = File::open(input).map_err(read_error)?;
+                let (mut reader, header) = open_alignment(file, repository, threads)?;
+                let (read_times, secondary) = reader.read_times(&header)?;
+                Ok(TimeIndex {
+                    read_times,
+                    secondary,
+                    read_groups: ReadGroups::default(),
+                    offsets: vec![],
+                    offset_kind: OffsetKind::None,
+                })
+            }
+        }
+    }
+
+    /// Write the index of `input` next to it, with the `stamp` of `input` taken before the index
+    /// was built. Nothing is written if `input` has changed since - e.g. reads were appended while
+    /// it was read. The index is written to a temporary file that is then renamed, so a partially
+    /// written index is never read.
+    pub fn write(&self, input: &Path, stamp: FileStamp) -> io::Result<()> {
+        if FileStamp::of(input)? != stamp {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "the input changed while it was being indexed",
+            ));
+        }
+        let path = Self::path_for(input);
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let mut tmp = NamedTempFile::new_in(dir)?;
+        {
+            let mut writer = BufWriter::new(tmp.as_file_mut());
+            writer.write_all(MAGIC)?;
+            writer.write_all(&VERSION.to_le_bytes())?;
+            writer.write_all(&stamp.size.to_le_bytes())?;
+            writer.write_all(&stamp.mtime.to_le_bytes())?;
+            writer.write_all(&[self.offset_kind.to_byte()])?;
+            self.read_times.write_to(&mut writer)?;
+            self.secondary.write_to(&mut writer)?;
+            self.read_groups.write_to(&mut writer)?;
+            writer.write_all(&(self.offsets.len() as u64).to_le_bytes())?;
+            for offset in &self.offsets {
+                writer.write_all(&offset.to_le_bytes())?;
+            }
+            writer.flush()?;
+        }
+        tmp.persist(path).map_err(|e| e.error)?;
+        Ok(())
+    }
+
+    /// Read the index of `input`. Returns `None` if there is no index, or if the input has
+    /// changed - i.e. its size or modification time differ - since the index was written.
+    pub fn load(input: &Path) -> io::Result<Option<Self>> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+        let file = match File::open(Self::path_for(input)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let mut reader = BufReader::new(file);
+
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        let mut buf = [0; 4];
+        reader.read_exact(&mut buf)?;
+        if &magic != MAGIC || u32::from_le_bytes(buf) != VERSION {
+            return Err(invalid("not an ontime index, or an unsupported version"));
+        }
+        let mut buf = [0; 8];
+        reader.read_exact(&mut buf)?;
+        let size = u64::from_le_bytes(buf);
+        let mut mtime = [0; 16];
+        reader.read_exact(&mut mtime)?;
+        let stamp = FileStamp {
+            size,
+            mtime: i128::from_le_bytes(mtime),
+        };
+        if stamp != FileStamp::of(input)? {
+            return Ok(None);
+        }
+
+        let mut kind = [0; 1];
+        reader.read_exact(&mut kind)?;
+        let offset_kind = OffsetKind::from_byte(kind[0]).ok_or_else(|| invalid("offset kind"))?;
+        let read_times = ReadTimes::read_from(&mut reader)?;
+        let secondary = SecondaryRecords::read_from(&mut reader)?;
+        let read_groups = ReadGroups::read_from(&mut reader)?;
+        reader.read_exact(&mut buf)?;
+        let nb_offsets = u64::from_le_bytes(buf) as usize;
+        let expected_nb_offsets = match offset_kind {
+            OffsetKind::None => 0,
+            _ => read_times.len() + secondary.len(),
+        };
+        if nb_offsets != expected_nb_offsets {
+            return Err(invalid(
+                "the number of offsets does not match the number of records",
+            ));
+        }
+        let mut offsets = Vec::with_capacity(nb_offsets.min(1 << 20));
+        for _ in 0..nb_offsets {
+            reader.read_exact(&mut buf)?;
+            offsets.push(u64::from_le_bytes(buf));
+        }
+
+        Ok(Some(TimeIndex {
+            read_times,
+            secondary,
+            read_groups,
+            offsets,
+            offset_kind,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::datetime;
+
+    #[test]
+    fn index_path() {
+        assert_eq!(
+            TimeIndex::path_for(Path::new("dir/reads.fq.gz")),
+            PathBuf::from("dir/reads.fq.gz.ontime")
+        )
+    }
+
+    #[test]
+    fn write_and_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("reads.fq");
+        std::fs::write(
+            &input,
+            "@r start_time=2022-12-12T12:00:00Z runid=abc\nA\n+\n1\n",
+        )
+        .unwrap();
+
+        assert_eq!(TimeIndex::load(&input).unwrap(), None);
+
+        let stamp = FileStamp::of(&input).unwrap();
+        let (read_times, read_groups) = Fastx::from_path(&input)
+            .read_times(NonZeroUsize::new(1).unwrap())
+            .unwrap();
+        assert_eq!(read_times.start_time(0), datetime!(2022-12-12 12:00 UTC));
+        assert_ne!(read_groups, ReadGroups::default());
+        let index = TimeIndex {
+            read_times,
+            secondary: SecondaryRecords::default(),
+            read_groups,
+            offsets: vec![0],
+            offset_kind: OffsetKind::Byte,
+        };
+        index.write(&input, stamp).unwrap();
+
+        assert_eq!(TimeIndex::load(&input).unwrap(), Some(index.clone()));
+
+        // the index is stale once the input changes, and is not saved with the stamp from before
+        std::fs::write(&input, "@r start_time=2022-12-12T12:00:00Z\nAC\n+\n12\n").unwrap();
+        assert_eq!(TimeIndex::load(&input).unwrap(), None);
+        assert!(index.write(&input, stamp).is_err());
+        assert_eq!(TimeIndex::load(&input).unwrap(), None);
+    }
+
+    #[test]
+    fn build_fastx_offsets() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("reads");
+        let fastq = "@r0 start_time=2022-12-12T12:00:00Z\nA\n+\n1\n@r1 start_time=2022-12-12T13:00:00Z\nACG\n+\n123\n@r2 st:Z:2022-12-12T11:00:00Z\nAC\n+\n12\n";
+        let fasta = ">r0 start_time=2022-12-12T12:00:00Z\nA\nC\n>r1 start_time=2022-12-12T13:00:00Z\nACG\n>r2 st:Z:2022-12-12T11:00:00Z\r\nAC\r\n";
+
+        for content in [fastq, fasta] {
+            std::fs::write(&input, content).unwrap();
+            let index = TimeIndex::build(
+                &input,
+                FileFormat::Fastx,
+                &fasta::Repository::default(),
+                NonZeroUsize::new(1).unwrap(),
+            )
+            .unwrap();
+
+            assert_eq!(index.offset_kind, OffsetKind::Byte);
+            assert_eq!(index.read_times.len(), 3);
+            for (offset, name) in index.offsets.iter().zip(["r0", "r1", "r2"]) {
+                assert_eq!(&content[*offset as usize + 1..*offset as usize + 3], name);
+            }
+        }
+    }
+}
@@ -1,16 +1,63 @@
-use crate::cli::CompressionExt;
+use crate::bam_index::IndexedBamWriter;
+use crate::cli::{CompressionExt, OutputCompression};
+use crate::parallel;
+use crate::sort::StartTimeSorter;
+use crate::unaligned::{FastxOutput, ReadGroups};
 use anyhow::anyhow;
+use bstr::BString;
+use needletail::errors::ParseError;
 use needletail::errors::ParseErrorKind::EmptyFile;
-use needletail::parse_fastx_file;
-use noodles_sam::alignment::record::data::field::Tag;
-use noodles_util::alignment::io::Writer;
-use ontime::FastxRecordExt;
+use needletail::{parse_fastx_reader, FastxReader};
+use noodles_bam as bam;
+use noodles_bgzf as bgzf;
+use noodles_fasta as fasta;
+use noodles_fasta::repository::adapters::IndexedReader;
+use noodles_sam as sam;
+use noodles_sam::alignment::io::Write as _;
+use noodles_sam::alignment::record::data::field::value::array::{Array, Values};
+use noodles_sam::alignment::record::data::field::{Tag, Value};
+use noodles_sam::alignment::{Record, RecordBuf};
+use noodles_sam::header::record::value::map::header::{group_order, sort_order, tag, Version};
+use noodles_sam::header::record::value::map::program;
+use noodles_sam::header::record::value::{map, Map};
+use noodles_util::alignment::io::{reader, writer, Format, Writer};
+use ontime::{
+    bin_index, duration_from_secs, parse_timestamp, FastxRecordExt, Membership, ReadTimes,
+    TimeFilter,
+};
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
-use std::io::{BufRead, BufWriter, Write};
+use std::io::{stdin, BufRead, BufReader, BufWriter, Cursor, Read, Seek, SeekFrom, Write};
+use std::iter::Peekable;
+use std::num::NonZeroUsize;
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tempfile::NamedTempFile;
 use thiserror::Error;
-use time::format_description::well_known::Rfc3339;
-use time::PrimitiveDateTime;
+use time::{Duration, OffsetDateTime};
+
+/// The number of bytes read from the start of a stream to detect its format.
+const SNIFF_LEN: usize = 64 * 1024;
+
+/// The `SS` value of alignment outputs sorted by start time.
+const START_TIME_SUBSORT_ORDER: &str = "unknown:start_time";
+
+/// The name of this program in the @PG lines of alignment outputs.
+const PROGRAM_NAME: &str = "ontime";
+
+/// Extensions of the compression formats niffler can read.
+const COMPRESSION_EXTENSIONS: &[&str] = &["gz", "bgz", "bz", "bz2", "lzma", "xz", "zst", "zstd"];
+
+/// The kind of records a file holds.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, clap::ValueEnum)]
+pub enum FileFormat {
+    /// SAM, BAM or CRAM
+    #[value(alias("sam"), alias("bam"), alias("cram"))]
+    Alignment,
+    /// fasta or fastq
+    #[value(alias("fastq"), alias("fasta"))]
+    Fastx,
+}
 
 /// A `Struct` used for seamlessly dealing with either compressed or uncompressed fasta/fastq files.
 #[derive(Debug, PartialEq, Eq)]
@@ -58,6 +105,10 @@ pub enum IOError {
     #[error("Could not read the header of the input file")]
     ReadHeaderError { source: anyhow::Error },
 
+    /// Indicates that alignment inputs cannot be merged as their reference sequences differ.
+    #[error("The inputs have different reference sequences (@SQ lines)")]
+    ReferenceSequencesDiffer,
+
     /// Indicates that the alignment file record could not be parsed.
     #[error("Failed to parse alignment record")]
     ParseAlignmentError { source: anyhow::Error },
@@ -77,7 +128,11 @@ impl Fastx {
             path: path.to_path_buf(),
         }
     }
-    /// Create the file associated with this `Fastx` object for writing.
+    /// Create the file associated with this `Fastx` object for writing. With more than one thread,
+    /// gzip output at the default compression level is written as BGZF - which any gzip reader can
+    /// read - by `threads` workers, and zstd output is compressed with `threads` workers. Gzip
+    /// output at any other level is written on a single thread. BGZF output is written when asked
+    /// for, or when the path ends in .bgz.
     ///
     /// # Errors
     /// If the file cannot be created then an `Err` containing a variant of [`FastxError`](#fastxerror) is
@@ -89,76 +144,142 @@ impl Fastx {
     /// let path = std::path::Path::new("output.fa");
     /// let fastx = Fastx{ path };
     /// { // this scoping means the file handle is closed afterwards.
-    ///     let file_handle = fastx.create(6, None)?;
+    ///     let file_handle = fastx.create(6, None, NonZeroUsize::new(1).unwrap())?;
     ///     write!(file_handle, ">read1\nACGT\n")?
     /// }
     /// ```
     pub fn create(
         &self,
         compression_lvl: niffler::compression::Level,
-        compression_fmt: Option<niffler::compression::Format>,
+        compression_fmt: Option<OutputCompression>,
+        threads: NonZeroUsize,
     ) -> Result<Box<dyn Write>, IOError> {
         let file = File::create(&self.path).map_err(|source| IOError::CreateError { source })?;
-        let file_handle = Box::new(BufWriter::new(file));
         let fmt = match compression_fmt {
-            None => niffler::Format::from_path(&self.path),
+            None => OutputCompression::from_path(&self.path),
             Some(f) => f,
         };
+        let fmt = match fmt {
+            OutputCompression::Bgzf => return Ok(bgzf_writer(file, compression_lvl, threads)),
+            OutputCompression::Niffler(fmt) => fmt,
+        };
+        match fmt {
+            niffler::Format::Gzip
+                if threads.get() > 1 && is_parallel_bgzf_level(compression_lvl) =>
+            {
+                return Ok(bgzf_writer(file, compression_lvl, threads));
+            }
+            niffler::Format::Zstd if threads.get() > 1 => {
+                let mut encoder =
+                    zstd::stream::write::Encoder::new(BufWriter::new(file), compression_lvl.into())
+                        .map_err(|source| IOError::CreateError { source })?;
+                encoder
+                    .multithread(threads.get() as u32)
+                    .map_err(|source| IOError::CreateError { source })?;
+                return Ok(Box::new(encoder.auto_finish()));
+            }
+            _ => {}
+        }
+        let file_handle = Box::new(BufWriter::new(file));
         niffler::get_writer(file_handle, fmt, compression_lvl).map_err(IOError::CompressOutputError)
     }
-    /// Returns a vector containing the start time of each read.
+    /// Whether the output [`create`](Fastx::create) writes with these settings can be indexed -
+    /// i.e. it is uncompressed or BGZF.
+    pub fn is_indexable(
+        &self,
+        compression_lvl: niffler::compression::Level,
+        compression_fmt: Option<OutputCompression>,
+        threads: NonZeroUsize,
+    ) -> bool {
+        match compression_fmt.unwrap_or_else(|| OutputCompression::from_path(&self.path)) {
+            OutputCompression::Bgzf | OutputCompression::Niffler(niffler::Format::No) => true,
+            OutputCompression::Niffler(niffler::Format::Gzip) => {
+                threads.get() > 1 && is_parallel_bgzf_level(compression_lvl)
+            }
+            OutputCompression::Niffler(_) => false,
+        }
+    }
+    /// Open the file for parsing. Compression is detected by niffler, which knows more formats -
+    /// e.g. zstd - than needletail.
+    fn reader(&self) -> Result<Box<dyn FastxReader>, ParseError> {
+        decompressed_fastx_reader(Box::new(File::open(&self.path)?))
+    }
+    /// Returns the start time and sequence length of each read, and the read groups of the reads.
     ///
     /// # Errors
     /// If the file cannot be opened or there is an issue parsing any records then an
     /// `Err` containing a variant of [`IOError`](#ioerror) is returned.
-    pub fn start_times(&self) -> Result<Vec<PrimitiveDateTime>, IOError> {
-        let mut start_times: Vec<PrimitiveDateTime> = vec![];
-        let mut reader = match parse_fastx_file(&self.path) {
-            Ok(rdr) => rdr,
-            Err(e) if e.kind == EmptyFile => return Ok(start_times),
-            Err(source) => return Err(IOError::ReadError { source }),
-        };
+    pub fn read_times(&self, threads: NonZeroUsize) -> Result<(ReadTimes, ReadGroups), IOError> {
+        self.read_times_and_offsets(threads)
+            .map(|(read_times, read_groups, _)| (read_times, read_groups))
+    }
 
-        while let Some(record) = reader.next() {
-            match record {
-                Ok(rec) => {
-                    let start_time = match rec.start_time() {
-                        Some(t) => t,
-                        None => return Err(IOError::MissingTime(rec.start_line_number())),
-                    };
-                    start_times.push(start_time)
+    /// Returns the start time and sequence length of each read and the read groups of the reads,
+    /// along with the offset of each read in the (uncompressed) file. With more than one thread,
+    /// the records are parsed in chunks by a pool of workers.
+    pub fn read_times_and_offsets(
+        &self,
+        threads: NonZeroUsize,
+    ) -> Result<(ReadTimes, ReadGroups, Vec<u64>), IOError> {
+        if threads.get() > 1 {
+            let file = File::open(&self.path).map_err(|source| IOError::ReadError {
+                source: source.into(),
+            })?;
+            return match niffler::send::get_reader(Box::new(file)) {
+                Ok((reader, _)) => {
+                    parallel::read_times_and_offsets(reader, threads, parallel::CHUNK_SIZE)
                 }
-                Err(err) => return Err(IOError::ParseError { source: err }),
-            }
+                Err(niffler::Error::FileTooShort) => Ok(Default::default()),
+                Err(niffler::Error::IOError(source)) => Err(IOError::ReadError {
+                    source: source.into(),
+                }),
+                Err(err) => Err(IOError::ReadError {
+                    source: std::io::Error::new(std::io::ErrorKind::Other, err).into(),
+                }),
+            };
         }
-        Ok(start_times)
+        let mut reader = match self.reader() {
+            Ok(rdr) => rdr,
+            Err(e) if e.kind == EmptyFile => return Ok(Default::default()),
+            Err(source) => return Err(IOError::ReadError { source }),
+        };
+        fastx_read_times_and_offsets(reader.as_mut(), 0)
     }
 
+    /// Write the reads that fall within a window to that window's output.
+    ///
+    /// `membership` holds, for each read, the index of the first window it belongs to, with the
+    /// reads of this file starting at `first_read`. As windows are cumulative, the read is also
+    /// written to the outputs of all windows after that one.
     pub fn extract_reads_in_timeframe_into<T: Write>(
         &self,
-        reads_to_keep: &[bool],
+        membership: &Membership,
+        first_read: usize,
         nb_reads_keep: usize,
-        write_to: &mut T,
+        write_to: &mut [T],
     ) -> Result<(), IOError> {
-        let mut reader =
-            parse_fastx_file(&self.path).map_err(|source| IOError::ReadError { source })?;
+        let mut reader = self
+            .reader()
+            .map_err(|source| IOError::ReadError { source })?;
         let mut read_idx: usize = 0;
         let mut nb_reads_written = 0;
 
         while let Some(record) = reader.next() {
             match record {
                 Err(source) => return Err(IOError::ParseError { source }),
-                Ok(rec) if reads_to_keep[read_idx] => {
-                    rec.write(write_to, None)
-                        .map_err(|err| IOError::WriteError {
-                            source: anyhow::Error::from(err),
-                        })?;
-                    nb_reads_written += 1;
-                    if nb_reads_keep == nb_reads_written {
-                        break;
+                Ok(rec) => {
+                    if let Some(window) = membership.get(first_read + read_idx) {
+                        for writer in write_to[window..].iter_mut() {
+                            rec.write(writer, None).map_err(|err| IOError::WriteError {
+                                source: anyhow::Error::from(err),
+                            })?;
+                        }
+                        nb_reads_written += 1;
+                        if nb_reads_keep == nb_reads_written {
+                            break;
+                        }
                     }
                 }
-                Ok(_) => (),
             }
 
             read_idx += 1;
@@ -170,80 +291,1778 @@ impl Fastx {
             Err(IOError::IndicesNotFound)
         }
     }
+
+    /// Add the reads that fall within a window to `sorter`, to be written in start time order.
+    ///
+    /// `membership` holds, for each read, the index of the first window it belongs to, with the
+    /// reads of this file starting at `first_read`.
+    pub fn sort_reads_in_timeframe_into(
+        &self,
+        membership: &Membership,
+        first_read: usize,
+        nb_reads_keep: usize,
+        sorter: &mut StartTimeSorter,
+    ) -> Result<(), IOError> {
+        let mut reader = self
+            .reader()
+            .map_err(|source| IOError::ReadError { source })?;
+        let mut read_idx: usize = 0;
+        let mut nb_reads_sorted = 0;
+
+        while nb_reads_sorted < nb_reads_keep {
+            let Some(record) = reader.next() else {
+                break;
+            };
+            let rec = record.map_err(|source| IOError::ParseError { source })?;
+            if let Some(window) = membership.get(first_read + read_idx) {
+                let start_time = rec
+                    .start_time()
+                    .ok_or(IOError::MissingTime(rec.start_line_number()))?;
+                let mut data = vec![];
+                rec.write(&mut data, None)
+                    .map_err(|err| IOError::WriteError {
+                        source: anyhow::Error::from(err),
+                    })?;
+                sorter
+                    .push(&start_time, window, data)
+                    .map_err(|err| IOError::WriteError {
+                        source: anyhow::Error::from(err),
+                    })?;
+                nb_reads_sorted += 1;
+            }
+            read_idx += 1;
+        }
+
+        if nb_reads_sorted == nb_reads_keep {
+            Ok(())
+        } else {
+            Err(IOError::IndicesNotFound)
+        }
+    }
+
+    /// Stream each read to the writer of the time bin its start time falls in. Bins are the
+    /// half-open intervals `[origin + i * bin_size, origin + (i + 1) * bin_size)` and the writer
+    /// for bin `i` is created with `create(i)` the first time a read in that bin is seen.
+    ///
+    /// Returns the number of reads written to each bin.
+    pub fn split_reads_into_bins<F>(
+        &self,
+        origin: &OffsetDateTime,
+        bin_size: &Duration,
+        mut create: F,
+    ) -> Result<Vec<usize>, IOError>
+    where
+        F: FnMut(usize) -> Result<FastxOutput, IOError>,
+    {
+        let mut reader = self
+            .reader()
+            .map_err(|source| IOError::ReadError { source })?;
+        let mut writers: Vec<Option<FastxOutput>> = vec![];
+        let mut nb_reads_per_bin: Vec<usize> = vec![];
+
+        while let Some(record) = reader.next() {
+            let rec = record.map_err(|source| IOError::ParseError { source })?;
+            let start_time = rec
+                .start_time()
+                .ok_or(IOError::MissingTime(rec.start_line_number()))?;
+            let Some(bin) = bin_index(&start_time, origin, bin_size) else {
+                continue;
+            };
+            if bin >= writers.len() {
+                writers.resize_with(bin + 1, || None);
+                nb_reads_per_bin.resize(bin + 1, 0);
+            }
+            let writer = match &mut writers[bin] {
+                Some(writer) => writer,
+                slot @ None => slot.insert(create(bin)?),
+            };
+            rec.write(writer, None).map_err(|err| IOError::WriteError {
+                source: anyhow::Error::from(err),
+            })?;
+            nb_reads_per_bin[bin] += 1;
+        }
+
+        for writer in writers.into_iter().flatten() {
+            writer.finish().map_err(|source| IOError::WriteError {
+                source: anyhow::Error::from(source),
+            })?;
+        }
+
+        Ok(nb_reads_per_bin)
+    }
+
+    /// Like [`Fastx::extract_reads_in_timeframe_into`], but only reads the kept reads by seeking to
+    /// their `offsets` in the uncompressed file, as given by [`Fastx::read_times_and_offsets`].
+    /// Consecutive kept reads are read in one go.
+    ///
+    /// Returns `false`, without writing anything, if the file cannot be read from an offset -
+    /// i.e. it is compressed with something other than BGZF.
+    pub fn extract_indexed_reads_into<T: Write>(
+        &self,
+        offsets: &[u64],
+        membership: &Membership,
+        first_read: usize,
+        nb_reads_keep: usize,
+        write_to: &mut [T],
+    ) -> Result<bool, IOError> {
+        let read_error = |source: std::io::Error| IOError::ReadError {
+            source: ParseError::from(source),
+        };
+        let Some(mut file) = seekable_reader(&self.path).map_err(read_error)? else {
+            return Ok(false);
+        };
+        let is_kept = |i: usize| membership.get(first_read + i).is_some();
+        let mut read_idx = 0;
+        let mut nb_reads_written = 0;
+
+        while read_idx < offsets.len() && nb_reads_written < nb_reads_keep {
+            if !is_kept(read_idx) {
+                read_idx += 1;
+                continue;
+            }
+            let mut run_end = read_idx + 1;
+            while run_end < offsets.len() && is_kept(run_end) {
+                run_end += 1;
+            }
+            file.seek(SeekFrom::Start(offsets[read_idx]))
+                .map_err(read_error)?;
+            let run: Box<dyn Read + Send + '_> = match offsets.get(run_end) {
+                Some(end) => Box::new((&mut file).take(end - offsets[read_idx])),
+                None => Box::new(&mut file),
+            };
+            let mut reader =
+                parse_fastx_reader(run).map_err(|source| IOError::ReadError { source })?;
+
+            for i in read_idx..run_end {
+                let rec = match reader.next() {
+                    Some(record) => record.map_err(|source| IOError::ParseError { source })?,
+                    None => return Err(IOError::IndicesNotFound),
+                };
+                // safe to unwrap as every read in the run is kept
+                let window = membership.get(first_read + i).unwrap();
+                for writer in write_to[window..].iter_mut() {
+                    rec.write(writer, None).map_err(|err| IOError::WriteError {
+                        source: anyhow::Error::from(err),
+                    })?;
+                }
+                nb_reads_written += 1;
+            }
+            read_idx = run_end;
+        }
+
+        if nb_reads_written == nb_reads_keep {
+            Ok(true)
+        } else {
+            Err(IOError::IndicesNotFound)
+        }
+    }
+
+    /// Returns the start time of the first read in the file, or `None` if the file is empty.
+    pub fn first_start_time(&self) -> Result<Option<OffsetDateTime>, IOError> {
+        let mut reader = match self.reader() {
+            Ok(rdr) => rdr,
+            Err(e) if e.kind == EmptyFile => return Ok(None),
+            Err(source) => return Err(IOError::ReadError { source }),
+        };
+        match reader.next() {
+            None => Ok(None),
+            Some(Err(source)) => Err(IOError::ParseError { source }),
+            Some(Ok(rec)) => match rec.start_time() {
+                Some(t) => Ok(Some(t)),
+                None => Err(IOError::MissingTime(rec.start_line_number())),
+            },
+        }
+    }
+}
+
+/// Write the fasta/fastq reads from `reader` kept by `filter` in a single pass, deciding for each
+/// record as it is read. If `sorted` is set, the reads are assumed to be in start time order and
+/// reading stops once no later read can be kept.
+///
+/// Returns the number of reads written.
+pub fn stream_fastx_reads_in_timeframe_into<R, T>(
+    reader: R,
+    filter: &TimeFilter,
+    sorted: bool,
+    write_to: &mut T,
+) -> Result<usize, IOError>
+where
+    R: Read + Send + 'static,
+    T: Write,
+{
+    let mut reader = match decompressed_fastx_reader(Box::new(reader)) {
+        Ok(rdr) => rdr,
+        Err(e) if e.kind == EmptyFile => return Ok(0),
+        Err(source) => return Err(IOError::ReadError { source }),
+    };
+    let mut nb_reads_written = 0;
+
+    while let Some(record) = reader.next() {
+        let rec = record.map_err(|source| IOError::ParseError { source })?;
+        let (start, end) = rec
+            .time_interval()
+            .ok_or(IOError::MissingTime(rec.start_line_number()))?;
+        if sorted && filter.is_exhausted(&start) {
+            break;
+        }
+        if filter.keeps(&start, &end) {
+            rec.write(write_to, None)
+                .map_err(|err| IOError::WriteError {
+                    source: anyhow::Error::from(err),
+                })?;
+            nb_reads_written += 1;
+        }
+    }
+
+    Ok(nb_reads_written)
+}
+
+/// The BGZF compression level for a compression level. BGZF uses DEFLATE, whose highest level is 9.
+fn bgzf_compression_level(
+    compression_lvl: niffler::compression::Level,
+) -> bgzf::writer::CompressionLevel {
+    let level = u32::from(compression_lvl).min(9) as u8;
+    bgzf::writer::CompressionLevel::try_from(level).unwrap_or_default()
+}
+
+/// Whether BGZF blocks can be compressed by several workers at this compression level - the
+/// multithreaded writer only compresses at the default level.
+fn is_parallel_bgzf_level(compression_lvl: niffler::compression::Level) -> bool {
+    bgzf_compression_level(compression_lvl) == bgzf::writer::CompressionLevel::default()
+}
+
+/// A BGZF writer, which finishes the stream when dropped. With more than one thread, blocks are
+/// compressed by `threads` workers if the compression level is the default one, and on a single
+/// thread otherwise.
+pub fn bgzf_writer<W: Write + Send + 'static>(
+    inner: W,
+    compression_lvl: niffler::compression::Level,
+    threads: NonZeroUsize,
+) -> Box<dyn Write> {
+    if threads.get() > 1 && is_parallel_bgzf_level(compression_lvl) {
+        return Box::new(bgzf::MultithreadedWriter::with_worker_count(threads, inner));
+    }
+    let level = bgzf_compression_level(compression_lvl);
+    Box::new(
+        bgzf::writer::Builder::default()
+            .set_compression_level(level)
+            .build_with_writer(inner),
+    )
+}
+
+/// Returns the start time and sequence length of each read, along with the offset of each read in
+/// the stream being parsed. `first_line` is the number of lines before the stream, which is added
+/// to the line number of a read that has no start time.
+pub fn fastx_read_times_and_offsets(
+    reader: &mut dyn FastxReader,
+    first_line: u64,
+) -> Result<(ReadTimes, ReadGroups, Vec<u64>), IOError> {
+    let mut read_times = ReadTimes::default();
+    let mut read_groups = ReadGroups::default();
+    let mut offsets = vec![];
+    while let Some(record) = reader.next() {
+        match record {
+            Ok(rec) => {
+                let start_time = match rec.start_time() {
+                    Some(t) => t,
+                    None => return Err(IOError::MissingTime(first_line + rec.start_line_number())),
+                };
+                read_times.push(start_time, rec.duration(), rec.num_bases() as u64);
+                read_groups.add(&rec);
+            }
+            Err(err) => return Err(IOError::ParseError { source: err }),
+        }
+        // the reader's position is the start of the record it last returned
+        offsets.push(reader.position().byte());
+    }
+    Ok((read_times, read_groups, offsets))
+}
+
+/// Decompress a stream and parse it as fasta/fastq.
+fn decompressed_fastx_reader(
+    reader: Box<dyn Read + Send>,
+) -> Result<Box<dyn FastxReader>, ParseError> {
+    let (reader, _) = niffler::send::get_reader(reader).map_err(|err| match err {
+        niffler::Error::FileTooShort => ParseError::new_empty_file(),
+        niffler::Error::IOError(source) => ParseError::from(source),
+        err => ParseError::from(std::io::Error::new(std::io::ErrorKind::Other, err)),
+    })?;
+    parse_fastx_reader(reader)
+}
+
+/// Whether the input can only be read once - i.e. it is stdin (`-`) or a named pipe.
+pub fn is_stream(path: &Path) -> bool {
+    path == Path::new("-") || std::fs::metadata(path).map_or(false, |m| !m.is_file())
+}
+
+/// Open a stream (stdin for `-`) and detect its format from the first bytes. The returned reader
+/// still yields the bytes used for detection.
+pub fn sniff_stream(path: &Path) -> std::io::Result<(Box<dyn Read + Send>, Option<FileFormat>)> {
+    let mut inner: Box<dyn Read + Send> = if path == Path::new("-") {
+        Box::new(stdin())
+    } else {
+        Box::new(File::open(path)?)
+    };
+    let mut prefix = Vec::with_capacity(SNIFF_LEN);
+    inner
+        .by_ref()
+        .take(SNIFF_LEN as u64)
+        .read_to_end(&mut prefix)?;
+    let format = sniff_format(&prefix);
+
+    Ok((Box::new(Cursor::new(prefix).chain(inner)), format))
+}
+
+/// Copy a stream into a temporary file so that it can be read more than once. The file is deleted
+/// when the returned handle is dropped.
+pub fn spill_to_tempfile<R: Read>(mut reader: R) -> std::io::Result<NamedTempFile> {
+    let mut file = NamedTempFile::new()?;
+    {
+        let mut writer = BufWriter::new(file.as_file_mut());
+        std::io::copy(&mut reader, &mut writer)?;
+        writer.flush()?;
+    }
+    Ok(file)
+}
+
+/// Build the reference sequence repository used to decode and encode CRAM records. An index
+/// (.fai) next to the reference is used if there is one, otherwise it is built in memory. Without
+/// a reference, only CRAM records that do not need one - e.g. unmapped reads - can be handled.
+pub fn reference_repository(reference: Option<&Path>) -> std::io::Result<fasta::Repository> {
+    let Some(reference) = reference else {
+        return Ok(fasta::Repository::default());
+    };
+    let mut fai = reference.as_os_str().to_owned();
+    fai.push(".fai");
+    let mut builder = fasta::indexed_reader::Builder::default();
+    if !Path::new(&fai).exists() {
+        builder = builder.set_index(fasta::index(reference)?);
+    }
+    let reader = builder.build_from_path(reference)?;
+    Ok(fasta::Repository::new(IndexedReader::new(reader)))
+}
+
+/// An alignment reader builder that decodes CRAM records with `repository`.
+fn alignment_reader_builder(repository: &fasta::Repository) -> reader::Builder {
+    reader::Builder::default().set_reference_sequence_repository(repository.clone())
+}
+
+/// An alignment writer builder that encodes CRAM records - and fills in missing reference MD5s in
+/// the header - with `repository`.
+pub fn alignment_writer_builder(repository: &fasta::Repository) -> writer::Builder {
+    writer::Builder::default().set_reference_sequence_repository(repository.clone())
+}
+
+/// A SAM/BAM/CRAM reader.
+pub type AlignmentReader = reader::Reader<Box<dyn BufRead>>;
+
+/// Open a SAM/BAM/CRAM reader. BGZF-compressed input - i.e. BAM or bgzipped SAM - is decompressed
+/// with `threads` worker threads.
+pub fn alignment_reader<R>(
+    reader: R,
+    repository: &fasta::Repository,
+    threads: NonZeroUsize,
+) -> std::io::Result<AlignmentReader>
+where
+    R: Read + Send + 'static,
+{
+    let mut reader = BufReader::new(reader);
+    if threads.get() > 1 && is_bgzf(reader.fill_buf()?) {
+        let decompressed = bgzf::MultithreadedReader::with_worker_count(threads, reader);
+        return alignment_reader_builder(repository)
+            .set_compression_method(None)
+            .build_from_reader(decompressed);
+    }
+    alignment_reader_builder(repository).build_from_reader(reader)
+}
+
+/// Open an alignment reader with [`alignment_reader`] and read its header.
+pub fn open_alignment<R>(
+    reader: R,
+    repository: &fasta::Repository,
+    threads: NonZeroUsize,
+) -> Result<(AlignmentReader, sam::Header), IOError>
+where
+    R: Read + Send + 'static,
+{
+    let read_header = |source: std::io::Error| IOError::ReadHeaderError {
+        source: anyhow::Error::from(source),
+    };
+    let mut reader = alignment_reader(reader, repository, threads).map_err(read_header)?;
+    let header = reader.read_header().map_err(read_header)?;
+    Ok((reader, header))
+}
+
+/// Read the header of an alignment stream. The returned reader still yields the bytes read for
+/// the header, so the stream can be opened again - or copied to a temporary file.
+pub fn peek_alignment_header(
+    stream: Box<dyn Read + Send>,
+    repository: &fasta::Repository,
+) -> Result<(Box<dyn Read + Send>, sam::Header), IOError> {
+    let recording = Arc::new(Mutex::new(Recording {
+        stream,
+        recorded: vec![],
+    }));
+    let reader = RecordingReader(Arc::clone(&recording));
+    // a single thread, so no worker holds on to the stream once the reader is dropped
+    let (reader, header) = open_alignment(reader, repository, NonZeroUsize::new(1).unwrap())?;
+    drop(reader);
+    let Ok(recording) = Arc::try_unwrap(recording) else {
+        unreachable!("the alignment reader is dropped");
+    };
+    let Recording { stream, recorded } = recording.into_inner().unwrap();
+    Ok((Box::new(Cursor::new(recorded).chain(stream)), header))
+}
+
+/// A stream, and a copy of the bytes read from it.
+struct Recording {
+    stream: Box<dyn Read + Send>,
+    recorded: Vec<u8>,
+}
+
+/// A reader of the stream of a [`Recording`], shared with [`peek_alignment_header`].
+struct RecordingReader(Arc<Mutex<Recording>>);
+
+impl Read for RecordingReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let mut recording = self.0.lock().unwrap();
+        let nb_read = recording.stream.read(buf)?;
+        recording.recorded.extend_from_slice(&buf[..nb_read]);
+        Ok(nb_read)
+    }
+}
+
+/// Create a SAM/BAM/CRAM writer for `path`. BAM output is compressed with `threads` worker threads.
+pub fn alignment_writer_to_path(
+    path: &Path,
+    repository: &fasta::Repository,
+    threads: NonZeroUsize,
+) -> std::io::Result<Writer> {
+    if threads.get() > 1 && path.extension().and_then(|ext| ext.to_str()) == Some("bam") {
+        let compressed = bgzf::MultithreadedWriter::with_worker_count(threads, File::create(path)?);
+        // the writer finishes the BGZF stream - i.e. writes the EOF block - when dropped
+        return alignment_writer_builder(repository)
+            .set_format(Format::Bam)
+            .set_compression_method(None)
+            .build_from_writer(compressed);
+    }
+    alignment_writer_builder(repository).build_from_path(path)
+}
+
+/// Add the read groups, programs, and comments of `other` that are not already in `header`. The
+/// reference sequences must be the same, as records refer to them by index.
+pub fn merge_header(header: &mut sam::Header, other: &sam::Header) -> Result<(), IOError> {
+    if header.reference_sequences() != other.reference_sequences() {
+        return Err(IOError::ReferenceSequencesDiffer);
+    }
+    for (id, read_group) in other.read_groups() {
+        if !header.read_groups().contains_key(id) {
+            header
+                .read_groups_mut()
+                .insert(id.clone(), read_group.clone());
+        }
+    }
+    for (id, program) in other.programs() {
+        if !header.programs().contains_key(id) {
+            header.programs_mut().insert(id.clone(), program.clone());
+        }
+    }
+    for comment in other.comments() {
+        if !header.comments().contains(comment) {
+            header.add_comment(comment.clone());
+        }
+    }
+    Ok(())
+}
+
+/// Whether the first bytes of a file are a BGZF block header - i.e. a gzip header with a BC extra
+/// subfield.
+pub fn is_bgzf(buf: &[u8]) -> bool {
+    const FEXTRA: u8 = 0x04;
+    matches!(
+        buf,
+        [0x1f, 0x8b, 0x08, flags, _, _, _, _, _, _, _, _, b'B', b'C', ..] if flags & FEXTRA != 0
+    )
+}
+
+/// Detect the format of a file from its content, falling back to its extension if the content is
+/// not recognised - e.g. the file is empty.
+pub fn detect_file_format(path: &Path) -> std::io::Result<Option<FileFormat>> {
+    let mut prefix = Vec::with_capacity(SNIFF_LEN);
+    File::open(path)?
+        .take(SNIFF_LEN as u64)
+        .read_to_end(&mut prefix)?;
+    Ok(sniff_format(&prefix).or_else(|| format_from_extension(path)))
+}
+
+/// Determine the format of a file from its extension, ignoring any compression extension - e.g.
+/// reads.fq.zst is fastq.
+pub fn format_from_extension(path: &Path) -> Option<FileFormat> {
+    match strip_compression_extension(path)
+        .extension()
+        .and_then(|ext| ext.to_str())
+    {
+        Some("sam" | "bam" | "cram") => Some(FileFormat::Alignment),
+        Some("fastq" | "fq" | "fasta" | "fa" | "fna") => Some(FileFormat::Fastx),
+        _ => None,
+    }
+}
+
+/// Whether a fasta/fastq path is fasta, going by its extension - e.g. reads.fa.gz
+pub fn is_fasta_path(path: &Path) -> bool {
+    matches!(
+        strip_compression_extension(path)
+            .extension()
+            .and_then(|ext| ext.to_str()),
+        Some("fasta" | "fa" | "fna")
+    )
+}
+
+/// Remove the compression extension, if there is one - e.g. reads.fq.gz -> reads.fq
+pub fn strip_compression_extension(path: &Path) -> PathBuf {
+    let mut path = path.to_path_buf();
+    if let Some(ext) = path.extension().and_then(|ext| ext.to_str()) {
+        if COMPRESSION_EXTENSIONS.contains(&ext) {
+            path.set_extension("");
+        }
+    }
+    path
+}
+
+/// Detect whether the (possibly compressed) first bytes of a file are from a fasta/fastq or an
+/// alignment file.
+pub fn sniff_format(prefix: &[u8]) -> Option<FileFormat> {
+    let (mut reader, _) = niffler::get_reader(Box::new(prefix)).ok()?;
+    let mut decompressed = Vec::with_capacity(SNIFF_LEN);
+    let mut buf = [0; 8192];
+    // the prefix is usually a truncated compressed stream, so we keep whatever could be
+    // decompressed before hitting the end of it
+    while decompressed.len() < SNIFF_LEN {
+        match reader.read(&mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => decompressed.extend_from_slice(&buf[..n]),
+        }
+    }
+    record_format(&decompressed)
+}
+
+/// Detect the format from the first (decompressed) bytes of a file.
+fn record_format(buf: &[u8]) -> Option<FileFormat> {
+    const BAM_MAGIC_NUMBER: &[u8] = b"BAM\x01";
+    const CRAM_MAGIC_NUMBER: &[u8] = b"CRAM";
+
+    if buf.starts_with(BAM_MAGIC_NUMBER) || buf.starts_with(CRAM_MAGIC_NUMBER) {
+        return Some(FileFormat::Alignment);
+    }
+    match buf.first()? {
+        b'>' => Some(FileFormat::Fastx),
+        // SAM header lines are @ followed by a two letter record type and a tab - e.g. @HD\t
+        b'@' => match buf.get(1..4) {
+            Some([a, b, b'\t']) if a.is_ascii_uppercase() && b.is_ascii_uppercase() => {
+                Some(FileFormat::Alignment)
+            }
+            _ => Some(FileFormat::Fastx),
+        },
+        // a SAM file without a header has (at least) 11 tab-separated fields per line
+        _ => {
+            let first_line = buf.split(|c| *c == b'\n').next()?;
+            (first_line.iter().filter(|c| **c == b'\t').count() >= 10)
+                .then_some(FileFormat::Alignment)
+        }
+    }
+}
+
+/// Write the BAM records added to `sorter` by
+/// [`TimeExt::sort_reads_in_timeframe_into`] to the outputs of their windows, in start time order.
+pub fn write_sorted_records(
+    sorter: StartTimeSorter,
+    header: &sam::Header,
+    writers: &mut [RecordWriter],
+) -> Result<(), IOError> {
+    // decoded into a record buffer, as it reads the 0xFF scores BAM uses for missing qualities as
+    // missing - a raw BAM record passes them on as scores, which writers reject
+    let mut record = RecordBuf::default();
+    sorter
+        .finish(|window, data| {
+            bam::io::Reader::from(data).read_record_buf(header, &mut record)?;
+            for writer in writers[window..].iter_mut() {
+                writer.write_record(header, &record)?;
+            }
+            Ok(())
+        })
+        .map_err(|source| IOError::WriteError {
+            source: anyhow::Error::from(source),
+        })
+}
+
+/// Mark a header as sorted by start time: `@HD SO:unknown SS:unknown:start_time`. There is no
+/// standard sort order for time, so the sub-sort tag describes it.
+pub fn set_start_time_sort_order(header: &mut sam::Header) {
+    let hd = header
+        .header_mut()
+        .get_or_insert_with(|| Map::<map::Header>::new(Version::new(1, 6)));
+    let fields = hd.other_fields_mut();
+    fields.insert(tag::SORT_ORDER, sort_order::UNKNOWN.into());
+    fields.insert(tag::SUBSORT_ORDER, START_TIME_SUBSORT_ORDER.into());
+}
+
+/// Whether a header says its records are sorted by coordinate (`@HD SO:coordinate`).
+pub fn is_coordinate_sorted(header: &sam::Header) -> bool {
+    header
+        .header()
+        .and_then(|hd| hd.other_fields().get(&tag::SORT_ORDER))
+        .map_or(false, |so| so == sort_order::COORDINATE)
+}
+
+/// Whether a header says the records of each read are next to each other - sorted by name
+/// (`@HD SO:queryname`) or grouped by query (`@HD GO:query`).
+pub fn is_grouped_by_name(header: &sam::Header) -> bool {
+    header.header().map_or(false, |hd| {
+        let fields = hd.other_fields();
+        fields
+            .get(&tag::SORT_ORDER)
+            .map_or(false, |so| so == sort_order::QUERY_NAME)
+            || fields
+                .get(&tag::GROUP_ORDER)
+                .map_or(false, |go| go == group_order::QUERY)
+    })
+}
+
+/// Add an @PG line for this run of ontime - chained to the last program of `header` - with the
+/// command line and the `timeframe` of the reads kept, and an @CO line with the number of reads
+/// kept, which replaces the one of an earlier run.
+pub fn add_provenance(header: &mut sam::Header, timeframe: &str, nb_reads_kept: usize) {
+    let programs = header.programs_mut();
+    // the last program in the chain is the last one no other program points to
+    let previous = programs
+        .keys()
+        .rfind(|id| {
+            !programs.values().any(|program| {
+                program
+                    .other_fields()
+                    .get(&program::tag::PREVIOUS_PROGRAM_ID)
+                    == Some(*id)
+            })
+        })
+        .cloned();
+    let mut id = BString::from(PROGRAM_NAME);
+    let mut i = 0;
+    while programs.contains_key(&id) {
+        i += 1;
+        id = BString::from(format!("{}.{}", PROGRAM_NAME, i));
+    }
+    let command_line = std::env::args_os()
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let mut program = Map::<map::Program>::default();
+    let fields = program.other_fields_mut();
+    fields.insert(program::tag::NAME, PROGRAM_NAME.into());
+    if let Some(previous) = previous {
+        fields.insert(program::tag::PREVIOUS_PROGRAM_ID, previous);
+    }
+    fields.insert(program::tag::VERSION, env!("CARGO_PKG_VERSION").into());
+    fields.insert(program::tag::COMMAND_LINE, command_line.into());
+    // every read is kept if the timeframe is unbounded
+    if !timeframe.is_empty() {
+        fields.insert(program::tag::DESCRIPTION, timeframe.into());
+    }
+    programs.insert(id, program);
+    let count_prefix = format!("{}: kept ", PROGRAM_NAME);
+    header
+        .comments_mut()
+        .retain(|comment| !comment.starts_with(count_prefix.as_bytes()));
+    header.add_comment(format!("{}{} reads", count_prefix, nb_reads_kept));
+}
+
+/// Returns the start time and sequence length of each read in a BAM file and its secondary and
+/// supplementary records, along with the BGZF virtual position of each record.
+pub fn bam_read_times_and_offsets(
+    path: &Path,
+) -> Result<(ReadTimes, SecondaryRecords, Vec<u64>), IOError> {
+    let read_error = |source: std::io::Error| IOError::ParseAlignmentError {
+        source: anyhow::Error::from(source),
+    };
+    let mut reader = File::open(path)
+        .map(bam::io::Reader::new)
+        .map_err(read_error)?;
+    reader
+        .read_header()
+        .map_err(|source| IOError::ReadHeaderError {
+            source: anyhow::Error::from(source),
+        })?;
+    let mut builder = ReadTimesBuilder::default();
+    let mut offsets = vec![];
+    let mut record = bam::Record::default();
+
+    loop {
+        let offset = u64::from(reader.virtual_position());
+        if reader.read_record(&mut record).map_err(read_error)? == 0 {
+            break;
+        }
+        builder.push(&record)?;
+        offsets.push(offset);
+    }
+    let (read_times, secondary) = builder.finish();
+    Ok((read_times, secondary, offsets))
+}
+
+/// Write the records of a BAM file whose read falls within a window to that window's output,
+/// reading only the kept records by seeking to their BGZF virtual positions, as given by
+/// [`bam_read_times_and_offsets`].
+pub fn extract_indexed_bam_records_into(
+    path: &Path,
+    offsets: &[u64],
+    membership: &Membership,
+    reads: RecordReads,
+    nb_reads_keep: usize,
+    writers: &mut [RecordWriter],
+) -> Result<(), IOError> {
+    let read_error = |source: std::io::Error| IOError::ParseAlignmentError {
+        source: anyhow::Error::from(source),
+    };
+    let mut reader = File::open(path)
+        .map(bam::io::Reader::new)
+        .map_err(read_error)?;
+    let header = reader
+        .read_header()
+        .map_err(|source| IOError::ReadHeaderError {
+            source: anyhow::Error::from(source),
+        })?;
+    // decoded into a record buffer so the 0xFF scores BAM uses for missing qualities are read as
+    // missing, as in [`write_sorted_records`]
+    let mut record = RecordBuf::default();
+    // the index of the record the reader is at, so consecutive records are read without seeking
+    let mut next_record = None;
+    let mut nb_reads_written = 0;
+
+    for ((i, offset), (read, primary)) in offsets.iter().enumerate().zip(reads) {
+        let Some(window) = read.and_then(|read| membership.get(read)) else {
+            continue;
+        };
+        if next_record != Some(i) {
+            reader
+                .seek(bgzf::VirtualPosition::from(*offset))
+                .map_err(read_error)?;
+        }
+        if reader
+            .read_record_buf(&header, &mut record)
+            .map_err(read_error)?
+            == 0
+        {
+            return Err(IOError::IndicesNotFound);
+        }
+        next_record = Some(i + 1);
+        for writer in writers[window..].iter_mut() {
+            writer
+                .write_record(&header, &record)
+                .map_err(|source| IOError::WriteError {
+                    source: anyhow::Error::from(source),
+                })?;
+        }
+        nb_reads_written += primary as usize;
+    }
+
+    if nb_reads_written == nb_reads_keep {
+        Ok(())
+    } else {
+        Err(IOError::IndicesNotFound)
+    }
+}
+
+/// A reader that can seek to an offset in the uncompressed data.
+trait SeekRead: Read + Seek + Send {}
+
+impl<R: Read + Seek + Send> SeekRead for R {}
+
+/// Open a file so it can be read from an offset in its uncompressed data. Returns `None` if the
+/// file is compressed with something other than BGZF.
+fn seekable_reader(path: &Path) -> std::io::Result<Option<Box<dyn SeekRead>>> {
+    let mut file = File::open(path)?;
+    let mut prefix = Vec::with_capacity(18);
+    (&mut file).take(18).read_to_end(&mut prefix)?;
+    file.rewind()?;
+    if is_bgzf(&prefix) {
+        let index = gzi_index(path)?;
+        return Ok(Some(Box::new(bgzf::IndexedReader::new(file, index))));
+    }
+    let compression = match niffler::sniff(Box::new(prefix.as_slice())) {
+        Ok((_, format)) => format,
+        Err(niffler::Error::FileTooShort) => niffler::compression::Format::No,
+        Err(e) => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                e.to_string(),
+            ))
+        }
+    };
+    match compression {
+        niffler::compression::Format::No => Ok(Some(Box::new(file))),
+        _ => Ok(None),
+    }
+}
+
+/// The GZ index of a BGZF file - read from `<path>.gzi` if there is one, otherwise built from the
+/// block headers.
+fn gzi_index(path: &Path) -> std::io::Result<bgzf::gzi::Index> {
+    let mut gzi_path = path.as_os_str().to_owned();
+    gzi_path.push(".gzi");
+    if Path::new(&gzi_path).exists() {
+        return bgzf::gzi::read(gzi_path);
+    }
+    build_gzi_index(path)
+}
+
+/// Build the GZ index of a BGZF file from its block headers.
+pub fn build_gzi_index(path: &Path) -> std::io::Result<bgzf::gzi::Index> {
+    // each block has an 18 byte header ending with its size (BSIZE + 1), and ends with the size of
+    // its uncompressed data (ISIZE)
+    let mut reader = BufReader::new(File::open(path)?);
+    let len = path.metadata()?.len();
+    let mut index = vec![(0, 0)];
+    let (mut pos, mut upos) = (0, 0);
+    let mut header = [0; 18];
+    let mut isize = [0; 4];
+    while pos < len {
+        reader.read_exact(&mut header)?;
+        if !is_bgzf(&header) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "invalid BGZF block header",
+            ));
+        }
+        let block_size = u64::from(u16::from_le_bytes([header[16], header[17]])) + 1;
+        reader.seek_relative(block_size as i64 - 18 - 4)?;
+        reader.read_exact(&mut isize)?;
+        pos += block_size;
+        upos += u64::from(u32::from_le_bytes(isize));
+        if pos < len {
+            index.push((pos, upos));
+        }
+    }
+    Ok(index)
 }
 
+/// Whether a file is BAM - i.e. BGZF-compressed with the BAM magic number.
+pub fn is_bam(path: &Path) -> std::io::Result<bool> {
+    let mut file = File::open(path)?;
+    let mut prefix = Vec::with_capacity(18);
+    (&mut file).take(18).read_to_end(&mut prefix)?;
+    if !is_bgzf(&prefix) {
+        return Ok(false);
+    }
+    file.rewind()?;
+    let mut magic = Vec::with_capacity(4);
+    bgzf::Reader::new(file).take(4).read_to_end(&mut magic)?;
+    Ok(magic == b"BAM\x01")
+}
+
+/// The tags of an alignment record that are carried into the header comment of a fasta/fastq
+/// record: start time, channel, read group, base modifications, and mean quality.
+const FASTX_COMMENT_TAGS: [[u8; 2]; 6] = [*b"st", *b"ch", *b"RG", *b"MM", *b"ML", *b"qs"];
+
+/// Writes alignment records as fastq - or fasta - records. The tags in [`FASTX_COMMENT_TAGS`] are
+/// added to the header in the `TAG:TYPE:VALUE` style of Dorado - e.g.
+/// `@read st:Z:2022-12-12T12:00:00Z ch:i:42` (tab-separated) - so the start time can still be read.
+/// Records on the reverse strand are reverse complemented back to the sequenced read, and
+/// secondary and supplementary records are skipped, as `samtools fastq` does.
+pub struct FastxRecordWriter {
+    inner: Box<dyn Write>,
+    fasta: bool,
+    buf: Vec<u8>,
+}
+
+impl FastxRecordWriter {
+    pub fn new(inner: Box<dyn Write>, fasta: bool) -> Self {
+        FastxRecordWriter {
+            inner,
+            fasta,
+            buf: vec![],
+        }
+    }
+
+    pub fn write_record(&mut self, record: &dyn Record) -> std::io::Result<()> {
+        if !is_primary(record)? {
+            return Ok(());
+        }
+        let buf = &mut self.buf;
+        buf.clear();
+        buf.push(if self.fasta { b'>' } else { b'@' });
+        match record.name() {
+            Some(name) => buf.extend_from_slice(name.as_bytes()),
+            None => buf.push(b'*'),
+        }
+        let data = record.data();
+        for tag in FASTX_COMMENT_TAGS {
+            if let Some(value) = data.get(&Tag::new(tag[0], tag[1])) {
+                buf.push(b'\t');
+                buf.extend_from_slice(&tag);
+                write_tag_value(buf, &value?)?;
+            }
+        }
+        buf.push(b'\n');
+
+        let reverse = record.flags()?.is_reverse_complemented();
+        let sequence = record.sequence();
+        let start = buf.len();
+        buf.extend(sequence.iter());
+        if reverse {
+            buf[start..].reverse();
+            for base in buf[start..].iter_mut() {
+                *base = complement(*base);
+            }
+        }
+        buf.push(b'\n');
+
+        if !self.fasta {
+            buf.extend_from_slice(b"+\n");
+            let quality_scores = record.quality_scores();
+            let start = buf.len();
+            if quality_scores.is_empty() {
+                // missing base qualities
+                buf.resize(start + sequence.len(), b'!');
+            } else {
+                buf.extend(quality_scores.iter().map(|q| q.saturating_add(33)));
+            }
+            if reverse {
+                buf[start..].reverse();
+            }
+            buf.push(b'\n');
+        }
+        self.inner.write_all(buf)
+    }
+}
+
+/// The complement of a (IUPAC) base, keeping its case.
+fn complement(base: u8) -> u8 {
+    match base {
+        b'A' => b'T',
+        b'C' => b'G',
+        b'G' => b'C',
+        b'T' => b'A',
+        b'a' => b't',
+        b'c' => b'g',
+        b'g' => b'c',
+        b't' => b'a',
+        b'R' => b'Y',
+        b'Y' => b'R',
+        b'K' => b'M',
+        b'M' => b'K',
+        b'B' => b'V',
+        b'V' => b'B',
+        b'D' => b'H',
+        b'H' => b'D',
+        b => b,
+    }
+}
+
+/// Write the type and value of a tag in SAM text format - e.g. `:Z:text`, `:i:42` or `:B:C,1,2`.
+fn write_tag_value(buf: &mut Vec<u8>, value: &Value) -> std::io::Result<()> {
+    fn write_values<N: std::fmt::Display>(
+        buf: &mut Vec<u8>,
+        subtype: char,
+        values: &dyn Values<'_, N>,
+    ) -> std::io::Result<()> {
+        write!(buf, ":B:{}", subtype)?;
+        for value in values.iter() {
+            write!(buf, ",{}", value?)?;
+        }
+        Ok(())
+    }
+
+    match value {
+        Value::Character(c) => write!(buf, ":A:{}", *c as char),
+        Value::Float(f) => write!(buf, ":f:{}", f),
+        Value::String(s) => {
+            buf.extend_from_slice(b":Z:");
+            buf.extend_from_slice(s);
+            Ok(())
+        }
+        Value::Hex(s) => {
+            buf.extend_from_slice(b":H:");
+            buf.extend_from_slice(s);
+            Ok(())
+        }
+        Value::Array(Array::Int8(values)) => write_values(buf, 'c', values.as_ref()),
+        Value::Array(Array::UInt8(values)) => write_values(buf, 'C', values.as_ref()),
+        Value::Array(Array::Int16(values)) => write_values(buf, 's', values.as_ref()),
+        Value::Array(Array::UInt16(values)) => write_values(buf, 'S', values.as_ref()),
+        Value::Array(Array::Int32(values)) => write_values(buf, 'i', values.as_ref()),
+        Value::Array(Array::UInt32(values)) => write_values(buf, 'I', values.as_ref()),
+        Value::Array(Array::Float(values)) => write_values(buf, 'f', values.as_ref()),
+        // the remaining values are integers
+        value => write!(buf, ":i:{}", value.as_int().unwrap_or_default()),
+    }
+}
+
+/// Where alignment records are written - an alignment file, or a fasta/fastq file.
+pub enum RecordWriter {
+    Alignment(Writer),
+    /// A BAM output that is indexed as it is written.
+    IndexedBam(Box<IndexedBamWriter>),
+    Fastx(FastxRecordWriter),
+}
+
+impl RecordWriter {
+    pub fn write_header(&mut self, header: &sam::Header) -> std::io::Result<()> {
+        match self {
+            RecordWriter::Alignment(writer) => writer.write_header(header),
+            RecordWriter::IndexedBam(writer) => writer.write_header(header),
+            RecordWriter::Fastx(_) => Ok(()),
+        }
+    }
+
+    pub fn write_record<R: Record>(
+        &mut self,
+        header: &sam::Header,
+        record: &R,
+    ) -> std::io::Result<()> {
+        match self {
+            RecordWriter::Alignment(writer) => writer.write_record(header, record),
+            RecordWriter::IndexedBam(writer) => writer.write_record(header, record),
+            RecordWriter::Fastx(writer) => writer.write_record(record),
+        }
+    }
+
+    pub fn finish(&mut self, header: &sam::Header) -> std::io::Result<()> {
+        match self {
+            RecordWriter::Alignment(writer) => writer.finish(header),
+            RecordWriter::IndexedBam(writer) => writer.finish(header),
+            RecordWriter::Fastx(writer) => writer.inner.flush(),
+        }
+    }
+}
+
+impl From<Writer> for RecordWriter {
+    fn from(writer: Writer) -> Self {
+        RecordWriter::Alignment(writer)
+    }
+}
+
+/// Time-based operations on an alignment reader whose header has already been read. `header` is
+/// the header of the input, which is used to decode its records. A read is made of its primary
+/// record and its secondary and supplementary records, which are kept or dropped together; `reads`
+/// gives the read of each record (see [`SecondaryRecords::record_reads`]).
 pub trait TimeExt {
-    fn start_times(&mut self) -> Result<Vec<PrimitiveDateTime>, IOError>;
+    fn read_times(
+        &mut self,
+        header: &sam::Header,
+    ) -> Result<(ReadTimes, SecondaryRecords), IOError>;
     fn extract_reads_in_timeframe_into(
         &mut self,
-        reads_to_keep: &[bool],
+        header: &sam::Header,
+        membership: &Membership,
+        reads: RecordReads,
         nb_reads_keep: usize,
-        writer: &mut Writer,
+        writers: &mut [RecordWriter],
     ) -> Result<(), IOError>;
+    fn sort_reads_in_timeframe_into(
+        &mut self,
+        header: &sam::Header,
+        read_times: &ReadTimes,
+        membership: &Membership,
+        reads: RecordReads,
+        nb_reads_keep: usize,
+        sorter: &mut StartTimeSorter,
+    ) -> Result<(), IOError>;
+    fn split_reads_into_bins<F>(
+        &mut self,
+        header: &sam::Header,
+        read_times: &ReadTimes,
+        reads: RecordReads,
+        origin: &OffsetDateTime,
+        bin_size: &Duration,
+        create: F,
+    ) -> Result<Vec<usize>, IOError>
+    where
+        F: FnMut(usize) -> Result<RecordWriter, IOError>;
+    fn first_start_time(&mut self, header: &sam::Header)
+        -> Result<Option<OffsetDateTime>, IOError>;
+    fn stream_reads_in_timeframe_into(
+        &mut self,
+        header: &sam::Header,
+        filter: &TimeFilter,
+        sorted: bool,
+        primary_only: bool,
+        writer: &mut RecordWriter,
+    ) -> Result<(usize, usize), IOError>;
+}
+
+/// Get the start time from the `st` tag of an alignment record. `i` is the index of the record and
+/// is used for error reporting.
+fn record_start_time(record: &dyn Record, i: usize) -> Result<OffsetDateTime, IOError> {
+    let tag = Tag::new(b's', b't');
+    let data = record.data();
+    let start_time = data
+        .get(&tag)
+        .ok_or(IOError::MissingTime(i as u64))?
+        .map_err(|_| IOError::MissingTime(i as u64))?;
+    let start_time = match start_time {
+        Value::String(s) => s.to_string(),
+        _ => return Err(IOError::MissingTime(i as u64)),
+    };
+    parse_timestamp(&start_time).map_err(|_| IOError::MissingTime(i as u64))
+}
+
+/// Get the duration of the read from the `du` tag of an alignment record, if it has one.
+fn record_duration(record: &dyn Record) -> Option<Duration> {
+    let tag = Tag::new(b'd', b'u');
+    let data = record.data();
+    let secs = match data.get(&tag)?.ok()? {
+        Value::Float(f) => f as f64,
+        value => value.as_int()? as f64,
+    };
+    duration_from_secs(secs)
+}
+
+/// Whether an alignment record is the primary record of its read - i.e. neither secondary nor
+/// supplementary.
+fn is_primary(record: &dyn Record) -> std::io::Result<bool> {
+    let flags = record.flags()?;
+    Ok(!flags.is_secondary() && !flags.is_supplementary())
+}
+
+/// The name of an alignment record if it does not belong to the run of records named `group` -
+/// `Some(None)` if it has no name, as records without a name are never grouped.
+fn new_group_name(
+    record: &dyn Record,
+    group: &Option<(Vec<u8>, Option<bool>)>,
+) -> Option<Option<Vec<u8>>> {
+    let name = record.name();
+    let name = name.as_ref().map(|name| name.as_bytes());
+    match (group, name) {
+        (Some((group, _)), Some(name)) if group.as_slice() == name => None,
+        (None, None) => None,
+        _ => Some(name.map(|name| name.to_vec())),
+    }
+}
+
+/// The secondary and supplementary records of an alignment file, each with the read it belongs
+/// to. Records are numbered in file order, and reads in the order of their primary records.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SecondaryRecords {
+    /// The index of each record and of its read - `None` if the file has no primary record with
+    /// its name, or the record is detached, in which case it is never written.
+    records: Vec<(u64, Option<u64>)>,
+}
+
+impl SecondaryRecords {
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    /// The number of records whose read has no primary record.
+    pub fn nb_orphans(&self) -> usize {
+        self.records
+            .iter()
+            .filter(|(_, read)| read.is_none())
+            .count()
+    }
+
+    /// Detach the records from their reads, so only primary records are written.
+    pub fn detach(&mut self) {
+        for (_, read) in self.records.iter_mut() {
+            *read = None;
+        }
+    }
+
+    /// The read of each record of the file, in file order, with the reads numbered from
+    /// `first_read`.
+    pub fn record_reads(&self, first_read: usize) -> RecordReads<'_> {
+        RecordReads {
+            secondary: self.records.iter().peekable(),
+            record: 0,
+            next_read: first_read,
+            first_read,
+        }
+    }
+
+    /// Write the records in a binary format, read back by [`SecondaryRecords::read_from`].
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        writer.write_all(&(self.len() as u64).to_le_bytes())?;
+        for (record, read) in &self.records {
+            writer.write_all(&record.to_le_bytes())?;
+            writer.write_all(&read.unwrap_or(u64::MAX).to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Read the records written by [`SecondaryRecords::write_to`].
+    pub fn read_from<R: Read>(reader: &mut R) -> std::io::Result<Self> {
+        let mut buf = [0; 8];
+        reader.read_exact(&mut buf)?;
+        let len = u64::from_le_bytes(buf) as usize;
+        let mut records = Vec::with_capacity(len.min(1 << 20));
+        for _ in 0..len {
+            reader.read_exact(&mut buf)?;
+            let record = u64::from_le_bytes(buf);
+            reader.read_exact(&mut buf)?;
+            let read = Some(u64::from_le_bytes(buf)).filter(|&read| read != u64::MAX);
+            records.push((record, read));
+        }
+        Ok(SecondaryRecords { records })
+    }
+}
+
+/// The read each record of an alignment file belongs to - see [`SecondaryRecords::record_reads`].
+/// Yields the index of the read, if the record is ever written, and whether the record is the
+/// primary record of the read.
+pub struct RecordReads<'a> {
+    secondary: Peekable<std::slice::Iter<'a, (u64, Option<u64>)>>,
+    record: u64,
+    next_read: usize,
+    first_read: usize,
+}
+
+impl Iterator for RecordReads<'_> {
+    type Item = (Option<usize>, bool);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let record = self.record;
+        self.record += 1;
+        match self.secondary.next_if(|(r, _)| *r == record) {
+            Some((_, read)) => Some((read.map(|read| self.first_read + read as usize), false)),
+            None => {
+                self.next_read += 1;
+                Some((Some(self.next_read - 1), true))
+            }
+        }
+    }
+}
+
+/// Gathers the start times of the reads of an alignment file from its records, taking the time
+/// of a read from its primary record and grouping its other records with it by name.
+#[derive(Default)]
+struct ReadTimesBuilder {
+    read_times: ReadTimes,
+    /// The name of each read, prefixed by its length. Only mapped reads can have other records, so
+    /// the names of unmapped reads - and of reads without a name - are left empty.
+    names: Vec<u8>,
+    /// The index and name of each secondary and supplementary record.
+    secondary: Vec<(u64, Option<Vec<u8>>)>,
+    nb_records: u64,
 }
 
-impl TimeExt for noodles_util::alignment::io::reader::Reader<Box<dyn BufRead>> {
-    fn start_times(&mut self) -> Result<Vec<PrimitiveDateTime>, IOError> {
-        let mut start_times: Vec<PrimitiveDateTime> = vec![];
-        let header = self
-            .read_header()
-            .map_err(|source| IOError::ReadHeaderError {
+impl ReadTimesBuilder {
+    fn push(&mut self, record: &dyn Record) -> Result<(), IOError> {
+        let flags = record
+            .flags()
+            .map_err(|source| IOError::ParseAlignmentError {
                 source: anyhow::Error::from(source),
             })?;
-        let records = self.records(&header);
-        let tag = Tag::new(b's', b't');
+        let name = record.name();
+        let name = name.as_ref().map(|name| name.as_bytes());
+        if !flags.is_secondary() && !flags.is_supplementary() {
+            let start_time = record_start_time(record, self.nb_records as usize)?;
+            self.read_times.push(
+                start_time,
+                record_duration(record),
+                record.sequence().len() as u64,
+            );
+            // names are at most 254 bytes long in SAM
+            match name.filter(|name| !flags.is_unmapped() && name.len() <= u8::MAX as usize) {
+                Some(name) => {
+                    self.names.push(name.len() as u8);
+                    self.names.extend_from_slice(name);
+                }
+                None => self.names.push(0),
+            }
+        } else {
+            self.secondary
+                .push((self.nb_records, name.map(|name| name.to_vec())));
+        }
+        self.nb_records += 1;
+        Ok(())
+    }
 
-        for (i, record) in records.enumerate() {
+    fn finish(self) -> (ReadTimes, SecondaryRecords) {
+        let ReadTimesBuilder {
+            read_times,
+            names,
+            secondary,
+            ..
+        } = self;
+        let wanted: HashSet<&[u8]> = secondary
+            .iter()
+            .filter_map(|(_, name)| name.as_deref())
+            .collect();
+        // the first read with the name of a record is its read
+        let mut reads = HashMap::with_capacity(wanted.len());
+        let mut rest = names.as_slice();
+        let mut read = 0;
+        while let Some((&len, tail)) = rest.split_first() {
+            let (name, tail) = tail.split_at(len as usize);
+            if len > 0 && wanted.contains(name) {
+                reads.entry(name).or_insert(read);
+            }
+            rest = tail;
+            read += 1;
+        }
+        let records = secondary
+            .iter()
+            .map(|(record, name)| {
+                let read = name.as_deref().and_then(|name| reads.get(name).copied());
+                (*record, read)
+            })
+            .collect();
+        (read_times, SecondaryRecords { records })
+    }
+}
+
+impl TimeExt for AlignmentReader {
+    fn read_times(
+        &mut self,
+        header: &sam::Header,
+    ) -> Result<(ReadTimes, SecondaryRecords), IOError> {
+        let mut builder = ReadTimesBuilder::default();
+        let records = self.records(header);
+
+        for record in records {
             let record = record.map_err(|source| IOError::ParseAlignmentError {
                 source: anyhow! { source.to_string() },
             })?;
-            let data = record.data();
-            let start_time = data
-                .get(&tag)
-                .ok_or(IOError::MissingTime(i as u64))?
-                .map_err(|_| IOError::MissingTime(i as u64))?;
-            let start_time = match start_time {
-                noodles_sam::alignment::record::data::field::Value::String(s) => s.to_string(),
-                _ => return Err(IOError::MissingTime(i as u64)),
-            };
-            let start_time = PrimitiveDateTime::parse(&start_time, &Rfc3339)
-                .map_err(|_| IOError::MissingTime(i as u64))?;
-            start_times.push(start_time);
+            builder.push(record.as_ref())?;
         }
-        Ok(start_times)
+        Ok(builder.finish())
     }
 
     fn extract_reads_in_timeframe_into(
         &mut self,
-        reads_to_keep: &[bool],
+        header: &sam::Header,
+        membership: &Membership,
+        reads: RecordReads,
         nb_reads_keep: usize,
-        writer: &mut Writer,
+        writers: &mut [RecordWriter],
     ) -> Result<(), IOError> {
-        let header = self
-            .read_header()
-            .map_err(|source| IOError::ReadHeaderError {
-                source: anyhow::Error::from(source),
-            })?;
-        let records = self.records(&header);
+        let records = self.records(header);
         let mut nb_reads_written = 0;
 
-        for (i, record) in records.enumerate() {
+        for (record, (read, primary)) in records.zip(reads) {
             let record = record.map_err(|source| IOError::ParseAlignmentError {
                 source: anyhow! { source.to_string() },
             })?;
-            if reads_to_keep[i] {
-                writer
-                    .write_record(&header, &record)
+            if let Some(window) = read.and_then(|read| membership.get(read)) {
+                for writer in writers[window..].iter_mut() {
+                    writer
+                        .write_record(header, &record)
+                        .map_err(|source| IOError::WriteError {
+                            source: anyhow::Error::from(source),
+                        })?;
+                }
+                nb_reads_written += primary as usize;
+            }
+        }
+        if nb_reads_written == nb_reads_keep {
+            Ok(())
+        } else {
+            Err(IOError::IndicesNotFound)
+        }
+    }
+
+    /// Add the records that fall within a window to `sorter` as uncompressed BAM records, to be
+    /// written in start time order by [`write_sorted_records`]. The records of a read are sorted
+    /// by the start time of the read, from `read_times`.
+    fn sort_reads_in_timeframe_into(
+        &mut self,
+        header: &sam::Header,
+        read_times: &ReadTimes,
+        membership: &Membership,
+        reads: RecordReads,
+        nb_reads_keep: usize,
+        sorter: &mut StartTimeSorter,
+    ) -> Result<(), IOError> {
+        let records = self.records(header);
+        let mut encoder = bam::io::Writer::from(vec![]);
+        let mut nb_reads_sorted = 0;
+
+        for (record, (read, primary)) in records.zip(reads) {
+            let record = record.map_err(|source| IOError::ParseAlignmentError {
+                source: anyhow! { source.to_string() },
+            })?;
+            let Some(read) = read else {
+                continue;
+            };
+            if let Some(window) = membership.get(read) {
+                let start_time = read_times.start_time(read);
+                encoder
+                    .write_alignment_record(header, record.as_ref())
+                    .and_then(|_| {
+                        let data = std::mem::take(encoder.get_mut());
+                        sorter.push(&start_time, window, data)
+                    })
                     .map_err(|source| IOError::WriteError {
                         source: anyhow::Error::from(source),
                     })?;
-                nb_reads_written += 1;
+                nb_reads_sorted += primary as usize;
             }
         }
-        if nb_reads_written == nb_reads_keep {
+        if nb_reads_sorted == nb_reads_keep {
             Ok(())
         } else {
             Err(IOError::IndicesNotFound)
         }
     }
+
+    /// Write each read to the output of the bin its start time - from `read_times` - falls in.
+    /// `create` returns the output of a bin with its header already written.
+    fn split_reads_into_bins<F>(
+        &mut self,
+        header: &sam::Header,
+        read_times: &ReadTimes,
+        reads: RecordReads,
+        origin: &OffsetDateTime,
+        bin_size: &Duration,
+        mut create: F,
+    ) -> Result<Vec<usize>, IOError>
+    where
+        F: FnMut(usize) -> Result<RecordWriter, IOError>,
+    {
+        let records = self.records(header);
+        let mut writers: Vec<Option<RecordWriter>> = vec![];
+        let mut nb_reads_per_bin: Vec<usize> = vec![];
+
+        for (record, (read, primary)) in records.zip(reads) {
+            let record = record.map_err(|source| IOError::ParseAlignmentError {
+                source: anyhow! { source.to_string() },
+            })?;
+            let Some(read) = read else {
+                continue;
+            };
+            let Some(bin) = bin_index(&read_times.start_time(read), origin, bin_size) else {
+                continue;
+            };
+            if bin >= writers.len() {
+                writers.resize_with(bin + 1, || None);
+                nb_reads_per_bin.resize(bin + 1, 0);
+            }
+            let writer = match &mut writers[bin] {
+                Some(writer) => writer,
+                slot @ None => slot.insert(create(bin)?),
+            };
+            writer
+                .write_record(header, &record)
+                .map_err(|source| IOError::WriteError {
+                    source: anyhow::Error::from(source),
+                })?;
+            nb_reads_per_bin[bin] += primary as usize;
+        }
+
+        for writer in writers.iter_mut().flatten() {
+            writer
+                .finish(header)
+                .map_err(|source| IOError::WriteError {
+                    source: anyhow::Error::from(source),
+                })?;
+        }
+
+        Ok(nb_reads_per_bin)
+    }
+
+    /// The start time of the first read, from the first primary record.
+    fn first_start_time(
+        &mut self,
+        header: &sam::Header,
+    ) -> Result<Option<OffsetDateTime>, IOError> {
+        let parse_error = |source: std::io::Error| IOError::ParseAlignmentError {
+            source: anyhow! { source.to_string() },
+        };
+        for (i, record) in self.records(header).enumerate() {
+            let record = record.map_err(parse_error)?;
+            if is_primary(record.as_ref()).map_err(parse_error)? {
+                return record_start_time(record.as_ref(), i).map(Some);
+            }
+        }
+        Ok(None)
+    }
+
+    /// Write the records kept by `filter` in a single pass, deciding for each read as its primary
+    /// record is read. If `sorted` is set, the records are assumed to be in start time order
+    /// and reading stops once no later record can be kept.
+    ///
+    /// The secondary and supplementary records of a read must be next to its primary record - as
+    /// aligners write them - and are kept or dropped along with it. Those before their primary
+    /// record are held back until it is read. The start time of a read only ever comes from its
+    /// primary record, so the other records without one next to them - or without a name - are
+    /// skipped. If `primary_only` is set, they are all dropped.
+    ///
+    /// Returns the number of reads written, and the number of records skipped as they have no
+    /// primary record next to them.
+    fn stream_reads_in_timeframe_into(
+        &mut self,
+        header: &sam::Header,
+        filter: &TimeFilter,
+        sorted: bool,
+        primary_only: bool,
+        writer: &mut RecordWriter,
+    ) -> Result<(usize, usize), IOError> {
+        let parse_error = |source: std::io::Error| IOError::ParseAlignmentError {
+            source: anyhow! { source.to_string() },
+        };
+        let write_error = |source: std::io::Error| IOError::WriteError {
+            source: anyhow::Error::from(source),
+        };
+        let records = self.records(header);
+        let (mut nb_reads_written, mut nb_orphans) = (0, 0);
+        // the name of the current run of records with the same name, and whether the read of its
+        // last primary record was kept - `None` until its primary record is read
+        let mut group: Option<(Vec<u8>, Option<bool>)> = None;
+        // the records of the group read before its primary record
+        let mut pending = vec![];
+
+        for (i, record) in records.enumerate() {
+            let record = record.map_err(parse_error)?;
+            let primary = is_primary(record.as_ref()).map_err(parse_error)?;
+            if primary_only && !primary {
+                continue;
+            }
+            if let Some(name) = new_group_name(record.as_ref(), &group) {
+                nb_orphans += pending.len();
+                pending.clear();
+                group = name.map(|name| (name, None));
+            }
+
+            if primary {
+                let start = record_start_time(record.as_ref(), i)?;
+                let end = record_duration(record.as_ref()).map_or(start, |d| start + d);
+                if sorted && filter.is_exhausted(&start) {
+                    pending.clear();
+                    break;
+                }
+                let keep = filter.keeps(&start, &end);
+                if keep {
+                    for record in pending.drain(..) {
+                        writer.write_record(header, &record).map_err(write_error)?;
+                    }
+                    writer.write_record(header, &record).map_err(write_error)?;
+                    nb_reads_written += 1;
+                }
+                pending.clear();
+                if let Some((_, read_kept)) = group.as_mut() {
+                    *read_kept = Some(keep);
+                }
+            } else {
+                match group {
+                    Some((_, Some(true))) => {
+                        writer.write_record(header, &record).map_err(write_error)?
+                    }
+                    Some((_, Some(false))) => {}
+                    Some((_, None)) => pending.push(record),
+                    None => nb_orphans += 1,
+                }
+            }
+        }
+        nb_orphans += pending.len();
+
+        Ok((nb_reads_written, nb_orphans))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sniff_fastq() {
+        assert_eq!(
+            sniff_format(b"@read1 start_time=2022-12-12T12:00:00Z\nACGT\n+\n!!!!\n"),
+            Some(FileFormat::Fastx)
+        );
+    }
+
+    #[test]
+    fn sniff_fasta() {
+        assert_eq!(sniff_format(b">read1\nACGT\n"), Some(FileFormat::Fastx));
+    }
+
+    #[test]
+    fn sniff_sam() {
+        assert_eq!(
+            sniff_format(b"@HD\tVN:1.6\tSO:unknown\n"),
+            Some(FileFormat::Alignment)
+        );
+        let headerless = b"r1\t4\t*\t0\t0\t*\t*\t0\t0\tACGT\t!!!!\tst:Z:2022-12-12T12:00:00Z\n";
+        assert_eq!(sniff_format(headerless), Some(FileFormat::Alignment));
+    }
+
+    fn gzip(content: &[u8]) -> Vec<u8> {
+        let mut compressed = vec![];
+        let mut writer = niffler::get_writer(
+            Box::new(&mut compressed),
+            niffler::Format::Gzip,
+            niffler::Level::One,
+        )
+        .unwrap();
+        writer.write_all(content).unwrap();
+        drop(writer);
+        compressed
+    }
+
+    #[test]
+    fn sniff_bam() {
+        let input = gzip(b"BAM\x01\x0b\x00\x00\x00@HD\tVN:1.6\n");
+        assert_eq!(sniff_format(&input), Some(FileFormat::Alignment));
+    }
+
+    #[test]
+    fn sniff_gzipped_fastq() {
+        let input = gzip(b"@read1\nACGT\n+\n!!!!\n");
+        assert_eq!(sniff_format(&input), Some(FileFormat::Fastx));
+    }
+
+    #[test]
+    fn sniff_truncated_gzipped_sam() {
+        let input = gzip(&b"@HD\tVN:1.6\n".repeat(10_000));
+        assert_eq!(
+            sniff_format(&input[..input.len() / 2]),
+            Some(FileFormat::Alignment)
+        );
+    }
+
+    #[test]
+    fn format_from_compressed_extension() {
+        let t = |p: &str| format_from_extension(Path::new(p));
+        assert_eq!(t("reads.fq.zst"), Some(FileFormat::Fastx));
+        assert_eq!(t("reads.fastq.bz2"), Some(FileFormat::Fastx));
+        assert_eq!(t("reads.fa.xz"), Some(FileFormat::Fastx));
+        assert_eq!(t("reads.fasta"), Some(FileFormat::Fastx));
+        assert_eq!(t("reads.bam"), Some(FileFormat::Alignment));
+        assert_eq!(t("reads.cram"), Some(FileFormat::Alignment));
+        assert_eq!(t("reads.sam.gz"), Some(FileFormat::Alignment));
+        assert_eq!(t("reads.txt.gz"), None);
+        assert_eq!(t("FAQ12345_pass_0"), None);
+    }
+
+    #[test]
+    fn bgzf_header() {
+        let mut writer = bgzf::Writer::new(vec![]);
+        writer.write_all(b"@HD\tVN:1.6\n").unwrap();
+        let bgzf = writer.finish().unwrap();
+        assert!(is_bgzf(&bgzf));
+        assert!(!is_bgzf(&gzip(b"@HD\tVN:1.6\n")));
+        assert!(!is_bgzf(b"@HD\tVN:1.6\n"));
+    }
+
+    #[test]
+    fn seek_in_bgzf_without_gzi() {
+        let data = (0..50_000u32)
+            .flat_map(|i| i.to_le_bytes())
+            .collect::<Vec<_>>();
+        let mut writer = bgzf::Writer::new(vec![]);
+        writer.write_all(&data).unwrap();
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&writer.finish().unwrap()).unwrap();
+
+        let index = gzi_index(file.path()).unwrap();
+        assert!(index.len() > 1);
+
+        let mut reader = seekable_reader(file.path()).unwrap().unwrap();
+        for pos in [0, 65_280, 65_284, 199_996] {
+            reader.seek(SeekFrom::Start(pos)).unwrap();
+            let mut buf = [0; 4];
+            reader.read_exact(&mut buf).unwrap();
+            assert_eq!(u32::from_le_bytes(buf), pos as u32 / 4);
+        }
+    }
+
+    #[test]
+    fn gzip_is_not_seekable() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&gzip(b"@r\nA\n+\n1\n")).unwrap();
+
+        assert!(seekable_reader(file.path()).unwrap().is_none());
+    }
+
+    #[test]
+    fn secondary_records_grouped_by_name() {
+        let text = "@HD\tVN:1.6\n\
+            r1\t2048\t*\t0\t0\t*\t*\t0\t0\tAC\t*\n\
+            r0\t0\t*\t0\t0\t*\t*\t0\t0\tACGT\t*\tst:Z:2022-12-12T12:00:00Z\n\
+            r1\t0\t*\t0\t0\t*\t*\t0\t0\tACG\t*\tst:Z:2022-12-12T13:00:00Z\n\
+            r0\t256\t*\t0\t0\t*\t*\t0\t0\t*\t*\n\
+            r2\t256\t*\t0\t0\t*\t*\t0\t0\t*\t*\n\
+            *\t0\t*\t0\t0\t*\t*\t0\t0\tA\t*\tst:Z:2022-12-12T14:00:00Z\n\
+            *\t2048\t*\t0\t0\t*\t*\t0\t0\tA\t*\n\
+            r10\t0\t*\t0\t0\t*\t*\t0\t0\tA\t*\tst:Z:2022-12-12T15:00:00Z\n\
+            r1\t256\t*\t0\t0\t*\t*\t0\t0\t*\t*\n";
+        let mut reader = sam::io::Reader::new(text.as_bytes());
+        reader.read_header().unwrap();
+        let mut builder = ReadTimesBuilder::default();
+        for record in reader.records() {
+            builder.push(&record.unwrap()).unwrap();
+        }
+        let (read_times, mut secondary) = builder.finish();
+
+        assert_eq!(read_times.len(), 4);
+        assert_eq!(read_times.nb_bases_since(&read_times.start_time(0)), 9);
+        assert_eq!(secondary.len(), 5);
+        // records without a name are never grouped, and names are compared in full
+        assert_eq!(secondary.nb_orphans(), 2);
+        assert_eq!(
+            secondary.record_reads(10).take(10).collect::<Vec<_>>(),
+            vec![
+                (Some(11), false),
+                (Some(10), true),
+                (Some(11), true),
+                (Some(10), false),
+                (None, false),
+                (Some(12), true),
+                (None, false),
+                (Some(13), true),
+                (Some(11), false),
+                (Some(14), true)
+            ]
+        );
+
+        let mut buf = vec![];
+        secondary.write_to(&mut buf).unwrap();
+        assert_eq!(
+            SecondaryRecords::read_from(&mut buf.as_slice()).unwrap(),
+            secondary
+        );
+
+        secondary.detach();
+        assert!(secondary
+            .record_reads(0)
+            .take(5)
+            .all(|(read, primary)| primary || read.is_none()));
+    }
+
+    #[test]
+    fn alignment_records_as_fastx() {
+        let text = "@HD\tVN:1.6\n\
+            r0\t4\t*\t0\t0\t*\t*\t0\t0\tACGTN\t!\"#$%\tst:Z:2022-12-12T12:00:00Z\tdu:f:1.5\tch:i:42\tMM:Z:C+m?,0;\tML:B:C,200\n\
+            r0\t256\t*\t0\t0\t*\t*\t0\t0\tACGTN\t!\"#$%\tst:Z:2022-12-12T12:00:00Z\n\
+            r1\t16\t*\t0\t0\t*\t*\t0\t0\tAACG\t*\tqs:f:9.5\n";
+        let mut reader = sam::io::Reader::new(text.as_bytes());
+        let header = reader.read_header().unwrap();
+        let records = reader
+            .records()
+            .collect::<std::io::Result<Vec<_>>>()
+            .unwrap();
+
+        for (fasta, expected) in [
+            (false, "@r0\tst:Z:2022-12-12T12:00:00Z\tch:i:42\tMM:Z:C+m?,0;\tML:B:C,200\nACGTN\n+\n!\"#$%\n@r1\tqs:f:9.5\nCGTT\n+\n!!!!\n"),
+            (true, ">r0\tst:Z:2022-12-12T12:00:00Z\tch:i:42\tMM:Z:C+m?,0;\tML:B:C,200\nACGTN\n>r1\tqs:f:9.5\nCGTT\n"),
+        ] {
+            let output = tempfile::NamedTempFile::new().unwrap();
+            {
+                let mut writer = RecordWriter::Fastx(FastxRecordWriter::new(
+                    Box::new(output.reopen().unwrap()),
+                    fasta,
+                ));
+                for record in &records {
+                    writer.write_record(&header, record).unwrap();
+                }
+                writer.finish(&header).unwrap();
+            }
+            assert_eq!(std::fs::read_to_string(output.path()).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn sniff_unknown() {
+        assert_eq!(sniff_format(b""), None);
+        assert_eq!(sniff_format(b"hello world\n"), None);
+    }
 }
@@ -3,23 +3,400 @@ use duration_str::DError;
 use lazy_static::lazy_static;
 use needletail::parser::SequenceRecord;
 use regex::bytes::Regex;
+use std::io::{self, Read, Write};
+use std::ops::Range;
 use time::format_description::well_known::Rfc3339;
-use time::{Duration, PrimitiveDateTime};
+use time::format_description::FormatItem;
+use time::macros::format_description;
+use time::{Duration, OffsetDateTime, UtcOffset};
 
 lazy_static! {
     pub static ref DATETIME_RE: Regex = Regex::new(r"(start_time=|st:Z:)(?P<time>\S+)\s*").unwrap();
+    pub static ref DURATION_RE: Regex =
+        Regex::new(r"(duration=|du:f:)(?P<duration>\S+)\s*").unwrap();
+}
+
+const UTC_FMT: &[FormatItem<'_>] =
+    format_description!("[year]-[month]-[day]T[hour]:[minute]:[second].[subsecond]Z");
+const OFFSET_FMT: &[FormatItem<'_>] = format_description!(
+    "[year]-[month]-[day]T[hour]:[minute]:[second].[subsecond][offset_hour sign:mandatory]:[offset_minute]"
+);
+const UTC_OFFSET_FMT: &[FormatItem<'_>] =
+    format_description!("[offset_hour sign:mandatory]:[offset_minute]");
+
+/// Parse an RFC3339 timestamp, keeping its UTC offset, and normalise it to UTC.
+pub fn parse_timestamp(s: &str) -> Result<OffsetDateTime, time::error::Parse> {
+    OffsetDateTime::parse(s, &Rfc3339).map(|t| t.to_offset(UtcOffset::UTC))
+}
+
+/// Parse a UTC offset such as `+10:00`, `-05:30`, `Z`, or `UTC`.
+pub fn parse_utc_offset(s: &str) -> Result<UtcOffset, time::error::Parse> {
+    match s {
+        "Z" | "z" | "UTC" | "utc" => Ok(UtcOffset::UTC),
+        _ => UtcOffset::parse(s, UTC_OFFSET_FMT),
+    }
+}
+
+/// Format a timestamp for display in the given UTC offset. UTC times are written with a `Z`
+/// suffix, all other offsets are written explicitly - e.g. `+10:00`.
+pub fn format_timestamp(
+    t: &OffsetDateTime,
+    offset: UtcOffset,
+) -> Result<String, time::error::Format> {
+    let t = t.to_offset(offset);
+    if offset.is_utc() {
+        t.format(UTC_FMT)
+    } else {
+        t.format(OFFSET_FMT)
+    }
+}
+
+/// How a read's time interval is compared to a timeframe to decide whether the read is in it.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Overlap {
+    /// The read starts in the timeframe
+    #[default]
+    Start,
+    /// The read ends in the timeframe
+    End,
+    /// The read starts and ends in the timeframe
+    Contained,
+    /// Any part of the read overlaps the timeframe
+    Overlaps,
+}
+
+impl Overlap {
+    /// Whether this overlap mode needs to know when reads end.
+    pub fn needs_end_time(&self) -> bool {
+        *self != Overlap::Start
+    }
+}
+
+/// Convert a read duration in seconds - as found in `du:f` tags and `duration=` fields - into a
+/// `Duration`, rounded to the millisecond.
+pub fn duration_from_secs(secs: f64) -> Option<Duration> {
+    if secs.is_finite() && secs >= 0.0 {
+        Some(Duration::milliseconds((secs * 1000.0).round() as i64))
+    } else {
+        None
+    }
+}
+
+/// The start time, end time, and sequence length of each read in a file, in file order.
+///
+/// To keep memory low for runs with tens of millions of reads, the start times are stored as
+/// nanoseconds from the start time of the first read, the durations as milliseconds, and the
+/// lengths as 32-bit integers. If a read has no duration, its end time is its start time.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ReadTimes {
+    /// The start time of the first read, which the start times are stored relative to
+    origin: Option<OffsetDateTime>,
+    start_offsets: Vec<i64>,
+    durations: Vec<u32>,
+    lengths: Vec<u32>,
+    /// The number of reads without a duration
+    pub nb_missing_durations: usize,
+}
+
+impl ReadTimes {
+    pub fn push(&mut self, start_time: OffsetDateTime, duration: Option<Duration>, length: u64) {
+        let origin = *self.origin.get_or_insert(start_time);
+        self.start_offsets.push(nanos_between(&origin, &start_time));
+        match duration {
+            Some(d) => self
+                .durations
+                .push(d.whole_milliseconds().clamp(0, u32::MAX as i128) as u32),
+            None => {
+                self.durations.push(0);
+                self.nb_missing_durations += 1;
+            }
+        }
+        self.lengths.push(length.min(u32::MAX as u64) as u32);
+    }
+
+    /// Append the reads of `other` - e.g. from another input file - after these reads.
+    pub fn extend(&mut self, other: ReadTimes) {
+        let Some(other_origin) = other.origin else {
+            return;
+        };
+        let origin = *self.origin.get_or_insert(other_origin);
+        let shift = nanos_between(&origin, &other_origin);
+        self.start_offsets.extend(
+            other
+                .start_offsets
+                .iter()
+                .map(|offset| offset.saturating_add(shift)),
+        );
+        self.durations.extend(other.durations);
+        self.lengths.extend(other.lengths);
+        self.nb_missing_durations += other.nb_missing_durations;
+    }
+
+    pub fn len(&self) -> usize {
+        self.start_offsets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start_offsets.is_empty()
+    }
+
+    /// The time `offset` nanoseconds after the origin.
+    fn time_at(&self, offset: i64) -> OffsetDateTime {
+        // there is an origin as soon as there is an offset
+        self.origin.unwrap() + Duration::nanoseconds(offset)
+    }
+
+    pub fn start_time(&self, i: usize) -> OffsetDateTime {
+        self.time_at(self.start_offsets[i])
+    }
+
+    pub fn end_time(&self, i: usize) -> OffsetDateTime {
+        self.start_time(i) + Duration::milliseconds(self.durations[i] as i64)
+    }
+
+    /// The start and end time of each read.
+    pub fn intervals(&self) -> impl Iterator<Item = (OffsetDateTime, OffsetDateTime)> + '_ {
+        (0..self.len()).map(|i| (self.start_time(i), self.end_time(i)))
+    }
+
+    /// The earliest and latest start times, or `None` if there are no reads.
+    pub fn start_time_bounds(&self) -> Option<(OffsetDateTime, OffsetDateTime)> {
+        let first = self.start_offsets.iter().min()?;
+        let last = self.start_offsets.iter().max()?;
+        Some((self.time_at(*first), self.time_at(*last)))
+    }
+
+    /// The latest end time, or `None` if there are no reads.
+    pub fn last_end_time(&self) -> Option<OffsetDateTime> {
+        (0..self.len()).map(|i| self.end_time(i)).max()
+    }
+
+    /// The number of bases in the reads that start at or after `earliest`.
+    pub fn nb_bases_since(&self, earliest: &OffsetDateTime) -> u64 {
+        let Some(origin) = self.origin else {
+            return 0;
+        };
+        let earliest = nanos_between(&origin, earliest);
+        self.start_offsets
+            .iter()
+            .zip(&self.lengths)
+            .filter(|(offset, _)| **offset >= earliest)
+            .map(|(_, len)| *len as u64)
+            .sum()
+    }
+
+    /// The start times at which the cumulative number of bases - counting reads in start time
+    /// order from `earliest` - first reaches each of the `targets`. `targets` must be sorted in
+    /// ascending order. A target that is never reached is `None`.
+    pub fn yield_times(
+        &self,
+        earliest: &OffsetDateTime,
+        targets: &[u64],
+    ) -> Vec<Option<OffsetDateTime>> {
+        let mut times = vec![None; targets.len()];
+        let Some(origin) = self.origin else {
+            return times;
+        };
+        let earliest = nanos_between(&origin, earliest);
+        let mut order = (0..self.len())
+            .filter(|&i| self.start_offsets[i] >= earliest)
+            .collect::<Vec<_>>();
+        order.sort_by_key(|&i| self.start_offsets[i]);
+
+        let mut target_idx = 0;
+        let mut nb_bases: u64 = 0;
+        for i in order {
+            nb_bases += self.lengths[i] as u64;
+            while target_idx < targets.len() && nb_bases >= targets[target_idx] {
+                times[target_idx] = Some(self.start_time(i));
+                target_idx += 1;
+            }
+            if target_idx == targets.len() {
+                break;
+            }
+        }
+        times
+    }
+
+    /// The number of reads in each of the bins of [`bin_index`], up to the last bin with a read.
+    pub fn nb_reads_per_bin(&self, origin: &OffsetDateTime, bin_size: &Duration) -> Vec<usize> {
+        let mut nb_reads_per_bin = vec![];
+        for i in 0..self.len() {
+            let Some(bin) = bin_index(&self.start_time(i), origin, bin_size) else {
+                continue;
+            };
+            if bin >= nb_reads_per_bin.len() {
+                nb_reads_per_bin.resize(bin + 1, 0);
+            }
+            nb_reads_per_bin[bin] += 1;
+        }
+        nb_reads_per_bin
+    }
+}
+
+impl ReadTimes {
+    /// Write the read times in the binary form read by [`ReadTimes::read_from`].
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let origin = self.origin.map_or(0, |t| t.unix_timestamp_nanos());
+        writer.write_all(&[self.origin.is_some() as u8])?;
+        writer.write_all(&origin.to_le_bytes())?;
+        writer.write_all(&(self.len() as u64).to_le_bytes())?;
+        writer.write_all(&(self.nb_missing_durations as u64).to_le_bytes())?;
+        for offset in &self.start_offsets {
+            writer.write_all(&offset.to_le_bytes())?;
+        }
+        for duration in &self.durations {
+            writer.write_all(&duration.to_le_bytes())?;
+        }
+        for length in &self.lengths {
+            writer.write_all(&length.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Read the read times written by [`ReadTimes::write_to`].
+    pub fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+        let mut has_origin = [0; 1];
+        reader.read_exact(&mut has_origin)?;
+        let mut origin = [0; 16];
+        reader.read_exact(&mut origin)?;
+        let origin = match has_origin[0] {
+            0 => None,
+            _ => Some(
+                OffsetDateTime::from_unix_timestamp_nanos(i128::from_le_bytes(origin))
+                    .map_err(|_| invalid("invalid origin"))?,
+            ),
+        };
+        let mut buf = [0; 8];
+        reader.read_exact(&mut buf)?;
+        let len = u64::from_le_bytes(buf) as usize;
+        reader.read_exact(&mut buf)?;
+        let nb_missing_durations = u64::from_le_bytes(buf) as usize;
+        if origin.is_none() && len > 0 {
+            return Err(invalid("read times without an origin"));
+        }
+
+        // the length is not trusted to allocate up front, in case the data is corrupt
+        let capacity = len.min(1 << 20);
+        let mut start_offsets = Vec::with_capacity(capacity);
+        for _ in 0..len {
+            reader.read_exact(&mut buf)?;
+            start_offsets.push(i64::from_le_bytes(buf));
+        }
+        let mut buf = [0; 4];
+        let mut durations = Vec::with_capacity(capacity);
+        for _ in 0..len {
+            reader.read_exact(&mut buf)?;
+            durations.push(u32::from_le_bytes(buf));
+        }
+        let mut lengths = Vec::with_capacity(capacity);
+        for _ in 0..len {
+            reader.read_exact(&mut buf)?;
+            lengths.push(u32::from_le_bytes(buf));
+        }
+        Ok(ReadTimes {
+            origin,
+            start_offsets,
+            durations,
+            lengths,
+            nb_missing_durations,
+        })
+    }
+}
+
+/// The number of nanoseconds from `origin` to `t`, saturating at the bounds of an `i64` - about
+/// 292 years either side of `origin`.
+fn nanos_between(origin: &OffsetDateTime, t: &OffsetDateTime) -> i64 {
+    (*t - *origin)
+        .whole_nanoseconds()
+        .clamp(i64::MIN as i128, i64::MAX as i128) as i64
+}
+
+/// The first window each read belongs to. Whether a read is kept is stored as a bitset, and the
+/// window indices are only stored when there is more than one window.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Membership {
+    kept: Vec<u64>,
+    windows: Vec<u16>,
+    len: usize,
+}
+
+impl Membership {
+    /// The maximum number of windows reads can be assigned to.
+    pub const MAX_WINDOWS: usize = u16::MAX as usize + 1;
+
+    /// Create the membership of `len` reads in `nb_windows` windows, with no read kept.
+    pub fn new(len: usize, nb_windows: usize) -> Self {
+        assert!(nb_windows <= Self::MAX_WINDOWS);
+        let windows = if nb_windows > 1 { vec![0; len] } else { vec![] };
+        Membership {
+            kept: vec![0; (len + 63) / 64],
+            windows,
+            len,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Keep read `i` in `window` and the windows after it.
+    pub fn set(&mut self, i: usize, window: usize) {
+        self.kept[i / 64] |= 1 << (i % 64);
+        if !self.windows.is_empty() {
+            self.windows[i] = window as u16;
+        }
+    }
+
+    /// The first window read `i` belongs to, or `None` if it is not kept.
+    pub fn get(&self, i: usize) -> Option<usize> {
+        if self.kept[i / 64] & (1 << (i % 64)) == 0 {
+            None
+        } else {
+            Some(self.windows.get(i).map_or(0, |w| *w as usize))
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Option<usize>> + '_ {
+        (0..self.len).map(|i| self.get(i))
+    }
+
+    /// The number of reads in `range` that are kept.
+    pub fn nb_kept(&self, range: Range<usize>) -> usize {
+        range.filter(|&i| self.get(i).is_some()).count()
+    }
 }
 
 pub trait FastxRecordExt {
-    fn start_time(&self) -> Option<PrimitiveDateTime>;
+    fn start_time(&self) -> Option<OffsetDateTime>;
+    fn duration(&self) -> Option<Duration>;
+    /// The start and end time of the read. If the read has no duration, the end time is the same
+    /// as the start time.
+    fn time_interval(&self) -> Option<(OffsetDateTime, OffsetDateTime)> {
+        let start = self.start_time()?;
+        let end = self.duration().map_or(start, |d| start + d);
+        Some((start, end))
+    }
 }
 
 impl FastxRecordExt for SequenceRecord<'_> {
-    fn start_time(&self) -> Option<PrimitiveDateTime> {
+    fn start_time(&self) -> Option<OffsetDateTime> {
         let caps = DATETIME_RE.captures(self.id())?;
         let m = caps.name("time")?;
         let datetime = m.as_bytes().to_str_lossy();
-        PrimitiveDateTime::parse(&datetime, &Rfc3339).ok()
+        parse_timestamp(&datetime).ok()
+    }
+
+    fn duration(&self) -> Option<Duration> {
+        let caps = DURATION_RE.captures(self.id())?;
+        let m = caps.name("duration")?;
+        let secs = m.as_bytes().to_str_lossy().parse::<f64>().ok()?;
+        duration_from_secs(secs)
     }
 }
 
@@ -40,21 +417,161 @@ impl DurationExt for Duration {
     }
 }
 
-pub fn valid_indices(
-    timestamps: &[PrimitiveDateTime],
-    earliest: &PrimitiveDateTime,
-    latest: &PrimitiveDateTime,
-) -> (Vec<bool>, usize) {
-    let mut to_keep: Vec<bool> = vec![false; timestamps.len()];
+/// A set of sorted, non-overlapping time ranges. Both ends of each range are inclusive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimeRanges {
+    ranges: Vec<(OffsetDateTime, OffsetDateTime)>,
+}
+
+impl TimeRanges {
+    /// Create a set of time ranges from `(start, end)` pairs, merging any ranges that overlap.
+    pub fn new(mut ranges: Vec<(OffsetDateTime, OffsetDateTime)>) -> Self {
+        ranges.sort();
+        let mut merged: Vec<(OffsetDateTime, OffsetDateTime)> = Vec::with_capacity(ranges.len());
+        for (start, end) in ranges {
+            match merged.last_mut() {
+                Some((_, prev_end)) if start <= *prev_end => *prev_end = end.max(*prev_end),
+                _ => merged.push((start, end)),
+            }
+        }
+        TimeRanges { ranges: merged }
+    }
+
+    pub fn ranges(&self) -> &[(OffsetDateTime, OffsetDateTime)] {
+        &self.ranges
+    }
+
+    /// Whether `t` falls in any of the ranges.
+    pub fn contains(&self, t: &OffsetDateTime) -> bool {
+        let i = self.ranges.partition_point(|(_, end)| end < t);
+        matches!(self.ranges.get(i), Some((start, _)) if start <= t)
+    }
+
+    /// Whether a read spanning `start` to `end` is in any of the ranges, according to `overlap`.
+    pub fn selects(&self, start: &OffsetDateTime, end: &OffsetDateTime, overlap: Overlap) -> bool {
+        match overlap {
+            Overlap::Start => self.contains(start),
+            Overlap::End => self.contains(end),
+            Overlap::Contained => {
+                let i = self
+                    .ranges
+                    .partition_point(|(_, range_end)| range_end < start);
+                matches!(self.ranges.get(i), Some((range_start, range_end)) if range_start <= start && end <= range_end)
+            }
+            Overlap::Overlaps => {
+                let i = self
+                    .ranges
+                    .partition_point(|(_, range_end)| range_end < start);
+                matches!(self.ranges.get(i), Some((range_start, _)) if range_start <= end)
+            }
+        }
+    }
+}
+
+/// Decides whether a read is kept based on when it was sequenced.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimeFilter {
+    pub ranges: TimeRanges,
+    pub overlap: Overlap,
+    /// Keep the reads that are *not* in any of the ranges
+    pub invert: bool,
+}
+
+impl TimeFilter {
+    /// Whether a read spanning `start` to `end` is kept.
+    pub fn keeps(&self, start: &OffsetDateTime, end: &OffsetDateTime) -> bool {
+        self.ranges.selects(start, end, self.overlap) != self.invert
+    }
+
+    /// Whether no read starting at or after `start` can be kept. Used to stop reading early when
+    /// the input is sorted by start time.
+    pub fn is_exhausted(&self, start: &OffsetDateTime) -> bool {
+        !self.invert
+            && self
+                .ranges
+                .ranges()
+                .last()
+                .map_or(true, |(_, end)| start > end)
+    }
+}
+
+/// Assign each read that is kept by the `filter` to the (single) output.
+///
+/// Returns the membership of each read and the number of reads kept.
+pub fn range_membership(read_times: &ReadTimes, filter: &TimeFilter) -> (Membership, usize) {
+    let mut membership = Membership::new(read_times.len(), 1);
     let mut nb_reads_to_keep = 0;
-    timestamps.iter().enumerate().for_each(|(i, t)| {
-        if earliest <= t && t <= latest {
-            to_keep[i] = true;
+    for (i, (start, end)) in read_times.intervals().enumerate() {
+        if filter.keeps(&start, &end) {
+            membership.set(i, 0);
             nb_reads_to_keep += 1;
         }
-    });
+    }
 
-    (to_keep, nb_reads_to_keep)
+    (membership, nb_reads_to_keep)
+}
+
+/// Assign each read to the first of the cumulative windows `[earliest, window_ends[k]]` that it is
+/// in, according to `overlap`. `window_ends` must be sorted in ascending order. As the windows are
+/// cumulative, a read assigned to window `k` also belongs to every window after `k`.
+///
+/// Returns the membership of each read and the number of reads in each window.
+pub fn window_membership(
+    read_times: &ReadTimes,
+    earliest: &OffsetDateTime,
+    window_ends: &[OffsetDateTime],
+    overlap: Overlap,
+) -> (Membership, Vec<usize>) {
+    let mut membership = Membership::new(read_times.len(), window_ends.len());
+    let mut nb_reads_per_window = vec![0; window_ends.len()];
+    for (i, (start, end)) in read_times.intervals().enumerate() {
+        // the time that must be after the start of the windows and the time that must be before
+        // the end of a window for the read to be in it
+        let (after_earliest, before_end) = match overlap {
+            Overlap::Start => (start, start),
+            Overlap::End => (end, end),
+            Overlap::Contained => (start, end),
+            Overlap::Overlaps => (end, start),
+        };
+        if &after_earliest < earliest {
+            continue;
+        }
+        let window = window_ends.partition_point(|window_end| window_end < &before_end);
+        if window < window_ends.len() {
+            membership.set(i, window);
+            nb_reads_per_window[window] += 1;
+        }
+    }
+
+    // a read in one window is also in all of the (later) windows that follow it
+    for i in 1..nb_reads_per_window.len() {
+        nb_reads_per_window[i] += nb_reads_per_window[i - 1];
+    }
+
+    (membership, nb_reads_per_window)
+}
+
+/// Round a timestamp down to the nearest wall-clock boundary of the given bin size - e.g. the top
+/// of the hour for one hour bins. Boundaries are multiples of the bin size since the Unix epoch.
+pub fn align_to_wall_clock(t: &OffsetDateTime, bin_size: &Duration) -> OffsetDateTime {
+    let size = bin_size.whole_nanoseconds();
+    let since_epoch = (*t - OffsetDateTime::UNIX_EPOCH).whole_nanoseconds();
+    let offset = since_epoch.rem_euclid(size);
+    *t - Duration::nanoseconds(offset as i64)
+}
+
+/// The index of the half-open bin `[origin + i * bin_size, origin + (i + 1) * bin_size)` that a
+/// start time falls in. Returns `None` if the start time is before `origin`.
+pub fn bin_index(
+    t: &OffsetDateTime,
+    origin: &OffsetDateTime,
+    bin_size: &Duration,
+) -> Option<usize> {
+    if t < origin {
+        return None;
+    }
+    let since_origin = (*t - *origin).whole_nanoseconds();
+    Some((since_origin / bin_size.whole_nanoseconds()) as usize)
 }
 
 #[cfg(test)]
@@ -63,8 +580,17 @@ mod tests {
     use needletail::parse_fastx_file;
     use std::io::Write;
     use tempfile::Builder;
-    use time::macros::{date, time};
-    use time::Duration;
+    use time::macros::{date, datetime, offset, time};
+    use time::{Duration, PrimitiveDateTime};
+
+    /// Build a `ReadTimes` for reads with the given start times and no durations
+    fn read_times(start_times: &[OffsetDateTime]) -> ReadTimes {
+        let mut read_times = ReadTimes::default();
+        for t in start_times {
+            read_times.push(*t, None, 1);
+        }
+        read_times
+    }
 
     #[test]
     fn test_no_start_time() {
@@ -93,7 +619,7 @@ mod tests {
         let record = rec.unwrap();
 
         let actual = record.start_time().unwrap();
-        let expected = PrimitiveDateTime::new(date!(2022 - 12 - 12), time!(18:39:27));
+        let expected = PrimitiveDateTime::new(date!(2022 - 12 - 12), time!(18:39:27)).assume_utc();
 
         assert_eq!(actual, expected)
     }
@@ -110,9 +636,23 @@ mod tests {
         let record = rec.unwrap();
 
         let actual = record.start_time().unwrap();
-        let expected = PrimitiveDateTime::new(date!(2021 - 07 - 08), time!(17:47:25));
+        let expected = datetime!(2021-07-08 16:47:25 UTC);
 
-        assert_eq!(actual, expected)
+        assert_eq!(actual, expected);
+        assert!(actual.offset().is_utc())
+    }
+
+    #[test]
+    fn test_start_time_offsets_are_comparable() {
+        let text = "@read1 start_time=2021-07-08T17:47:25+10:00\nA\n+\n1\n@read2 start_time=2021-07-08T08:47:24Z\nA\n+\n1";
+        let mut file = Builder::new().suffix(".fq").tempfile().unwrap();
+        file.write_all(text.as_bytes()).unwrap();
+
+        let mut reader = parse_fastx_file(file.path()).unwrap();
+        let first = reader.next().unwrap().unwrap().start_time().unwrap();
+        let second = reader.next().unwrap().unwrap().start_time().unwrap();
+
+        assert!(second > first)
     }
 
     #[test]
@@ -126,7 +666,7 @@ mod tests {
         let record = rec.unwrap();
 
         let actual = record.start_time().unwrap();
-        let expected = PrimitiveDateTime::new(date!(2021 - 07 - 08), time!(17:47:25.558027));
+        let expected = datetime!(2021-07-08 16:47:25.558027 UTC);
 
         assert_eq!(actual, expected)
     }
@@ -172,7 +712,8 @@ mod tests {
         let record = rec.unwrap();
 
         let actual = record.start_time().unwrap();
-        let expected = PrimitiveDateTime::new(date!(2023 - 08 - 07), time!(13:14:42.356));
+        let expected =
+            PrimitiveDateTime::new(date!(2023 - 08 - 07), time!(13:14:42.356)).assume_utc();
 
         assert_eq!(actual, expected)
     }
@@ -208,4 +749,424 @@ mod tests {
         let actual = Duration::from_str(s);
         assert!(actual.is_err())
     }
+
+    #[test]
+    fn test_parse_utc_offset() {
+        assert_eq!(parse_utc_offset("Z").unwrap(), UtcOffset::UTC);
+        assert_eq!(parse_utc_offset("+10:00").unwrap(), offset!(+10));
+        assert_eq!(parse_utc_offset("-05:30").unwrap(), offset!(-5:30));
+        assert!(parse_utc_offset("10").is_err());
+    }
+
+    #[test]
+    fn test_format_timestamp() {
+        let t = datetime!(2022-12-12 12:00:00 UTC);
+
+        assert_eq!(
+            format_timestamp(&t, UtcOffset::UTC).unwrap(),
+            "2022-12-12T12:00:00.0Z"
+        );
+        assert_eq!(
+            format_timestamp(&t, offset!(+10)).unwrap(),
+            "2022-12-12T22:00:00.0+10:00"
+        );
+    }
+
+    #[test]
+    fn test_time_ranges_merge_overlapping() {
+        let ranges = TimeRanges::new(vec![
+            (
+                datetime!(2022-12-12 22:00:00 UTC),
+                datetime!(2022-12-12 23:00:00 UTC),
+            ),
+            (
+                datetime!(2022-12-12 12:00:00 UTC),
+                datetime!(2022-12-12 14:00:00 UTC),
+            ),
+            (
+                datetime!(2022-12-12 13:00:00 UTC),
+                datetime!(2022-12-12 15:00:00 UTC),
+            ),
+            (
+                datetime!(2022-12-12 15:00:00 UTC),
+                datetime!(2022-12-12 15:30:00 UTC),
+            ),
+        ]);
+        let expected = [
+            (
+                datetime!(2022-12-12 12:00:00 UTC),
+                datetime!(2022-12-12 15:30:00 UTC),
+            ),
+            (
+                datetime!(2022-12-12 22:00:00 UTC),
+                datetime!(2022-12-12 23:00:00 UTC),
+            ),
+        ];
+
+        assert_eq!(ranges.ranges(), expected)
+    }
+
+    #[test]
+    fn test_read_times_extend() {
+        let mut actual = read_times(&[datetime!(2022-12-12 12:00 UTC)]);
+        let mut other = ReadTimes::default();
+        other.push(
+            datetime!(2022-12-12 11:00 UTC),
+            Some(Duration::seconds(10)),
+            5,
+        );
+        actual.extend(other);
+
+        assert_eq!(actual.len(), 2);
+        assert_eq!(actual.start_time(1), datetime!(2022-12-12 11:00 UTC));
+        assert_eq!(actual.end_time(1), datetime!(2022-12-12 11:00:10 UTC));
+        assert_eq!(actual.nb_bases_since(&datetime!(2022-12-12 11:00 UTC)), 6);
+        assert_eq!(actual.nb_missing_durations, 1);
+    }
+
+    #[test]
+    fn test_read_times_relative_to_first_read() {
+        let mut actual = read_times(&[
+            datetime!(2022-12-12 12:00:00.000001 +10:00),
+            datetime!(2022-12-12 01:00 UTC),
+        ]);
+        let mut other = ReadTimes::default();
+        other.push(
+            datetime!(2022-12-11 23:00 UTC),
+            Some(Duration::milliseconds(1500)),
+            5,
+        );
+        actual.extend(other);
+
+        let expected = vec![
+            (
+                datetime!(2022-12-12 02:00:00.000001 UTC),
+                datetime!(2022-12-12 02:00:00.000001 UTC),
+            ),
+            (
+                datetime!(2022-12-12 01:00 UTC),
+                datetime!(2022-12-12 01:00 UTC),
+            ),
+            (
+                datetime!(2022-12-11 23:00 UTC),
+                datetime!(2022-12-11 23:00:01.5 UTC),
+            ),
+        ];
+        assert_eq!(actual.intervals().collect::<Vec<_>>(), expected);
+        assert_eq!(
+            actual.start_time_bounds(),
+            Some((
+                datetime!(2022-12-11 23:00 UTC),
+                datetime!(2022-12-12 02:00:00.000001 UTC)
+            ))
+        );
+        assert_eq!(
+            actual.last_end_time(),
+            Some(datetime!(2022-12-12 02:00:00.000001 UTC))
+        );
+        assert_eq!(ReadTimes::default().start_time_bounds(), None);
+    }
+
+    #[test]
+    fn test_read_times_round_trip() {
+        let mut expected = read_times(&[
+            datetime!(2022-12-12 12:00:00.000001 +10:00),
+            datetime!(2022-12-12 01:00 UTC),
+        ]);
+        expected.push(
+            datetime!(2022-12-11 23:00 UTC),
+            Some(Duration::milliseconds(1500)),
+            5,
+        );
+        let mut buf = vec![];
+        expected.write_to(&mut buf).unwrap();
+        let actual = ReadTimes::read_from(&mut buf.as_slice()).unwrap();
+
+        assert_eq!(actual, expected);
+
+        let mut buf = vec![];
+        ReadTimes::default().write_to(&mut buf).unwrap();
+        let actual = ReadTimes::read_from(&mut buf.as_slice()).unwrap();
+
+        assert_eq!(actual, ReadTimes::default());
+        assert!(ReadTimes::read_from(&mut &buf[..10]).is_err());
+    }
+
+    #[test]
+    fn test_membership_bitset() {
+        let mut membership = Membership::new(130, 3);
+        membership.set(0, 2);
+        membership.set(64, 0);
+        membership.set(129, 1);
+
+        assert_eq!(membership.get(0), Some(2));
+        assert_eq!(membership.get(1), None);
+        assert_eq!(membership.get(64), Some(0));
+        assert_eq!(membership.get(129), Some(1));
+        assert_eq!(membership.nb_kept(0..130), 3);
+        assert_eq!(membership.nb_kept(1..129), 1);
+
+        let mut single = Membership::new(2, 1);
+        single.set(1, 0);
+        assert_eq!(single.iter().collect::<Vec<_>>(), vec![None, Some(0)]);
+    }
+
+    #[test]
+    fn test_range_membership() {
+        let timestamps = [
+            datetime!(2022-12-12 18:00:00 UTC),
+            datetime!(2022-12-12 14:00:00 UTC),
+            datetime!(2022-12-12 12:00:00 UTC),
+            datetime!(2022-12-12 23:00:00 UTC),
+        ];
+        let ranges = TimeRanges::new(vec![
+            (
+                datetime!(2022-12-12 13:00:00 UTC),
+                datetime!(2022-12-12 15:00:00 UTC),
+            ),
+            (
+                datetime!(2022-12-12 22:00:00 UTC),
+                datetime!(2022-12-12 23:00:00 UTC),
+            ),
+        ]);
+
+        let mut filter = TimeFilter {
+            ranges,
+            overlap: Overlap::Start,
+            invert: false,
+        };
+        let (actual, nb_reads) = range_membership(&read_times(&timestamps), &filter);
+        let expected = vec![None, Some(0), None, Some(0)];
+
+        assert_eq!(actual.iter().collect::<Vec<_>>(), expected);
+        assert_eq!(nb_reads, 2);
+
+        filter.invert = true;
+        let (actual, nb_reads) = range_membership(&read_times(&timestamps), &filter);
+        let expected = vec![Some(0), None, Some(0), None];
+
+        assert_eq!(actual.iter().collect::<Vec<_>>(), expected);
+        assert_eq!(nb_reads, 2)
+    }
+
+    #[test]
+    fn test_time_filter_is_exhausted() {
+        let mut filter = TimeFilter {
+            ranges: TimeRanges::new(vec![(
+                datetime!(2022-12-12 12:00:00 UTC),
+                datetime!(2022-12-12 13:00:00 UTC),
+            )]),
+            overlap: Overlap::Start,
+            invert: false,
+        };
+
+        assert!(!filter.is_exhausted(&datetime!(2022-12-12 13:00:00 UTC)));
+        assert!(filter.is_exhausted(&datetime!(2022-12-12 13:00:01 UTC)));
+
+        filter.invert = true;
+        assert!(!filter.is_exhausted(&datetime!(2022-12-12 13:00:01 UTC)));
+    }
+
+    #[test]
+    fn test_window_membership_single_window() {
+        let timestamps = [
+            datetime!(2022-12-12 18:00:00 UTC),
+            datetime!(2022-12-12 14:00:00 UTC),
+            datetime!(2022-12-12 12:00:00 UTC),
+        ];
+        let earliest = datetime!(2022-12-12 13:00:00 UTC);
+        let latest = datetime!(2022-12-12 15:00:00 UTC);
+
+        let (actual, counts) = window_membership(
+            &read_times(&timestamps),
+            &earliest,
+            &[latest],
+            Overlap::Start,
+        );
+        let expected = vec![None, Some(0), None];
+
+        assert_eq!(actual.iter().collect::<Vec<_>>(), expected);
+        assert_eq!(counts, vec![1])
+    }
+
+    #[test]
+    fn test_window_membership_cumulative_windows() {
+        let timestamps = [
+            datetime!(2022-12-12 12:30:00 UTC),
+            datetime!(2022-12-12 14:00:00 UTC),
+            datetime!(2022-12-12 12:00:00 UTC),
+            datetime!(2022-12-12 20:00:00 UTC),
+            datetime!(2022-12-12 13:00:00 UTC),
+        ];
+        let earliest = datetime!(2022-12-12 12:00:00 UTC);
+        let ends = [
+            datetime!(2022-12-12 13:00:00 UTC),
+            datetime!(2022-12-12 14:00:00 UTC),
+            datetime!(2022-12-12 16:00:00 UTC),
+        ];
+
+        let (actual, counts) =
+            window_membership(&read_times(&timestamps), &earliest, &ends, Overlap::Start);
+        let expected = vec![Some(0), Some(1), Some(0), None, Some(0)];
+
+        assert_eq!(actual.iter().collect::<Vec<_>>(), expected);
+        assert_eq!(counts, vec![3, 4, 4])
+    }
+
+    #[test]
+    fn test_align_to_wall_clock() {
+        let t = datetime!(2022-12-12 12:34:56.789 UTC);
+
+        assert_eq!(
+            align_to_wall_clock(&t, &Duration::hours(1)),
+            datetime!(2022-12-12 12:00:00 UTC)
+        );
+        assert_eq!(
+            align_to_wall_clock(&t, &Duration::minutes(15)),
+            datetime!(2022-12-12 12:30:00 UTC)
+        );
+        assert_eq!(
+            align_to_wall_clock(&t, &Duration::days(1)),
+            datetime!(2022-12-12 00:00:00 UTC)
+        );
+    }
+
+    #[test]
+    fn test_bin_index_is_half_open() {
+        let origin = datetime!(2022-12-12 12:00:00 UTC);
+        let size = Duration::hours(1);
+
+        assert_eq!(bin_index(&origin, &origin, &size), Some(0));
+        assert_eq!(
+            bin_index(&datetime!(2022-12-12 12:59:59.999 UTC), &origin, &size),
+            Some(0)
+        );
+        assert_eq!(
+            bin_index(&datetime!(2022-12-12 13:00:00 UTC), &origin, &size),
+            Some(1)
+        );
+        assert_eq!(
+            bin_index(&datetime!(2022-12-12 11:59:59 UTC), &origin, &size),
+            None
+        );
+    }
+
+    #[test]
+    fn test_nb_reads_per_bin() {
+        let origin = datetime!(2022-12-12 12:00:00 UTC);
+        let read_times = read_times(&[
+            datetime!(2022-12-12 14:30:00 UTC),
+            datetime!(2022-12-12 12:00:00 UTC),
+            datetime!(2022-12-12 11:00:00 UTC),
+            datetime!(2022-12-12 12:59:59 UTC),
+        ]);
+
+        assert_eq!(
+            read_times.nb_reads_per_bin(&origin, &Duration::hours(1)),
+            vec![2, 0, 1]
+        );
+    }
+
+    #[test]
+    fn test_yield_times() {
+        let mut read_times = ReadTimes::default();
+        read_times.push(datetime!(2022-12-12 14:00:00 UTC), None, 300);
+        read_times.push(datetime!(2022-12-12 12:00:00 UTC), None, 100);
+        read_times.push(datetime!(2022-12-12 13:00:00 UTC), None, 200);
+        read_times.push(datetime!(2022-12-12 11:00:00 UTC), None, 5000);
+        let earliest = datetime!(2022-12-12 12:00:00 UTC);
+
+        let actual = read_times.yield_times(&earliest, &[1, 100, 250, 600, 601]);
+        let expected = vec![
+            Some(datetime!(2022-12-12 12:00:00 UTC)),
+            Some(datetime!(2022-12-12 12:00:00 UTC)),
+            Some(datetime!(2022-12-12 13:00:00 UTC)),
+            Some(datetime!(2022-12-12 14:00:00 UTC)),
+            None,
+        ];
+
+        assert_eq!(actual, expected)
+    }
+
+    #[test]
+    fn test_duration_from_header() {
+        let text = "@read1 start_time=2022-12-12T18:39:27Z duration=1.5\nA\n+\n1\n@read2 st:Z:2022-12-12T18:39:27Z du:f:2\nA\n+\n1";
+        let mut file = Builder::new().suffix(".fq").tempfile().unwrap();
+        file.write_all(text.as_bytes()).unwrap();
+
+        let mut reader = parse_fastx_file(file.path()).unwrap();
+        let first = reader.next().unwrap().unwrap();
+        assert_eq!(first.duration(), Some(Duration::milliseconds(1500)));
+        assert_eq!(
+            first.time_interval(),
+            Some((
+                datetime!(2022-12-12 18:39:27 UTC),
+                datetime!(2022-12-12 18:39:28.5 UTC)
+            ))
+        );
+        let second = reader.next().unwrap().unwrap();
+        assert_eq!(second.duration(), Some(Duration::seconds(2)));
+    }
+
+    #[test]
+    fn test_ranges_select_with_overlap() {
+        let ranges = TimeRanges::new(vec![(
+            datetime!(2022-12-12 12:00:00 UTC),
+            datetime!(2022-12-12 13:00:00 UTC),
+        )]);
+        // starts before the range and ends in it
+        let start = datetime!(2022-12-12 11:59:00 UTC);
+        let end = datetime!(2022-12-12 12:01:00 UTC);
+
+        assert!(!ranges.selects(&start, &end, Overlap::Start));
+        assert!(ranges.selects(&start, &end, Overlap::End));
+        assert!(!ranges.selects(&start, &end, Overlap::Contained));
+        assert!(ranges.selects(&start, &end, Overlap::Overlaps));
+
+        // spans the whole range
+        let start = datetime!(2022-12-12 11:00:00 UTC);
+        let end = datetime!(2022-12-12 14:00:00 UTC);
+
+        assert!(!ranges.selects(&start, &end, Overlap::Start));
+        assert!(!ranges.selects(&start, &end, Overlap::End));
+        assert!(!ranges.selects(&start, &end, Overlap::Contained));
+        assert!(ranges.selects(&start, &end, Overlap::Overlaps));
+
+        // inside the range
+        let start = datetime!(2022-12-12 12:10:00 UTC);
+        let end = datetime!(2022-12-12 12:20:00 UTC);
+
+        assert!(ranges.selects(&start, &end, Overlap::Contained));
+    }
+
+    #[test]
+    fn test_window_membership_with_overlap() {
+        let mut read_times = ReadTimes::default();
+        // starts in the first window, ends in the second
+        read_times.push(
+            datetime!(2022-12-12 12:30:00 UTC),
+            Some(Duration::hours(1)),
+            1,
+        );
+        // starts before the windows, ends in the first
+        read_times.push(
+            datetime!(2022-12-12 11:30:00 UTC),
+            Some(Duration::hours(1)),
+            1,
+        );
+        let earliest = datetime!(2022-12-12 12:00:00 UTC);
+        let ends = [
+            datetime!(2022-12-12 13:00:00 UTC),
+            datetime!(2022-12-12 14:00:00 UTC),
+        ];
+
+        let (actual, _) = window_membership(&read_times, &earliest, &ends, Overlap::Start);
+        assert_eq!(actual.iter().collect::<Vec<_>>(), vec![Some(0), None]);
+        let (actual, _) = window_membership(&read_times, &earliest, &ends, Overlap::End);
+        assert_eq!(actual.iter().collect::<Vec<_>>(), vec![Some(1), Some(0)]);
+        let (actual, _) = window_membership(&read_times, &earliest, &ends, Overlap::Contained);
+        assert_eq!(actual.iter().collect::<Vec<_>>(), vec![Some(1), None]);
+        let (actual, _) = window_membership(&read_times, &earliest, &ends, Overlap::Overlaps);
+        assert_eq!(actual.iter().collect::<Vec<_>>(), vec![Some(0), Some(0)]);
+    }
 }
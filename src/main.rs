@@ -1,31 +1,47 @@
+mod bam_index;
 mod cli;
+mod faidx;
+mod index;
 mod io;
+mod parallel;
+mod sort;
+mod unaligned;
 
-use crate::cli::Cli;
-use crate::io::Fastx;
+use crate::bam_index::{BamIndexFormat, IndexedBamWriter};
+use crate::cli::{Cli, Command, Index, OutputCompression, Split};
+use crate::faidx::write_indices;
+use crate::index::{is_index_path, FileStamp, OffsetKind, TimeIndex};
 use crate::io::TimeExt;
+use crate::io::{
+    add_provenance, alignment_writer_builder, alignment_writer_to_path, bgzf_writer,
+    detect_file_format, extract_indexed_bam_records_into, format_from_extension,
+    is_coordinate_sorted, is_fasta_path, is_grouped_by_name, is_stream, merge_header,
+    open_alignment, peek_alignment_header, reference_repository, set_start_time_sort_order,
+    sniff_stream, spill_to_tempfile, stream_fastx_reads_in_timeframe_into,
+    strip_compression_extension, write_sorted_records, Fastx, FastxRecordWriter, FileFormat,
+    IOError, RecordWriter, SecondaryRecords,
+};
+use crate::sort::StartTimeSorter;
+use crate::unaligned::{unaligned_header, FastxOutput, ReadGroups, UnalignedRecordWriter};
 use anyhow::{anyhow, Context, Result};
 use clap::Parser;
 use env_logger::Builder;
 use itertools::Itertools;
-use itertools::MinMaxResult::{MinMax, NoElements, OneElement};
-use log::info;
 use log::LevelFilter;
-use ontime::{valid_indices, DurationExt};
-use std::io::stdout;
-use time::format_description::well_known::Rfc3339;
-use time::format_description::FormatItem;
-use time::macros::format_description;
-use time::{Duration, PrimitiveDateTime};
-
-const TIME_FMT: &[FormatItem<'_>] =
-    format_description!("[year]-[month]-[day]T[hour]:[minute]:[second].[subsecond]Z");
-
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
-enum FileFormat {
-    Alignment,
-    Fastx,
-}
+use log::{debug, info, warn};
+use noodles_fasta as fasta;
+use noodles_sam as sam;
+use noodles_util::alignment::io::Writer;
+use ontime::{
+    align_to_wall_clock, format_timestamp, parse_timestamp, range_membership, window_membership,
+    DurationExt, Membership, ReadTimes, TimeFilter, TimeRanges,
+};
+use std::fs::File;
+use std::io::{stdout, Read, Write};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use time::{Duration, OffsetDateTime, PrimitiveDateTime, UtcOffset};
+use wildmatch::WildMatch;
 
 fn main() -> Result<()> {
     let args = Cli::parse();
@@ -37,177 +53,1311 @@ fn main() -> Result<()> {
         .format_target(false)
         .init();
 
-    let input_format = match &args.input.extension().and_then(|ext| ext.to_str()) {
-        Some("sam" | "bam") => FileFormat::Alignment,
-        Some("fastq" | "fq" | "fasta" | "fa") => FileFormat::Fastx,
-        Some("gz") => {
-            let p = args.input.with_extension("");
-            let ext = p.extension().and_then(|ext| ext.to_str());
-            match ext {
-                Some("sam" | "bam") => FileFormat::Alignment,
-                Some("fastq" | "fq" | "fasta" | "fa") => FileFormat::Fastx,
-                _ => return Err(anyhow!("Unrecognized file extension for input file")),
-            }
-        }
-        _ => return Err(anyhow!("Unrecognized file extension for input file")),
-    };
-
-    let output_type = match &args.output {
-        None => input_format,
-        Some(p) => match &p.extension().and_then(|ext| ext.to_str()) {
-            Some("sam" | "bam") => FileFormat::Alignment,
-            Some("fastq" | "fq" | "fasta" | "fa") => FileFormat::Fastx,
-            Some("gz") => {
-                let p = p.with_extension("");
-                let ext = p.extension().and_then(|ext| ext.to_str());
-                match ext {
-                    Some("sam" | "bam") => FileFormat::Alignment,
-                    Some("fastq" | "fq" | "fasta" | "fa") => FileFormat::Fastx,
-                    _ => return Err(anyhow!("Unrecognized file extension for output file")),
+    match &args.command {
+        Some(Command::Split(split_args)) => return split(split_args),
+        Some(Command::Index(index_args)) => return index(index_args),
+        None => {}
+    }
+    let inputs = expand_inputs(&args.input, args.glob.as_deref())?;
+
+    // stdin and named pipes are spilled to a temporary file if two passes are needed
+    let (stream, input_format) = open_inputs(&inputs, args.input_format)?;
+    let output_type = match (&args.output, args.output_format) {
+        (_, Some(format)) => format,
+        (None, None) => input_format,
+        (Some(p), None) => output_format(p)?,
+    };
+    if args.write_index.is_some() && output_type == FileFormat::Alignment {
+        if !args.output.as_deref().map_or(false, is_bam_path) {
+            return Err(anyhow!(
+                "--write-index is only supported for fasta/fastq and BAM output"
+            ));
+        }
+        if input_format == FileFormat::Fastx {
+            warn!("Unaligned BAM output is not coordinate-sorted, so it is not indexed");
+        }
+    }
+    if let Some(output) = args
+        .output
+        .as_deref()
+        .filter(|_| args.write_index.is_some() && output_type == FileFormat::Fastx)
+    {
+        if !Fastx::from_path(output).is_indexable(
+            args.compress_level,
+            args.output_type,
+            args.threads,
+        ) {
+            return Err(anyhow!(
+                "--write-index: only uncompressed or BGZF fasta/fastq output can be indexed (-O bgzf or a .bgz extension)"
+            ));
+        }
+    }
+
+    let repository =
+        reference_repository(args.reference.as_deref()).context("Failed to read the reference")?;
+
+    // the header of an alignment stream tells whether it can be read in a single pass
+    let (stream, stream_header) = match stream {
+        Some(stream) if input_format == FileFormat::Alignment => {
+            let (stream, header) = peek_alignment_header(stream, &repository)?;
+            (Some(stream), Some(header))
+        }
+        stream => (stream, None),
+    };
+    let peekable_inputs = stream.is_none().then_some(inputs.as_slice());
+    if let Some(filter) = single_pass_filter(
+        &args,
+        peekable_inputs,
+        stream_header.as_ref(),
+        input_format,
+        output_type,
+        &repository,
+    )? {
+        info!("Extracting reads in a single pass...");
+        let mut stream = stream;
+        let mut nb_reads_kept = 0;
+        match input_format {
+            FileFormat::Fastx => {
+                let mut output_handle =
+                    FastxOutput::Fastx(fastx_writer(args.output.as_deref(), &args)?);
+                for input in &inputs {
+                    let reader = match stream.take() {
+                        Some(stream) => stream,
+                        None => open_file(input)?,
+                    };
+                    nb_reads_kept += stream_fastx_reads_in_timeframe_into(
+                        reader,
+                        &filter,
+                        args.sorted,
+                        &mut output_handle,
+                    )
+                    .with_context(|| format!("Failed to extract reads from {}", input.display()))?;
                 }
+                output_handle
+                    .finish()
+                    .context("Failed to write the output")?;
             }
-            _ => return Err(anyhow!("Unrecognized file extension for output file")),
-        },
-    };
-    if input_format != output_type {
-        return Err(anyhow!("Input and output file formats do not match"));
+            FileFormat::Alignment => {
+                // alignment output is written in two passes, so the records are written as
+                // fasta/fastq, which has no header
+                let mut writer = record_writer(
+                    args.output.as_deref(),
+                    output_type,
+                    &repository,
+                    &args,
+                    None,
+                )?;
+                for input in &inputs {
+                    let reader = match stream.take() {
+                        Some(stream) => stream,
+                        None => open_file(input)?,
+                    };
+                    let (mut reader, header) = open_alignment(reader, &repository, args.threads)?;
+                    let (nb_reads, nb_orphans) = reader
+                        .stream_reads_in_timeframe_into(
+                            &header,
+                            &filter,
+                            args.sorted,
+                            args.primary_only,
+                            &mut writer,
+                        )
+                        .with_context(|| {
+                            format!("Failed to extract reads from {}", input.display())
+                        })?;
+                    nb_reads_kept += nb_reads;
+                    if nb_orphans > 0 {
+                        warn!(
+                            "{} secondary/supplementary records in {} are not next to the primary record of their read, so they are skipped",
+                            nb_orphans,
+                            input.display()
+                        );
+                    }
+                }
+                writer.finish(&sam::Header::default())?;
+            }
+        };
+        if let Some(output) = args
+            .output
+            .as_deref()
+            .filter(|_| args.write_index.is_some() && output_type == FileFormat::Fastx)
+        {
+            write_output_indices(output)?;
+        }
+        info!("Done! Kept {} reads", nb_reads_kept);
+        return Ok(());
     }
 
-    let input_fastx = Fastx::from_path(&args.input);
-    let mut bam_reader =
-        noodles_util::alignment::io::reader::Builder::default().build_from_path(&args.input)?;
+    // keep the temporary file alive until the end of main
+    let spilled = match stream {
+        None => None,
+        Some(stream) => {
+            info!("Copying the input stream to a temporary file...");
+            Some(spill_to_tempfile(stream).context("Failed to copy the input stream")?)
+        }
+    };
+    let inputs = match &spilled {
+        Some(spilled) => vec![spilled.path().to_path_buf()],
+        None => inputs,
+    };
 
     info!("Extracting read start times...");
 
-    let start_times = match input_format {
-        FileFormat::Fastx => input_fastx.start_times(),
-        FileFormat::Alignment => bam_reader.start_times(),
+    // the reads of all inputs are numbered consecutively, so we keep the number of reads in each
+    // input to map a read back to the input it came from
+    let mut read_times = ReadTimes::default();
+    let mut nb_reads_per_input = Vec::with_capacity(inputs.len());
+    // the secondary and supplementary records of each input, and the offsets of its records if it
+    // has an index, used to seek to the kept reads
+    let mut input_records = Vec::with_capacity(inputs.len());
+    // the read groups of fasta/fastq inputs, for the header of unaligned output
+    let mut read_groups = ReadGroups::default();
+    for input in &inputs {
+        let (input_read_times, mut secondary, input_read_groups, offset_kind, offsets) = if args
+            .no_index
+            || spilled.is_some()
+        {
+            let (input_read_times, secondary, input_read_groups) = match input_format {
+                FileFormat::Fastx => Fastx::from_path(input).read_times(args.threads).map(
+                    |(read_times, read_groups)| {
+                        (read_times, SecondaryRecords::default(), read_groups)
+                    },
+                ),
+                FileFormat::Alignment => {
+                    open_alignment(open_file(input)?, &repository, args.threads)
+                        .and_then(|(mut reader, header)| reader.read_times(&header))
+                        .map(|(read_times, secondary)| {
+                            (read_times, secondary, ReadGroups::default())
+                        })
+                }
+            }
+            .with_context(|| format!("Failed to extract start times from {}", input.display()))?;
+            (
+                input_read_times,
+                secondary,
+                input_read_groups,
+                OffsetKind::None,
+                vec![],
+            )
+        } else {
+            let index = load_or_build_index(input, input_format, &repository, args.threads)?;
+            (
+                index.read_times,
+                index.secondary,
+                index.read_groups,
+                index.offset_kind,
+                index.offsets,
+            )
+        };
+        read_groups.extend(input_read_groups);
+        log_orphans(&secondary, input);
+        if args.primary_only {
+            secondary.detach();
+        }
+        nb_reads_per_input.push(input_read_times.len());
+        read_times.extend(input_read_times);
+        input_records.push((offset_kind, offsets, secondary));
     }
-    .context("Failed to extract start times")?;
-
-    if start_times.is_empty() {
+    if read_times.is_empty() {
         return Err(anyhow!("Did not find any start times in the input"));
     }
 
-    info!("Gathered start times for {} reads", start_times.len());
+    info!("Gathered start times for {} reads", read_times.len());
+    if args.overlap.needs_end_time() && read_times.nb_missing_durations > 0 {
+        warn!(
+            "{} reads have no duration (du:f tag or duration= field), so their end time is taken to be their start time",
+            read_times.nb_missing_durations
+        );
+    }
+
+    let (first_timestamp, last_timestamp) = read_times
+        .start_time_bounds()
+        .context("No start times in input fastq")?;
 
-    // safe to unwrap as we know start times is not empty
-    let (first_timestamp, last_timestamp) = match start_times.iter().minmax() {
-        NoElements => return Err(anyhow!("No start times in input fastq")),
-        OneElement(el) => (*el, *el),
-        MinMax(x, y) => (*x, *y),
+    let earliest = match &args.earliest {
+        None => first_timestamp.to_owned(),
+        Some(s) => resolve_time(s, &first_timestamp, &last_timestamp, "--from")?,
+    };
+
+    // --until-depth is turned into a number of bases using the genome size
+    let target_bases = match (args.until_bases, args.until_depth, args.genome_size) {
+        (Some(bases), _, _) => Some(bases),
+        (None, Some(depth), Some(genome_size)) => Some((depth * genome_size as f64).round() as u64),
+        _ => None,
+    };
+    let target_time = match target_bases {
+        None => None,
+        Some(target) => {
+            let t = read_times.yield_times(&earliest, &[target])[0];
+            match t {
+                Some(t) => info!(
+                    "Reached {} bases at {} ({} after the earliest start time)",
+                    target,
+                    format_timestamp(&t, args.utc_offset)?,
+                    t - earliest
+                ),
+                None => warn!(
+                    "The input has fewer than {} bases after the earliest start time",
+                    target
+                ),
+            }
+            t
+        }
     };
 
     if args.show {
-        println!("Earliest: {}", first_timestamp.format(TIME_FMT)?);
-        println!("Latest  : {}", last_timestamp.format(TIME_FMT)?);
+        println!(
+            "Earliest: {}",
+            format_timestamp(&first_timestamp, args.utc_offset)?
+        );
+        println!(
+            "Latest  : {}",
+            format_timestamp(&last_timestamp, args.utc_offset)?
+        );
+        if let Some(t) = target_time {
+            println!("Target  : {}", format_timestamp(&t, args.utc_offset)?);
+        }
+        if let Some(genome_size) = args.genome_size {
+            print_depth_table(&read_times, &earliest, genome_size, args.utc_offset)?;
+        }
         return Ok(());
     }
     info!(
         "First and last timestamps in the input are {} and {}",
-        first_timestamp.format(TIME_FMT)?,
-        last_timestamp.format(TIME_FMT)?
+        format_timestamp(&first_timestamp, args.utc_offset)?,
+        format_timestamp(&last_timestamp, args.utc_offset)?
     );
 
-    let earliest = match args.earliest {
-        None => first_timestamp.to_owned(),
-        Some(s) => match PrimitiveDateTime::parse(&s, &Rfc3339) {
-            Ok(t) => t,
-            Err(_) => {
-                let duration = Duration::from_str(&s)?;
-                if duration.is_negative() {
-                    last_timestamp
-                        .checked_add(duration)
-                        .context("Subtracting --from from the last timestamp caused an overflow")?
-                } else {
-                    first_timestamp
-                        .checked_add(duration)
-                        .context("Adding --from to the first timestamp caused an overflow")?
+    // reads can end after the last start time, so make sure they are all included by default
+    let run_end = if args.overlap.needs_end_time() {
+        // safe to unwrap as we know there are reads
+        read_times.last_end_time().unwrap()
+    } else {
+        last_timestamp
+    };
+
+    // the timeframe of each output, for the @PG line of alignment outputs
+    let mut timeframes = vec![];
+    // without --windows, reads from one or more ranges are written to a single output. With
+    // --windows, reads are written to the output of each cumulative window they fall in
+    let (membership, nb_reads_per_window, output_paths, window_names) = if args.windows.is_empty() {
+        let ranges = if args.ranges.is_empty() {
+            let latest = match (&args.latest, target_bases) {
+                (_, Some(_)) => target_time.unwrap_or_else(|| {
+                    warn!("Target not reached; keeping all reads");
+                    run_end
+                }),
+                (None, None) => run_end,
+                (Some(s), None) => resolve_time(s, &first_timestamp, &last_timestamp, "--to")?,
+            };
+            vec![(earliest, latest)]
+        } else {
+            args.ranges
+                .iter()
+                .map(|r| resolve_range(r, &first_timestamp, &last_timestamp, &run_end))
+                .collect::<Result<Vec<_>>>()?
+        };
+        if ranges.iter().any(|(start, end)| end < start) {
+            return Err(anyhow!(
+                "The earliest timestamp is after the latest timestamp"
+            ));
+        }
+
+        let ranges = TimeRanges::new(ranges);
+        timeframes.push(describe_timeframe(
+            ranges.ranges(),
+            args.invert,
+            args.utc_offset,
+        )?);
+        let verb = if args.invert {
+            "Excluding"
+        } else {
+            "Extracting"
+        };
+        for (start, end) in ranges.ranges() {
+            info!(
+                "{} reads with a start time between {} and {}...",
+                verb,
+                format_timestamp(start, args.utc_offset)?,
+                format_timestamp(end, args.utc_offset)?
+            );
+        }
+        let filter = TimeFilter {
+            ranges,
+            overlap: args.overlap,
+            invert: args.invert,
+        };
+        let (membership, nb_reads_to_keep) = range_membership(&read_times, &filter);
+        (
+            membership,
+            vec![nb_reads_to_keep],
+            vec![args.output.clone()],
+            vec![],
+        )
+    } else {
+        // each window is a (name, end) pair
+        let mut windows = args
+            .windows
+            .iter()
+            .map(|w| {
+                let end = resolve_time(w, &first_timestamp, &last_timestamp, "--windows")?;
+                Ok((w.to_owned(), end))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if windows.len() > Membership::MAX_WINDOWS {
+            return Err(anyhow!(
+                "At most {} windows can be given",
+                Membership::MAX_WINDOWS
+            ));
+        }
+        windows.sort_by_key(|(_, end)| *end);
+
+        if windows[0].1 < earliest {
+            return Err(anyhow!(
+                "The earliest timestamp is after the latest timestamp"
+            ));
+        }
+
+        // clap ensures --output is given with --windows
+        let template = args.output.as_ref().unwrap().to_string_lossy();
+        if !template.contains("{window}") {
+            return Err(anyhow!(
+                "The --output template must contain {{window}} when using --windows"
+            ));
+        }
+        let stem = input_stem(&args.input[0]);
+        let output_paths = windows
+            .iter()
+            .map(|(name, _)| Some(fill_template(&template, &stem, "{window}", name)))
+            .collect_vec();
+
+        let window_ends = windows.iter().map(|(_, end)| *end).collect_vec();
+        for end in &window_ends {
+            info!(
+                "Extracting reads with a start time between {} and {}...",
+                format_timestamp(&earliest, args.utc_offset)?,
+                format_timestamp(end, args.utc_offset)?
+            );
+        }
+        let (membership, nb_reads_per_window) =
+            window_membership(&read_times, &earliest, &window_ends, args.overlap);
+        let window_names = windows.into_iter().map(|(name, _)| name).collect_vec();
+        for end in &window_ends {
+            timeframes.push(describe_timeframe(
+                &[(earliest, *end)],
+                false,
+                args.utc_offset,
+            )?);
+        }
+        (membership, nb_reads_per_window, output_paths, window_names)
+    };
+    // windows are cumulative, so the last window holds every read that is kept
+    let nb_reads_to_keep = *nb_reads_per_window.last().unwrap();
+
+    // the index of the first read of each input in `membership`, and the number of its reads kept
+    let mut offset = 0;
+    let input_memberships = nb_reads_per_input
+        .iter()
+        .zip(input_records)
+        .map(|(nb_reads, records)| {
+            let first_read = offset;
+            offset += nb_reads;
+            (first_read, membership.nb_kept(first_read..offset), records)
+        })
+        .collect_vec();
+
+    match input_format {
+        FileFormat::Fastx => {
+            let mut output_handles = output_paths
+                .iter()
+                .zip(&timeframes)
+                .zip(&nb_reads_per_window)
+                .map(|((output, timeframe), nb_reads)| {
+                    fastx_output(
+                        output.as_deref(),
+                        output_type,
+                        &repository,
+                        &args,
+                        &read_groups,
+                        timeframe,
+                        *nb_reads,
+                    )
+                })
+                .collect::<Result<Vec<_>>>()?;
+
+            if args.sort_output {
+                let mut sorter = StartTimeSorter::new(args.sort_memory);
+                for (input, (first_read, nb_reads_kept, _)) in inputs.iter().zip(input_memberships)
+                {
+                    Fastx::from_path(input)
+                        .sort_reads_in_timeframe_into(
+                            &membership,
+                            first_read,
+                            nb_reads_kept,
+                            &mut sorter,
+                        )
+                        .with_context(|| {
+                            format!("Failed to extract reads from {}", input.display())
+                        })?;
+                }
+                log_sort(&sorter);
+                sorter
+                    .finish(|window, data| {
+                        for handle in output_handles[window..].iter_mut() {
+                            handle.write_all(data)?;
+                        }
+                        Ok(())
+                    })
+                    .context("Failed to write the sorted reads")?;
+            } else {
+                for (input, (first_read, nb_reads_kept, (offset_kind, offsets, _))) in
+                    inputs.iter().zip(input_memberships)
+                {
+                    let fastx = Fastx::from_path(input);
+                    let extracted = match offset_kind {
+                        OffsetKind::Byte => fastx.extract_indexed_reads_into(
+                            &offsets,
+                            &membership,
+                            first_read,
+                            nb_reads_kept,
+                            &mut output_handles,
+                        ),
+                        _ => Ok(false),
+                    }
+                    .and_then(|extracted| match extracted {
+                        true => Ok(()),
+                        false => fastx.extract_reads_in_timeframe_into(
+                            &membership,
+                            first_read,
+                            nb_reads_kept,
+                            &mut output_handles,
+                        ),
+                    });
+                    extracted.with_context(|| {
+                        format!("Failed to extract reads from {}", input.display())
+                    })?;
+                }
+            }
+            for handle in output_handles {
+                handle.finish().context("Failed to write the output")?;
+            }
+        }
+        FileFormat::Alignment => {
+            let mut out_header = merged_header(&inputs, &repository, args.threads)?;
+            if args.sort_output {
+                set_start_time_sort_order(&mut out_header);
+            }
+            let index = bam_index_format(&args, output_type, &out_header, inputs.len());
+            let mut writers = output_paths
+                .iter()
+                .map(|output| {
+                    record_writer(output.as_deref(), output_type, &repository, &args, index)
+                })
+                .collect::<Result<Vec<_>>>()?;
+
+            for ((writer, timeframe), nb_reads) in writers
+                .iter_mut()
+                .zip(&timeframes)
+                .zip(&nb_reads_per_window)
+            {
+                let mut header = out_header.clone();
+                add_provenance(&mut header, timeframe, *nb_reads);
+                writer.write_header(&header)?;
+            }
+            let mut sorter = args
+                .sort_output
+                .then(|| StartTimeSorter::new(args.sort_memory));
+            for (input, (first_read, nb_reads_kept, (offset_kind, offsets, secondary))) in
+                inputs.iter().zip(input_memberships)
+            {
+                let reads = secondary.record_reads(first_read);
+                if sorter.is_none() && offset_kind == OffsetKind::Virtual {
+                    extract_indexed_bam_records_into(
+                        input,
+                        &offsets,
+                        &membership,
+                        reads,
+                        nb_reads_kept,
+                        &mut writers,
+                    )
+                    .with_context(|| format!("Failed to extract reads from {}", input.display()))?;
+                    continue;
+                }
+                let (mut reader, header) =
+                    open_alignment(open_file(input)?, &repository, args.threads)?;
+                match &mut sorter {
+                    Some(sorter) => reader.sort_reads_in_timeframe_into(
+                        &header,
+                        &read_times,
+                        &membership,
+                        reads,
+                        nb_reads_kept,
+                        sorter,
+                    ),
+                    None => reader.extract_reads_in_timeframe_into(
+                        &header,
+                        &membership,
+                        reads,
+                        nb_reads_kept,
+                        &mut writers,
+                    ),
                 }
+                .with_context(|| format!("Failed to extract reads from {}", input.display()))?;
+            }
+            if let Some(sorter) = sorter {
+                log_sort(&sorter);
+                write_sorted_records(sorter, &out_header, &mut writers)
+                    .context("Failed to write the sorted reads")?;
+            }
+            for (writer, output) in writers.iter_mut().zip(&output_paths) {
+                writer.finish(&out_header)?;
+                log_bam_index(output.as_deref(), index);
+            }
+        }
+    };
+
+    if args.write_index.is_some() && output_type == FileFormat::Fastx {
+        for output in output_paths.iter().flatten() {
+            write_output_indices(output)?;
+        }
+    }
+
+    if args.windows.is_empty() {
+        info!("Done! Kept {} reads", nb_reads_to_keep);
+    } else {
+        for (name, (path, nb_reads)) in window_names
+            .iter()
+            .zip(output_paths.iter().zip(nb_reads_per_window))
+        {
+            // all window outputs have a path as --output is required with --windows
+            info!(
+                "Kept {} reads for window {} in {}",
+                nb_reads,
+                name,
+                path.as_ref().unwrap().display()
+            );
+        }
+        info!("Done!");
+    }
+
+    Ok(())
+}
+
+/// Log how the reads are being sorted
+fn log_sort(sorter: &StartTimeSorter) {
+    match sorter.nb_runs() {
+        0 => info!("Sorting reads by start time in memory..."),
+        n => info!("Merging {} sorted runs of reads from temporary files...", n),
+    }
+}
+
+/// Create the fasta/fastq writer for an output path, or stdout if there is no path
+fn fastx_writer(output: Option<&Path>, args: &Cli) -> Result<Box<dyn Write>> {
+    let handle = match output {
+        None => match args.output_type {
+            None => Box::new(stdout()),
+            Some(OutputCompression::Bgzf) => {
+                bgzf_writer(stdout(), args.compress_level, args.threads)
+            }
+            Some(OutputCompression::Niffler(fmt)) => {
+                niffler::basic::get_writer(Box::new(stdout()), fmt, args.compress_level)?
             }
         },
+        Some(p) => {
+            let out_fastx = Fastx::from_path(p);
+            out_fastx
+                .create(args.compress_level, args.output_type, args.threads)
+                .context("Failed to create the output file")?
+        }
+    };
+    Ok(handle)
+}
+
+/// Create the output of fasta/fastq input for an output path, or stdout if there is no path. The
+/// reads are written as unaligned records if the output is SAM/BAM/CRAM, with `read_groups` in its
+/// header, and `timeframe` and the number of reads kept in its @PG and @CO lines
+fn fastx_output(
+    output: Option<&Path>,
+    output_type: FileFormat,
+    repository: &fasta::Repository,
+    args: &Cli,
+    read_groups: &ReadGroups,
+    timeframe: &str,
+    nb_reads_kept: usize,
+) -> Result<FastxOutput> {
+    let handle = match output_type {
+        FileFormat::Fastx => FastxOutput::Fastx(fastx_writer(output, args)?),
+        FileFormat::Alignment => {
+            let mut header = unaligned_header(read_groups);
+            if args.sort_output {
+                set_start_time_sort_order(&mut header);
+            }
+            let writer = alignment_writer(output, repository, args.threads)?;
+            FastxOutput::Unaligned(Box::new(UnalignedRecordWriter::new(
+                writer,
+                header,
+                timeframe,
+                nb_reads_kept,
+            )?))
+        }
+    };
+    Ok(handle)
+}
+
+/// Write the indices of a fasta/fastq output next to it
+fn write_output_indices(output: &Path) -> Result<()> {
+    let written =
+        write_indices(output).with_context(|| format!("Failed to index {}", output.display()))?;
+    for path in written {
+        info!("Wrote index {}", path.display());
+    }
+    Ok(())
+}
+
+/// Create the SAM/BAM/CRAM writer for an output path, or stdout if there is no path
+fn alignment_writer(
+    output: Option<&Path>,
+    repository: &fasta::Repository,
+    threads: NonZeroUsize,
+) -> Result<Writer> {
+    let writer = match output {
+        None => alignment_writer_builder(repository).build_from_writer(Box::new(stdout()))?,
+        Some(p) => alignment_writer_to_path(p, repository, threads)?,
+    };
+    Ok(writer)
+}
+
+/// Whether a path has a BAM extension.
+fn is_bam_path(path: &Path) -> bool {
+    path.extension().map_or(false, |ext| ext == "bam")
+}
+
+/// The index to write next to a BAM output with --write-index. Only coordinate-sorted output can be
+/// indexed, and several inputs are written one after the other.
+fn bam_index_format(
+    args: &Cli,
+    output_type: FileFormat,
+    header: &sam::Header,
+    nb_inputs: usize,
+) -> Option<BamIndexFormat> {
+    let format = args
+        .write_index
+        .filter(|_| output_type == FileFormat::Alignment)?;
+    if nb_inputs > 1 {
+        warn!("The records of several inputs are written one input after the other, so the output is not indexed");
+        return None;
+    }
+    if !is_coordinate_sorted(header) {
+        warn!("The output is not coordinate-sorted (@HD SO:coordinate), so it is not indexed");
+        return None;
+    }
+    Some(format)
+}
+
+/// Log the index written next to a BAM output, if any.
+fn log_bam_index(output: Option<&Path>, index: Option<BamIndexFormat>) {
+    if let (Some(output), Some(format)) = (output, index) {
+        info!("Wrote index {}", format.index_path(output).display());
+    }
+}
+
+/// Create the writer of alignment records for an output path, or stdout if there is no path. Fasta
+/// is written if the output has a fasta extension, and fastq otherwise. A BAM output is indexed as
+/// it is written if `index` is set.
+fn record_writer(
+    output: Option<&Path>,
+    output_type: FileFormat,
+    repository: &fasta::Repository,
+    args: &Cli,
+    index: Option<BamIndexFormat>,
+) -> Result<RecordWriter> {
+    let writer = match (output_type, output, index) {
+        (FileFormat::Alignment, Some(output), Some(format)) => RecordWriter::IndexedBam(Box::new(
+            IndexedBamWriter::create(output, format)
+                .with_context(|| format!("Failed to create {}", output.display()))?,
+        )),
+        (FileFormat::Alignment, _, _) => alignment_writer(output, repository, args.threads)?.into(),
+        (FileFormat::Fastx, _, _) => RecordWriter::Fastx(FastxRecordWriter::new(
+            fastx_writer(output, args)?,
+            output.map_or(false, is_fasta_path),
+        )),
     };
+    Ok(writer)
+}
 
-    let latest = match args.latest {
-        None => last_timestamp.to_owned(),
-        Some(s) => match PrimitiveDateTime::parse(&s, &Rfc3339) {
-            Ok(t) => t,
+/// Describe the timeframe of the reads kept, for the @PG line of alignment outputs - e.g.
+/// `earliest=2022-12-12T12:00:00Z latest=2022-12-12T14:00:00Z`. Open bounds are left out, and
+/// ranges are separated by `; `
+fn describe_timeframe(
+    ranges: &[(OffsetDateTime, OffsetDateTime)],
+    invert: bool,
+    offset: UtcOffset,
+) -> Result<String, time::error::Format> {
+    let mut descriptions = vec![];
+    for (start, end) in ranges {
+        let mut bounds = vec![];
+        if *start != PrimitiveDateTime::MIN.assume_utc() {
+            bounds.push(format!("earliest={}", format_timestamp(start, offset)?));
+        }
+        if *end != PrimitiveDateTime::MAX.assume_utc() {
+            bounds.push(format!("latest={}", format_timestamp(end, offset)?));
+        }
+        descriptions.push(bounds.join(" "));
+    }
+    let description = descriptions.join("; ");
+    Ok(match invert {
+        true => format!("excluding {}", description),
+        false => description,
+    })
+}
+
+/// Build the time filter if reads can be extracted in a single pass - i.e. without first gathering
+/// all start times. This is possible when the timeframe does not depend on the first or last start
+/// time, such as when --from and --to are both timestamps, or when the input is --sorted and the
+/// timeframe only depends on the first start time.
+///
+/// `inputs` is `None` if the input is a stream, in which case the first start time cannot be peeked,
+/// and `stream_header` is the header of an alignment stream. Returns `None` if a first pass over the
+/// input is needed.
+fn single_pass_filter(
+    args: &Cli,
+    inputs: Option<&[PathBuf]>,
+    stream_header: Option<&sam::Header>,
+    input_format: FileFormat,
+    output_type: FileFormat,
+    repository: &fasta::Repository,
+) -> Result<Option<TimeFilter>> {
+    if args.show
+        || args.sort_output
+        || !args.windows.is_empty()
+        || args.until_bases.is_some()
+        || args.until_depth.is_some()
+    {
+        return Ok(None);
+    }
+    // the header of alignment output has the number of reads kept - and, for unaligned output,
+    // the read groups of all reads - which are gathered in a first pass
+    if output_type == FileFormat::Alignment {
+        return Ok(None);
+    }
+
+    // the secondary and supplementary records of a read are only known to be next to its primary
+    // record when the input is grouped by name. Otherwise, the read they belong to is looked up in
+    // a first pass
+    if input_format == FileFormat::Alignment && !args.primary_only {
+        if stream_header.map_or(false, |header| !is_grouped_by_name(header)) {
+            return Ok(None);
+        }
+        for input in inputs.unwrap_or_default() {
+            let (_, header) = open_alignment(open_file(input)?, repository, args.threads)?;
+            if !is_grouped_by_name(&header) {
+                return Ok(None);
+            }
+        }
+    }
+
+    let bounds = if args.ranges.is_empty() {
+        vec![(args.earliest.as_deref(), args.latest.as_deref())]
+    } else {
+        args.ranges
+            .iter()
+            .map(|r| {
+                // clap has already validated the range format
+                let (from, to) = r.split_once("..").unwrap();
+                (
+                    Some(from).filter(|s| !s.is_empty()),
+                    Some(to).filter(|s| !s.is_empty()),
+                )
+            })
+            .collect_vec()
+    };
+    let is_duration = |s: &Option<&str>| s.map_or(false, |s| parse_timestamp(s).is_err());
+    let needs_first_timestamp = bounds
+        .iter()
+        .any(|(from, to)| is_duration(from) || is_duration(to));
+    let first_timestamp = match (needs_first_timestamp, args.sorted, inputs) {
+        (false, _, _) => None,
+        (true, false, _) | (true, true, None) => return Ok(None),
+        // each input is sorted, so the first start time is the earliest of their first reads
+        (true, true, Some(inputs)) => {
+            let mut first_timestamps = vec![];
+            for input in inputs {
+                let first = match input_format {
+                    FileFormat::Fastx => Fastx::from_path(input).first_start_time()?,
+                    FileFormat::Alignment => {
+                        let (mut reader, header) =
+                            open_alignment(open_file(input)?, repository, args.threads)?;
+                        reader.first_start_time(&header)?
+                    }
+                };
+                first_timestamps.extend(first);
+            }
+            first_timestamps.into_iter().min()
+        }
+    };
+
+    // reads can only start after the first start time and end before the end of the run, so an
+    // open bound can be treated as unbounded
+    let resolve = |s: Option<&str>, unbounded: OffsetDateTime| match s {
+        None => Some(unbounded),
+        Some(s) => match parse_timestamp(s) {
+            Ok(t) => Some(t),
             Err(_) => {
-                let duration = Duration::from_str(&s)?;
-                if duration.is_negative() {
-                    last_timestamp
-                        .checked_add(duration)
-                        .context("Subtracting --to from the last timestamp caused an overflow")?
-                } else {
-                    first_timestamp
-                        .checked_add(duration)
-                        .context("Adding --to to the first timestamp caused an overflow")?
-                }
+                // the last start time is needed for negative durations
+                let duration = Duration::from_str(s).ok().filter(|d| !d.is_negative())?;
+                first_timestamp?.checked_add(duration)
             }
         },
     };
+    let mut ranges = vec![];
+    for (from, to) in bounds {
+        let start = resolve(from, PrimitiveDateTime::MIN.assume_utc());
+        let end = resolve(to, PrimitiveDateTime::MAX.assume_utc());
+        match (start, end) {
+            (Some(start), Some(end)) if end < start => {
+                return Err(anyhow!(
+                    "The earliest timestamp is after the latest timestamp"
+                ))
+            }
+            (Some(start), Some(end)) => ranges.push((start, end)),
+            _ => return Ok(None),
+        }
+    }
+
+    Ok(Some(TimeFilter {
+        ranges: TimeRanges::new(ranges),
+        overlap: args.overlap,
+        invert: args.invert,
+    }))
+}
 
-    if latest < earliest {
+/// Print the times at which each multiple of 10x depth was reached, up to the depth of the whole
+/// input
+fn print_depth_table(
+    read_times: &ReadTimes,
+    earliest: &OffsetDateTime,
+    genome_size: u64,
+    offset: UtcOffset,
+) -> Result<()> {
+    const DEPTH_STEP: u64 = 10;
+    let total_bases = read_times.nb_bases_since(earliest);
+    let max_depth = total_bases / genome_size;
+    let depths = (1..=max_depth / DEPTH_STEP)
+        .map(|i| i * DEPTH_STEP)
+        .collect_vec();
+    let targets = depths.iter().map(|d| d * genome_size).collect_vec();
+    let times = read_times.yield_times(earliest, &targets);
+
+    println!("Depth\tBases\tTime\tElapsed");
+    for ((depth, target), time) in depths.iter().zip(&targets).zip(times) {
+        // all targets are at most the total number of bases, so are always reached
+        let Some(time) = time else { break };
+        println!(
+            "{}x\t{}\t{}\t{}",
+            depth,
+            target,
+            format_timestamp(&time, offset)?,
+            time - *earliest
+        );
+    }
+    Ok(())
+}
+
+/// Resolve a FROM..TO range, where FROM and TO use the --from/--to format. Either side can be left
+/// empty to use the first timestamp or `run_end` respectively
+fn resolve_range(
+    s: &str,
+    first_timestamp: &OffsetDateTime,
+    last_timestamp: &OffsetDateTime,
+    run_end: &OffsetDateTime,
+) -> Result<(OffsetDateTime, OffsetDateTime)> {
+    // clap has already validated the range format
+    let (from, to) = s.split_once("..").unwrap();
+    let start = match from {
+        "" => *first_timestamp,
+        _ => resolve_time(from, first_timestamp, last_timestamp, "--range")?,
+    };
+    let end = match to {
+        "" => *run_end,
+        _ => resolve_time(to, first_timestamp, last_timestamp, "--range")?,
+    };
+    Ok((start, end))
+}
+
+/// Resolve a --from/--to style argument into a timestamp. `s` is either an RFC3339 timestamp or a
+/// duration, which is added to the first timestamp, or subtracted from the last timestamp if it is
+/// negative.
+fn resolve_time(
+    s: &str,
+    first_timestamp: &OffsetDateTime,
+    last_timestamp: &OffsetDateTime,
+    opt: &str,
+) -> Result<OffsetDateTime> {
+    match parse_timestamp(s) {
+        Ok(t) => Ok(t),
+        Err(_) => {
+            let duration = Duration::from_str(s)?;
+            if duration.is_negative() {
+                last_timestamp.checked_add(duration).with_context(|| {
+                    format!("Subtracting {opt} from the last timestamp caused an overflow")
+                })
+            } else {
+                first_timestamp.checked_add(duration).with_context(|| {
+                    format!("Adding {opt} to the first timestamp caused an overflow")
+                })
+            }
+        }
+    }
+}
+
+/// The input file name without its format and compression extensions - e.g. reads.fq.gz -> reads.
+/// Stdin is named "stdin"
+fn input_stem(path: &Path) -> String {
+    if path == Path::new("-") {
+        return "stdin".to_string();
+    }
+    let mut name = strip_compression_extension(Path::new(path.file_name().unwrap_or_default()));
+    if format_from_extension(&name).is_some() {
+        name.set_extension("");
+    }
+    name.to_string_lossy().to_string()
+}
+
+/// Determine the format of the input from its content, unless it is given by --input-format.
+///
+/// Stdin and named pipes can only be read once, so they are returned as a stream that still yields
+/// the bytes used to detect the format.
+fn open_input(
+    input: &Path,
+    input_format: Option<FileFormat>,
+) -> Result<(Option<Box<dyn Read + Send>>, FileFormat)> {
+    let (stream, detected) = if is_stream(input) {
+        let (stream, format) = sniff_stream(input).context("Failed to read the input")?;
+        (Some(stream), format)
+    } else {
+        let format = detect_file_format(input).context("Failed to read the input")?;
+        (None, format)
+    };
+    let format = input_format
+        .or(detected)
+        .context("Could not detect the format of the input. Use --input-format to set it")?;
+    Ok((stream, format))
+}
+
+/// Expand directories in the inputs into the files under them - searched recursively - whose name
+/// matches `glob`, or that have a recognised extension if there is no `glob`. Files in a
+/// directory are sorted by path.
+fn expand_inputs(inputs: &[PathBuf], glob: Option<&str>) -> Result<Vec<PathBuf>> {
+    let glob = glob.map(WildMatch::new);
+    let is_match = |path: &Path| match &glob {
+        Some(glob) => path
+            .file_name()
+            .map_or(false, |name| glob.matches(&name.to_string_lossy())),
+        None => format_from_extension(path).is_some(),
+    };
+
+    let mut expanded = vec![];
+    for input in inputs {
+        if input.is_dir() {
+            let mut files = vec![];
+            let mut dirs = vec![input.to_path_buf()];
+            while let Some(dir) = dirs.pop() {
+                for entry in std::fs::read_dir(&dir)
+                    .with_context(|| format!("Failed to read directory {}", dir.display()))?
+                {
+                    let path = entry?.path();
+                    if path.is_dir() {
+                        dirs.push(path);
+                    } else if is_match(&path) && !is_index_path(&path) {
+                        files.push(path);
+                    }
+                }
+            }
+            if files.is_empty() {
+                return Err(anyhow!(
+                    "Did not find any input files in directory {}",
+                    input.display()
+                ));
+            }
+            files.sort();
+            expanded.extend(files);
+        } else {
+            expanded.push(input.to_path_buf());
+        }
+    }
+    if expanded.len() > 1 && expanded.iter().any(|input| is_stream(input)) {
+        return Err(anyhow!(
+            "stdin and named pipes cannot be combined with other inputs"
+        ));
+    }
+    Ok(expanded)
+}
+
+/// Determine the format of the inputs, which must all be the same. See [`open_input`] - a stream
+/// can only be given as the sole input.
+fn open_inputs(
+    inputs: &[PathBuf],
+    input_format: Option<FileFormat>,
+) -> Result<(Option<Box<dyn Read + Send>>, FileFormat)> {
+    let (stream, format) = open_input(&inputs[0], input_format)
+        .with_context(|| format!("Failed to open {}", inputs[0].display()))?;
+    for input in &inputs[1..] {
+        let (_, other) = open_input(input, input_format)
+            .with_context(|| format!("Failed to open {}", input.display()))?;
+        if other != format {
+            return Err(anyhow!(
+                "{} is not the same format as {}",
+                input.display(),
+                inputs[0].display()
+            ));
+        }
+    }
+    Ok((stream, format))
+}
+
+/// Read the start time index of `input`, or build it - and save it next to the input, if possible -
+/// if there is no index or the input has changed since it was written.
+fn load_or_build_index(
+    input: &Path,
+    format: FileFormat,
+    repository: &fasta::Repository,
+    threads: NonZeroUsize,
+) -> Result<TimeIndex> {
+    let index_path = TimeIndex::path_for(input);
+    match TimeIndex::load(input) {
+        Ok(Some(index)) => return Ok(index),
+        Ok(None) => {}
+        Err(e) => warn!("Ignoring invalid index {}: {}", index_path.display(), e),
+    }
+    let stamp = FileStamp::of(input)
+        .with_context(|| format!("Failed to read the metadata of {}", input.display()))?;
+    let index = TimeIndex::build(input, format, repository, threads)
+        .with_context(|| format!("Failed to extract start times from {}", input.display()))?;
+    // e.g. the input is in a read-only or shared directory, or is still being written
+    if let Err(e) = index.write(input, stamp) {
+        debug!("Not saving index {}: {}", index_path.display(), e);
+    }
+    Ok(index)
+}
+
+/// Warn about the secondary and supplementary records of an input that have no primary record,
+/// as they are never written.
+fn log_orphans(secondary: &SecondaryRecords, input: &Path) {
+    let nb_orphans = secondary.nb_orphans();
+    if nb_orphans > 0 {
+        warn!(
+            "{} secondary/supplementary records in {} have no primary record, so they are skipped",
+            nb_orphans,
+            input.display()
+        );
+    }
+}
+
+/// Open a file for reading
+fn open_file(path: &Path) -> Result<Box<dyn Read + Send>> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    Ok(Box::new(file))
+}
+
+/// The header for the output of alignment inputs - the header of the first input with the read
+/// groups, programs, and comments of the others added.
+fn merged_header(
+    inputs: &[PathBuf],
+    repository: &fasta::Repository,
+    threads: NonZeroUsize,
+) -> Result<sam::Header> {
+    let (_, mut header) = open_alignment(open_file(&inputs[0])?, repository, threads)?;
+    for input in &inputs[1..] {
+        let (_, other) = open_alignment(open_file(input)?, repository, threads)?;
+        merge_header(&mut header, &other)
+            .with_context(|| format!("Failed to merge the header of {}", input.display()))?;
+    }
+    Ok(header)
+}
+
+/// Determine the format of an output file from its extension
+fn output_format(path: &Path) -> Result<FileFormat> {
+    format_from_extension(path)
+        .with_context(|| format!("Unrecognized file extension for output file {:?}", path))
+}
+
+/// Fill the {stem} and the given placeholder of an output file name template
+fn fill_template(template: &str, stem: &str, placeholder: &str, value: &str) -> PathBuf {
+    PathBuf::from(template.replace("{stem}", stem).replace(placeholder, value))
+}
+
+/// Write a start time index next to each input
+fn index(args: &Index) -> Result<()> {
+    let inputs = expand_inputs(&args.input, args.glob.as_deref())?;
+    if inputs.iter().any(|input| is_stream(input)) {
+        return Err(anyhow!("Cannot index stdin or a named pipe"));
+    }
+    let repository =
+        reference_repository(args.reference.as_deref()).context("Failed to read the reference")?;
+
+    for input in &inputs {
+        let (_, format) = open_input(input, args.input_format)
+            .with_context(|| format!("Failed to open {}", input.display()))?;
+        let stamp = FileStamp::of(input)
+            .with_context(|| format!("Failed to read the metadata of {}", input.display()))?;
+        let index = TimeIndex::build(input, format, &repository, args.threads)
+            .with_context(|| format!("Failed to extract start times from {}", input.display()))?;
+        let index_path = TimeIndex::path_for(input);
+        index
+            .write(input, stamp)
+            .with_context(|| format!("Failed to write index {}", index_path.display()))?;
+        info!(
+            "Indexed {} reads in {}",
+            index.read_times.len(),
+            index_path.display()
+        );
+    }
+    Ok(())
+}
+
+/// Split the input into consecutive time bins, writing each bin to its own file
+fn split(args: &Split) -> Result<()> {
+    // splitting always needs two passes, so streams are copied to a temporary file
+    let (stream, input_format) = open_input(&args.input, args.input_format)?;
+    let repository =
+        reference_repository(args.reference.as_deref()).context("Failed to read the reference")?;
+    let spilled = match stream {
+        None => None,
+        Some(stream) => {
+            info!("Copying the input stream to a temporary file...");
+            Some(spill_to_tempfile(stream).context("Failed to copy the input stream")?)
+        }
+    };
+    let input = spilled.as_ref().map_or(args.input.as_path(), |f| f.path());
+    let output_type = output_format(&args.output)?;
+    let template = args.output.to_string_lossy();
+    if !template.contains("{bin}") {
         return Err(anyhow!(
-            "The earliest timestamp is after the latest timestamp"
+            "The --output template must contain {{bin}} when splitting"
         ));
     }
+    let stem = input_stem(&args.input);
+
+    info!("Extracting read start times...");
+    let input_fastx = Fastx::from_path(input);
+    let (read_times, mut secondary, read_groups) = if args.no_index || spilled.is_some() {
+        match input_format {
+            FileFormat::Fastx => {
+                input_fastx
+                    .read_times(args.threads)
+                    .map(|(read_times, read_groups)| {
+                        (read_times, SecondaryRecords::default(), read_groups)
+                    })
+            }
+            FileFormat::Alignment => open_alignment(open_file(input)?, &repository, args.threads)
+                .and_then(|(mut reader, header)| reader.read_times(&header))
+                .map(|(read_times, secondary)| (read_times, secondary, ReadGroups::default())),
+        }
+        .context("Failed to extract start times")?
+    } else {
+        let index = load_or_build_index(input, input_format, &repository, args.threads)?;
+        (index.read_times, index.secondary, index.read_groups)
+    };
+    log_orphans(&secondary, &args.input);
+    if args.primary_only {
+        secondary.detach();
+    }
+    let (first_timestamp, _) = read_times
+        .start_time_bounds()
+        .context("Did not find any start times in the input")?;
 
+    let origin = if args.wall_clock {
+        align_to_wall_clock(&first_timestamp, &args.every)
+    } else {
+        first_timestamp
+    };
     info!(
-        "Extracting reads with a start time between {} and {}...",
-        earliest, latest
+        "Splitting reads into bins of {} starting at {}...",
+        args.every,
+        format_timestamp(&origin, UtcOffset::UTC)?
     );
-    let (reads_to_keep, nb_reads_to_keep) = valid_indices(&start_times, &earliest, &latest);
 
-    match output_type {
-        FileFormat::Fastx => {
-            let mut output_handle = match &args.output {
-                None => match args.output_type {
-                    None => Box::new(stdout()),
-                    Some(fmt) => {
-                        niffler::basic::get_writer(Box::new(stdout()), fmt, args.compress_level)?
-                    }
-                },
-                Some(p) => {
-                    let out_fastx = Fastx::from_path(p);
-                    out_fastx
-                        .create(args.compress_level, args.output_type)
-                        .context("Failed to create the output file")?
+    // the number of reads in each bin is known before the bins are written, for the @CO line of
+    // alignment output
+    let nb_reads_per_bin = read_times.nb_reads_per_bin(&origin, &args.every);
+    let bin_path = |bin: usize| fill_template(&template, &stem, "{bin}", &bin.to_string());
+    let bin_timeframe = |bin: usize| {
+        let start = origin + args.every * bin as u32;
+        describe_timeframe(&[(start, start + args.every)], false, UtcOffset::UTC).map_err(
+            |source| IOError::CreateError {
+                source: std::io::Error::new(std::io::ErrorKind::Other, source),
+            },
+        )
+    };
+    let nb_reads_written = match input_format {
+        FileFormat::Fastx => input_fastx.split_reads_into_bins(&origin, &args.every, |bin| {
+            let path = bin_path(bin);
+            match output_type {
+                FileFormat::Alignment => {
+                    let timeframe = bin_timeframe(bin)?;
+                    alignment_writer_to_path(&path, &repository, args.threads)
+                        .and_then(|writer| {
+                            UnalignedRecordWriter::new(
+                                writer,
+                                unaligned_header(&read_groups),
+                                &timeframe,
+                                nb_reads_per_bin[bin],
+                            )
+                        })
+                        .map(|writer| FastxOutput::Unaligned(Box::new(writer)))
+                        .map_err(|source| IOError::CreateError { source })
                 }
-            };
-
-            input_fastx.extract_reads_in_timeframe_into(
-                &reads_to_keep,
-                nb_reads_to_keep,
-                &mut output_handle,
-            )?;
-        }
+                FileFormat::Fastx => Fastx::from_path(&path)
+                    .create(args.compress_level, args.output_type, args.threads)
+                    .map(FastxOutput::Fastx),
+            }
+        })?,
         FileFormat::Alignment => {
-            let mut writer = match &args.output {
-                None => noodles_util::alignment::io::writer::Builder::default()
-                    .build_from_writer(Box::new(stdout()))?,
-                Some(p) => {
-                    noodles_util::alignment::io::writer::Builder::default().build_from_path(p)?
-                }
-            };
-
-            let mut bam_reader = noodles_util::alignment::io::reader::Builder::default()
-                .build_from_path(&args.input)?;
-            let header = bam_reader.read_header()?;
-            writer.write_header(&header)?;
-            // need to reopen the bam reader as the header has been read and we need to read it again
-            let mut bam_reader = noodles_util::alignment::io::reader::Builder::default()
-                .build_from_path(&args.input)?;
-            bam_reader.extract_reads_in_timeframe_into(
-                &reads_to_keep,
-                nb_reads_to_keep,
-                &mut writer,
-            )?;
-            writer.finish(&header)?;
+            let (mut bam_reader, header) =
+                open_alignment(open_file(input)?, &repository, args.threads)?;
+            let reads = secondary.record_reads(0);
+            bam_reader.split_reads_into_bins(
+                &header,
+                &read_times,
+                reads,
+                &origin,
+                &args.every,
+                |bin| {
+                    let path = bin_path(bin);
+                    match output_type {
+                        FileFormat::Alignment => {
+                            let mut bin_header = header.clone();
+                            add_provenance(
+                                &mut bin_header,
+                                &bin_timeframe(bin)?,
+                                nb_reads_per_bin[bin],
+                            );
+                            alignment_writer_to_path(&path, &repository, args.threads)
+                                .map(RecordWriter::from)
+                                .and_then(|mut writer| {
+                                    writer.write_header(&bin_header).map(|()| writer)
+                                })
+                                .map_err(|source| IOError::CreateError { source })
+                        }
+                        FileFormat::Fastx => Fastx::from_path(&path)
+                            .create(args.compress_level, args.output_type, args.threads)
+                            .map(|inner| {
+                                RecordWriter::Fastx(FastxRecordWriter::new(
+                                    inner,
+                                    is_fasta_path(&path),
+                                ))
+                            }),
+                    }
+                },
+            )?
         }
     };
 
-    info!("Done! Kept {} reads", nb_reads_to_keep);
+    for (bin, nb_reads) in nb_reads_written.iter().enumerate() {
+        if *nb_reads > 0 {
+            info!("Wrote {} reads to {}", nb_reads, bin_path(bin).display());
+        }
+    }
+    info!("Done!");
 
     Ok(())
 }
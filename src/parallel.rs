@@ -0,0 +1,252 @@
+use crate::io::{fastx_read_times_and_offsets, IOError};
+use crate::unaligned::ReadGroups;
+use needletail::parse_fastx_reader;
+use ontime::ReadTimes;
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, BufReader, Read};
+use std::num::NonZeroUsize;
+use std::sync::mpsc::{channel, sync_channel};
+use std::sync::Mutex;
+use std::thread;
+
+/// The (rough) number of uncompressed bytes given to a worker at a time.
+pub const CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+/// A run of whole records.
+struct Chunk {
+    /// The position of the chunk in the stream, used to put the results back in order.
+    index: usize,
+    /// The byte offset of the chunk in the stream.
+    offset: u64,
+    /// The number of lines before the chunk in the stream.
+    first_line: u64,
+    data: Vec<u8>,
+}
+
+impl Chunk {
+    fn read_times_and_offsets(&self) -> Result<(ReadTimes, ReadGroups, Vec<u64>), IOError> {
+        let mut reader =
+            parse_fastx_reader(&self.data[..]).map_err(|source| IOError::ReadError { source })?;
+        let (read_times, read_groups, mut offsets) =
+            fastx_read_times_and_offsets(reader.as_mut(), self.first_line)?;
+        for offset in offsets.iter_mut() {
+            *offset += self.offset;
+        }
+        Ok((read_times, read_groups, offsets))
+    }
+}
+
+/// The end of the last whole record in `buf` (which starts on a record boundary), or 0 if there
+/// is no whole record yet.
+pub fn last_record_end(buf: &[u8], is_fastq: bool) -> usize {
+    if is_fastq {
+        // fastq records are four lines
+        buf.iter()
+            .enumerate()
+            .filter(|(_, &b)| b == b'\n')
+            .skip(3)
+            .step_by(4)
+            .last()
+            .map_or(0, |(i, _)| i + 1)
+    } else {
+        buf.windows(2)
+            .rposition(|w| w == b"\n>")
+            .map_or(0, |i| i + 1)
+    }
+}
+
+/// Split an uncompressed fasta/fastq stream into chunks of whole records, passing each to `send`.
+fn split_into_chunks<R, F>(
+    mut reader: R,
+    is_fastq: bool,
+    chunk_size: usize,
+    mut send: F,
+) -> io::Result<()>
+where
+    R: Read,
+    F: FnMut(Chunk),
+{
+    let mut buf = Vec::with_capacity(chunk_size);
+    let (mut index, mut offset, mut first_line) = (0, 0, 0);
+    loop {
+        let nb_read = reader
+            .by_ref()
+            .take(chunk_size as u64)
+            .read_to_end(&mut buf)?;
+        let eof = nb_read == 0;
+        let end = if eof {
+            buf.len()
+        } else {
+            last_record_end(&buf, is_fastq)
+        };
+        // a record longer than the chunk size - keep reading until it is whole
+        if end > 0 {
+            let rest = buf.split_off(end);
+            let nb_lines = buf.iter().filter(|&&b| b == b'\n').count() as u64;
+            let data = std::mem::replace(&mut buf, rest);
+            send(Chunk {
+                index,
+                offset,
+                first_line,
+                data,
+            });
+            index += 1;
+            offset += end as u64;
+            first_line += nb_lines;
+        }
+        if eof {
+            return Ok(());
+        }
+    }
+}
+
+/// Returns the start time and sequence length of each read in an uncompressed fasta/fastq
+/// stream and the read groups of the reads, along with the offset of each read. The stream is
+/// split into chunks of whole records that are parsed by `threads` workers, and the results are
+/// put back in stream order.
+pub fn read_times_and_offsets<R: Read + Send>(
+    reader: R,
+    threads: NonZeroUsize,
+    chunk_size: usize,
+) -> Result<(ReadTimes, ReadGroups, Vec<u64>), IOError> {
+    let mut reader = BufReader::new(reader);
+    let is_fastq = match reader.fill_buf() {
+        Ok([b'@', ..]) => true,
+        Ok([b'>', ..]) => false,
+        // leave empty files and errors to the parser
+        _ => {
+            let mut reader =
+                parse_fastx_reader(reader).map_err(|source| IOError::ReadError { source })?;
+            return fastx_read_times_and_offsets(reader.as_mut(), 0);
+        }
+    };
+
+    let (chunk_tx, chunk_rx) = sync_channel::<Chunk>(threads.get());
+    let chunk_rx = Mutex::new(chunk_rx);
+    let (result_tx, result_rx) = channel();
+
+    thread::scope(|scope| {
+        for _ in 0..threads.get() {
+            let result_tx = result_tx.clone();
+            let chunk_rx = &chunk_rx;
+            scope.spawn(move || loop {
+                // the lock is released before the chunk is parsed
+                let chunk = match chunk_rx.lock().unwrap().recv() {
+                    Ok(chunk) => chunk,
+                    Err(_) => break,
+                };
+                let result = chunk.read_times_and_offsets();
+                if result_tx.send((chunk.index, result)).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(result_tx);
+        let splitter = scope.spawn(move || {
+            split_into_chunks(reader, is_fastq, chunk_size, |chunk| {
+                // the workers only stop once the chunks run out
+                let _ = chunk_tx.send(chunk);
+            })
+        });
+
+        // merge the results in chunk order as they come in
+        let mut read_times = ReadTimes::default();
+        let mut read_groups = ReadGroups::default();
+        let mut offsets = vec![];
+        let mut error = None;
+        let mut pending = BTreeMap::new();
+        let mut next = 0;
+        for (index, result) in result_rx {
+            pending.insert(index, result);
+            while let Some(result) = pending.remove(&next) {
+                match result {
+                    Ok((chunk_read_times, chunk_read_groups, chunk_offsets)) if error.is_none() => {
+                        read_times.extend(chunk_read_times);
+                        read_groups.extend(chunk_read_groups);
+                        offsets.extend(chunk_offsets);
+                    }
+                    Err(e) if error.is_none() => error = Some(e),
+                    _ => {}
+                }
+                next += 1;
+            }
+        }
+
+        splitter
+            .join()
+            .expect("the fastx splitting thread panicked")
+            .map_err(|source| IOError::ReadError {
+                source: source.into(),
+            })?;
+        match error {
+            Some(e) => Err(e),
+            None => Ok((read_times, read_groups, offsets)),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fastq(nb_reads: usize) -> String {
+        (0..nb_reads)
+            .map(|i| {
+                let seq = "ACGT".repeat(i % 7 + 1);
+                // a few read groups, whose first reads are in different chunks
+                format!(
+                    "@r{i} start_time=2022-12-12T12:{:02}:00Z runid=run{}\n{seq}\n+\n{}\n",
+                    (i * 13) % 60,
+                    (i / 17) % 5,
+                    "!".repeat(seq.len())
+                )
+            })
+            .collect()
+    }
+
+    fn sequential(content: &str) -> (ReadTimes, ReadGroups, Vec<u64>) {
+        let mut reader = parse_fastx_reader(content.as_bytes()).unwrap();
+        fastx_read_times_and_offsets(reader.as_mut(), 0).unwrap()
+    }
+
+    #[test]
+    fn chunks_end_on_record_boundaries() {
+        let fastq = b"@a\nA\n+\n1\n@b\nAC\n+\n12\n@c\nA";
+        assert_eq!(last_record_end(fastq, true), 20);
+        assert_eq!(last_record_end(&fastq[..8], true), 0);
+
+        let fasta = b">a\nA\nC\n>b\nAC\n>c";
+        assert_eq!(last_record_end(fasta, false), 13);
+        assert_eq!(last_record_end(&fasta[..5], false), 0);
+    }
+
+    #[test]
+    fn parallel_matches_sequential() {
+        let fastq = fastq(100);
+        let fasta = ">r0 start_time=2022-12-12T12:00:00Z\nA\nC\n>r1 start_time=2022-12-12T13:00:00Z\nACG\n>r2 st:Z:2022-12-12T11:00:00Z\r\nAC\r\n".repeat(20);
+
+        for content in [fastq, fasta] {
+            let expected = sequential(&content);
+            for chunk_size in [1, 50, 333, CHUNK_SIZE] {
+                let actual = read_times_and_offsets(
+                    content.as_bytes(),
+                    NonZeroUsize::new(4).unwrap(),
+                    chunk_size,
+                )
+                .unwrap();
+                assert_eq!(actual, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn parallel_reports_line_of_first_missing_time() {
+        let mut content = fastq(10);
+        content.push_str("@no_time\nA\n+\n1\n");
+        content.push_str(&fastq(10));
+        content.push_str("@no_time_either\nA\n+\n1\n");
+
+        let actual = read_times_and_offsets(content.as_bytes(), NonZeroUsize::new(3).unwrap(), 40);
+        assert!(matches!(actual, Err(IOError::MissingTime(41))));
+    }
+}
@@ -0,0 +1,210 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::mem::size_of;
+use time::OffsetDateTime;
+
+/// A record waiting to be written, along with the window it belongs to.
+struct Entry {
+    /// Start time as nanoseconds since the Unix epoch.
+    start: i128,
+    /// The order in which the record was pushed, so records with the same start time keep their
+    /// input order.
+    seq: u64,
+    window: usize,
+    data: Vec<u8>,
+}
+
+impl Entry {
+    fn key(&self) -> (i128, u64) {
+        (self.start, self.seq)
+    }
+
+    fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&self.start.to_le_bytes())?;
+        writer.write_all(&self.seq.to_le_bytes())?;
+        writer.write_all(&(self.window as u64).to_le_bytes())?;
+        writer.write_all(&(self.data.len() as u64).to_le_bytes())?;
+        writer.write_all(&self.data)
+    }
+
+    /// Read the next entry of a run, or `None` at the end of the run.
+    fn read_from<R: Read>(reader: &mut R) -> io::Result<Option<Self>> {
+        let mut start = [0; 16];
+        match reader.read_exact(&mut start) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let mut buf = [0; 8];
+        reader.read_exact(&mut buf)?;
+        let seq = u64::from_le_bytes(buf);
+        reader.read_exact(&mut buf)?;
+        let window = u64::from_le_bytes(buf) as usize;
+        reader.read_exact(&mut buf)?;
+        let mut data = vec![0; u64::from_le_bytes(buf) as usize];
+        reader.read_exact(&mut data)?;
+        Ok(Some(Entry {
+            start: i128::from_le_bytes(start),
+            seq,
+            window,
+            data,
+        }))
+    }
+}
+
+/// Sorts serialised records by start time. Records are sorted in memory until they take up more
+/// than the memory limit, at which point the sorted records are written to a temporary file as a
+/// run. The runs are then k-way merged when the sorter is finished.
+pub struct StartTimeSorter {
+    max_bytes: usize,
+    buffer: Vec<Entry>,
+    buffered_bytes: usize,
+    runs: Vec<File>,
+    nb_records: u64,
+}
+
+impl StartTimeSorter {
+    /// Create a sorter that keeps at most (roughly) `max_bytes` of records in memory.
+    pub fn new(max_bytes: usize) -> Self {
+        StartTimeSorter {
+            max_bytes,
+            buffer: vec![],
+            buffered_bytes: 0,
+            runs: vec![],
+            nb_records: 0,
+        }
+    }
+
+    /// Add a record that starts at `start` and belongs to `window`.
+    pub fn push(&mut self, start: &OffsetDateTime, window: usize, data: Vec<u8>) -> io::Result<()> {
+        self.buffered_bytes += data.len() + size_of::<Entry>();
+        self.buffer.push(Entry {
+            start: start.unix_timestamp_nanos(),
+            seq: self.nb_records,
+            window,
+            data,
+        });
+        self.nb_records += 1;
+        if self.buffered_bytes > self.max_bytes {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    /// The number of temporary files records have been written to.
+    pub fn nb_runs(&self) -> usize {
+        self.runs.len()
+    }
+
+    /// Write the buffered records, sorted, to a new run.
+    fn spill(&mut self) -> io::Result<()> {
+        self.buffer.sort_unstable_by_key(Entry::key);
+        let mut writer = BufWriter::new(tempfile::tempfile()?);
+        for entry in self.buffer.drain(..) {
+            entry.write_to(&mut writer)?;
+        }
+        let mut file = writer.into_inner().map_err(|e| e.into_error())?;
+        file.seek(SeekFrom::Start(0))?;
+        self.runs.push(file);
+        self.buffered_bytes = 0;
+        Ok(())
+    }
+
+    /// Pass each record, in start time order, to `emit` along with its window.
+    pub fn finish<F>(mut self, mut emit: F) -> io::Result<()>
+    where
+        F: FnMut(usize, &[u8]) -> io::Result<()>,
+    {
+        if self.runs.is_empty() {
+            self.buffer.sort_unstable_by_key(Entry::key);
+            for entry in &self.buffer {
+                emit(entry.window, &entry.data)?;
+            }
+            return Ok(());
+        }
+        if !self.buffer.is_empty() {
+            self.spill()?;
+        }
+
+        let mut runs = self
+            .runs
+            .into_iter()
+            .map(BufReader::new)
+            .collect::<Vec<_>>();
+        // the heap holds the key of the next entry of each run, and `heads` the entry itself
+        let mut heads = Vec::with_capacity(runs.len());
+        let mut heap = BinaryHeap::with_capacity(runs.len());
+        for (i, run) in runs.iter_mut().enumerate() {
+            let head = Entry::read_from(run)?;
+            if let Some(entry) = &head {
+                heap.push(Reverse((entry.key(), i)));
+            }
+            heads.push(head);
+        }
+        while let Some(Reverse((_, i))) = heap.pop() {
+            // safe to unwrap as a run is only on the heap when it has a head
+            let entry = heads[i].take().unwrap();
+            emit(entry.window, &entry.data)?;
+            heads[i] = Entry::read_from(&mut runs[i])?;
+            if let Some(next) = &heads[i] {
+                heap.push(Reverse((next.key(), i)));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::datetime;
+
+    fn sorted_records(max_bytes: usize) -> (Vec<(usize, Vec<u8>)>, usize) {
+        let mut sorter = StartTimeSorter::new(max_bytes);
+        let records = [
+            (datetime!(2022-12-12 18:39:09 UTC), 0, b"c".to_vec()),
+            (datetime!(2022-12-12 18:39:07 UTC), 1, b"a".to_vec()),
+            (datetime!(2022-12-12 18:39:10 UTC), 0, b"d".to_vec()),
+            (datetime!(2022-12-12 18:39:07 UTC), 0, b"b".to_vec()),
+            (datetime!(2022-12-12 18:39:08 +01:00), 2, b"first".to_vec()),
+        ];
+        for (start, window, data) in records {
+            sorter.push(&start, window, data).unwrap();
+        }
+        let nb_runs = sorter.nb_runs();
+        let mut emitted = vec![];
+        sorter
+            .finish(|window, data| {
+                emitted.push((window, data.to_vec()));
+                Ok(())
+            })
+            .unwrap();
+        (emitted, nb_runs)
+    }
+
+    #[test]
+    fn sort_in_memory() {
+        let (emitted, nb_runs) = sorted_records(usize::MAX);
+
+        assert_eq!(nb_runs, 0);
+        let expected = vec![
+            (2, b"first".to_vec()),
+            (1, b"a".to_vec()),
+            (0, b"b".to_vec()),
+            (0, b"c".to_vec()),
+            (0, b"d".to_vec()),
+        ];
+        assert_eq!(emitted, expected)
+    }
+
+    #[test]
+    fn sort_with_runs_on_disk() {
+        let (in_memory, _) = sorted_records(usize::MAX);
+        let (emitted, nb_runs) = sorted_records(1);
+
+        assert_eq!(nb_runs, 5);
+        assert_eq!(emitted, in_memory)
+    }
+}
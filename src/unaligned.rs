@@ -0,0 +1,535 @@
+use crate::io::add_provenance;
+use crate::parallel::last_record_end;
+use bstr::BString;
+use needletail::parse_fastx_reader;
+use needletail::parser::SequenceRecord;
+use noodles_sam as sam;
+use noodles_sam::alignment::record::data::field::Tag;
+use noodles_sam::alignment::record::{Flags, MappingQuality};
+use noodles_sam::alignment::record_buf::data::field::Value;
+use noodles_sam::alignment::record_buf::{Data, Name, QualityScores, Sequence};
+use noodles_sam::alignment::RecordBuf;
+use noodles_sam::header::record::value::map::header::Version;
+use noodles_sam::header::record::value::map::{self, read_group, ReadGroup};
+use noodles_sam::header::record::value::Map;
+use noodles_util::alignment::io::Writer;
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+
+/// The size of the text buffered by [`UnalignedRecordWriter`] before its whole records are
+/// converted.
+const BUFFER_SIZE: usize = 1 << 20;
+
+/// The header of unaligned records converted from fasta/fastq, with an @RG line for each of
+/// their `read_groups`.
+pub fn unaligned_header(read_groups: &ReadGroups) -> sam::Header {
+    let mut header = sam::Header::builder()
+        .set_header(Map::<map::Header>::new(Version::new(1, 6)))
+        .build();
+    *header.read_groups_mut() = read_groups.0.clone();
+    header
+}
+
+/// The read groups of fasta/fastq records, in the order they are first seen - see
+/// [`Comment::read_group`]. They are gathered along with the start times, so the header of
+/// unaligned output can be written before its records.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReadGroups(sam::header::ReadGroups);
+
+impl ReadGroups {
+    /// Add the read group of a record, if it has one that was not seen yet.
+    pub fn add(&mut self, rec: &SequenceRecord) {
+        let comment = Comment(comment(rec));
+        // the @RG fields are only gathered for the first record of a read group
+        if let Some(id) = comment.read_group_id() {
+            if !self.0.contains_key(&id) {
+                if let Some((id, read_group)) = comment.read_group() {
+                    self.0.insert(id, read_group);
+                }
+            }
+        }
+    }
+
+    /// Add the read groups of `other` that were not seen yet, after these.
+    pub fn extend(&mut self, other: ReadGroups) {
+        for (id, read_group) in other.0 {
+            self.0.entry(id).or_insert(read_group);
+        }
+    }
+
+    /// Write the read groups as the @RG lines of a SAM header.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let mut header = sam::Header::default();
+        *header.read_groups_mut() = self.0.clone();
+        let mut text = vec![];
+        sam::io::Writer::new(&mut text).write_header(&header)?;
+        writer.write_all(&(text.len() as u64).to_le_bytes())?;
+        writer.write_all(&text)
+    }
+
+    /// Read read groups written by [`ReadGroups::write_to`].
+    pub fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let mut len = [0; 8];
+        reader.read_exact(&mut len)?;
+        let mut text = String::new();
+        reader
+            .take(u64::from_le_bytes(len))
+            .read_to_string(&mut text)?;
+        let header = text
+            .parse::<sam::Header>()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(ReadGroups(header.read_groups().clone()))
+    }
+}
+
+/// Writes fasta/fastq records - written to it as text - as unaligned SAM/BAM/CRAM records. The
+/// header is written when the writer is created, so it must already have the read groups of the
+/// records (see [`unaligned_header`]). It also gets the @PG and @CO lines of [`add_provenance`].
+///
+/// The text is buffered, and converted whole records at a time. The header comment of each
+/// record is turned into tags by [`Comment::data`].
+pub struct UnalignedRecordWriter {
+    buffer: Vec<u8>,
+    writer: Writer,
+    header: sam::Header,
+}
+
+impl UnalignedRecordWriter {
+    /// `timeframe` describes the reads kept, and `nb_reads_kept` is their number.
+    pub fn new(
+        mut writer: Writer,
+        mut header: sam::Header,
+        timeframe: &str,
+        nb_reads_kept: usize,
+    ) -> io::Result<Self> {
+        add_provenance(&mut header, timeframe, nb_reads_kept);
+        writer.write_header(&header)?;
+        Ok(UnalignedRecordWriter {
+            buffer: Vec::with_capacity(BUFFER_SIZE),
+            writer,
+            header,
+        })
+    }
+
+    /// Convert the records in the buffer, up to `end`.
+    fn convert(&mut self, end: usize) -> io::Result<()> {
+        if end == 0 {
+            return Ok(());
+        }
+        let invalid = |e| io::Error::new(io::ErrorKind::InvalidData, e);
+        let mut reader = parse_fastx_reader(&self.buffer[..end]).map_err(invalid)?;
+        while let Some(record) = reader.next() {
+            let record = unaligned_record(&record.map_err(invalid)?);
+            self.writer.write_record(&self.header, &record)?;
+        }
+        drop(reader);
+        self.buffer.drain(..end);
+        Ok(())
+    }
+
+    /// Convert the rest of the records, and finish the output.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.convert(self.buffer.len())?;
+        self.writer.finish(&self.header)
+    }
+}
+
+impl Write for UnalignedRecordWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= BUFFER_SIZE {
+            let end = last_record_end(&self.buffer, self.buffer.first() == Some(&b'@'));
+            self.convert(end)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The part of a fasta/fastq header after the read name.
+fn comment<'a>(rec: &'a SequenceRecord) -> &'a [u8] {
+    let id = rec.id();
+    match id.iter().position(u8::is_ascii_whitespace) {
+        Some(i) => &id[i + 1..],
+        None => &[],
+    }
+}
+
+/// Convert a fasta/fastq record into an unaligned record.
+fn unaligned_record(rec: &SequenceRecord) -> RecordBuf {
+    let id = rec.id();
+    let name = id.split(u8::is_ascii_whitespace).next().unwrap_or_default();
+    let quality_scores: Vec<u8> = rec
+        .qual()
+        .map(|qual| qual.iter().map(|q| q.saturating_sub(33)).collect())
+        .unwrap_or_default();
+    RecordBuf::builder()
+        .set_name(Name::from(name))
+        .set_flags(Flags::UNMAPPED)
+        .set_mapping_quality(MappingQuality::MIN)
+        .set_sequence(Sequence::from(rec.seq().into_owned()))
+        .set_quality_scores(QualityScores::from(quality_scores))
+        .set_data(Comment(comment(rec)).data())
+        .build()
+}
+
+/// The comment of a fasta/fastq header - i.e. the whitespace-separated fields after the read name.
+///
+/// SAM-style `TAG:TYPE:VALUE` fields - as written by Dorado, or by ontime for alignment input - are
+/// kept as they are. The MinKNOW `key=value` fields are mapped to tags: `start_time` to `st`, `ch`
+/// to `ch`, `read` to `rn`, `duration` to `du`, and `runid` and `basecall_model_version_id` to the
+/// `RG` of the run, in the style of Dorado.
+struct Comment<'a>(&'a [u8]);
+
+impl<'a> Comment<'a> {
+    fn tokens(&self) -> impl Iterator<Item = &'a [u8]> {
+        self.0
+            .split(u8::is_ascii_whitespace)
+            .filter(|t| !t.is_empty())
+    }
+
+    /// The value of the first MinKNOW `key=value` field with this key.
+    fn field(&self, key: &str) -> Option<&'a str> {
+        self.tokens().find_map(|token| {
+            let value = token.strip_prefix(key.as_bytes())?.strip_prefix(b"=")?;
+            std::str::from_utf8(value).ok()
+        })
+    }
+
+    /// The value of the `RG` tag, if there is one - the last one wins, as with any tag.
+    fn read_group_tag(&self) -> Option<Value> {
+        self.tokens()
+            .filter(|t| t.starts_with(b"RG:"))
+            .filter_map(parse_sam_field)
+            .last()
+            .map(|(_, value)| value)
+    }
+
+    /// The ID of the read group of the run, from its `runid` and `basecall_model_version_id`.
+    fn run_id(&self) -> Option<String> {
+        let runid = self.field("runid")?;
+        Some(match self.field("basecall_model_version_id") {
+            Some(model) => format!("{}_{}", runid, model),
+            None => runid.to_string(),
+        })
+    }
+
+    /// The ID of the read group, without gathering its @RG fields - see [`Comment::read_group`].
+    fn read_group_id(&self) -> Option<BString> {
+        match self.read_group_tag() {
+            Some(Value::String(id)) => Some(id),
+            Some(_) => None,
+            None => self.run_id().map(BString::from),
+        }
+    }
+
+    /// The read group. It has the @RG fields of the run known from the header; an `RG` tag that
+    /// was already there gets a read group with only its ID.
+    fn read_group(&self) -> Option<(BString, Map<ReadGroup>)> {
+        match self.read_group_tag() {
+            Some(Value::String(id)) => return Some((id, Map::<ReadGroup>::default())),
+            Some(_) => return None,
+            None => {}
+        }
+        let id = self.run_id()?;
+        let runid = self.field("runid")?;
+        let description = match self.field("basecall_model_version_id") {
+            Some(model) => format!("basecall_model={} runid={}", model, runid),
+            None => format!("runid={}", runid),
+        };
+        let mut read_group = Map::<ReadGroup>::default();
+        let rg_fields = read_group.other_fields_mut();
+        if let Some(flow_cell) = self.field("flow_cell_id") {
+            rg_fields.insert(read_group::tag::PLATFORM_UNIT, flow_cell.into());
+        }
+        rg_fields.insert(read_group::tag::PLATFORM, "ONT".into());
+        rg_fields.insert(read_group::tag::DESCRIPTION, description.into());
+        if let Some(sample) = self.field("sample_id").or_else(|| self.field("sampleid")) {
+            rg_fields.insert(read_group::tag::LIBRARY, sample.into());
+            rg_fields.insert(read_group::tag::SAMPLE, sample.into());
+        }
+        Some((BString::from(id), read_group))
+    }
+
+    /// The tags of the record.
+    fn data(&self) -> Data {
+        let mut data: Data = self.tokens().filter_map(parse_sam_field).collect();
+        let int = |key: &str| {
+            self.field(key)
+                .and_then(|v| v.parse::<i64>().ok())
+                .and_then(|v| Value::try_from(v).ok())
+        };
+
+        // SAM-style fields take precedence over their MinKNOW equivalent
+        let mut insert = |tag: Tag, value: Option<Value>| {
+            if let (None, Some(value)) = (data.get(&tag), value) {
+                data.insert(tag, value);
+            }
+        };
+        insert(
+            Tag::new(b's', b't'),
+            self.field("start_time").map(Value::from),
+        );
+        insert(Tag::new(b'c', b'h'), int("ch"));
+        insert(Tag::new(b'r', b'n'), int("read"));
+        insert(
+            Tag::new(b'd', b'u'),
+            self.field("duration")
+                .and_then(|v| v.parse::<f32>().ok())
+                .map(Value::from),
+        );
+        insert(
+            Tag::READ_GROUP,
+            self.run_id().map(|id| Value::from(id.as_str())),
+        );
+        data
+    }
+}
+
+/// Parse a field in SAM text format - e.g. `st:Z:2022-12-12T12:00:00Z`, `ch:i:42`, or `ML:B:C,1,2`.
+fn parse_sam_field(token: &[u8]) -> Option<(Tag, Value)> {
+    let [a, b, b':', ty, b':', value @ ..] = token else {
+        return None;
+    };
+    if !a.is_ascii_alphabetic() || !b.is_ascii_alphanumeric() {
+        return None;
+    }
+    let value = std::str::from_utf8(value).ok()?;
+    let value = match ty {
+        b'A' => match value.as_bytes() {
+            [c] => Value::Character(*c),
+            _ => return None,
+        },
+        b'i' => Value::try_from(value.parse::<i64>().ok()?).ok()?,
+        b'f' => Value::Float(value.parse().ok()?),
+        b'Z' => Value::from(value),
+        b'H' => Value::Hex(value.into()),
+        b'B' => parse_array(value)?,
+        _ => return None,
+    };
+    Some((Tag::new(*a, *b), value))
+}
+
+/// Parse the value of a `B` (array) field - e.g. `C,1,2`.
+fn parse_array(value: &str) -> Option<Value> {
+    fn parse<'a, N: FromStr>(values: impl Iterator<Item = &'a str>) -> Option<Vec<N>> {
+        values.map(|v| v.parse().ok()).collect()
+    }
+
+    let mut values = value.split(',');
+    let value = match values.next()? {
+        "c" => Value::from(parse::<i8>(values)?),
+        "C" => Value::from(parse::<u8>(values)?),
+        "s" => Value::from(parse::<i16>(values)?),
+        "S" => Value::from(parse::<u16>(values)?),
+        "i" => Value::from(parse::<i32>(values)?),
+        "I" => Value::from(parse::<u32>(values)?),
+        "f" => Value::from(parse::<f32>(values)?),
+        _ => return None,
+    };
+    Some(value)
+}
+
+/// The output of fasta/fastq input - the records as they are, or as unaligned records.
+pub enum FastxOutput {
+    Fastx(Box<dyn Write>),
+    Unaligned(Box<UnalignedRecordWriter>),
+}
+
+impl FastxOutput {
+    /// Flush the output, converting the rest of the unaligned records if there are any.
+    pub fn finish(self) -> io::Result<()> {
+        match self {
+            FastxOutput::Fastx(mut writer) => writer.flush(),
+            FastxOutput::Unaligned(writer) => writer.finish(),
+        }
+    }
+}
+
+impl Write for FastxOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            FastxOutput::Fastx(writer) => writer.write(buf),
+            FastxOutput::Unaligned(writer) => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            FastxOutput::Fastx(writer) => writer.flush(),
+            FastxOutput::Unaligned(writer) => writer.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use noodles_util::alignment::io::writer;
+
+    fn tags(data: &Data) -> Vec<(String, Value)> {
+        data.iter()
+            .map(|(tag, value)| {
+                (
+                    String::from_utf8_lossy(tag.as_ref()).to_string(),
+                    value.clone(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn minknow_fields_to_tags() {
+        let comment = Comment(b"runid=abc sampleid=s1 read=12 ch=352 start_time=2022-12-12T18:39:27Z flow_cell_id=FAQ123 basecall_model_version_id=dna_r10.4.1_sup duration=1.5");
+
+        assert_eq!(
+            tags(&comment.data()),
+            vec![
+                ("st".to_string(), Value::from("2022-12-12T18:39:27Z")),
+                ("ch".to_string(), Value::from(352i16)),
+                ("rn".to_string(), Value::from(12u8)),
+                ("du".to_string(), Value::Float(1.5)),
+                ("RG".to_string(), Value::from("abc_dna_r10.4.1_sup")),
+            ]
+        );
+        assert_eq!(
+            comment.read_group_id(),
+            Some(BString::from("abc_dna_r10.4.1_sup"))
+        );
+        let (id, read_group) = comment.read_group().unwrap();
+        assert_eq!(id, "abc_dna_r10.4.1_sup");
+        let fields = read_group.other_fields();
+        assert_eq!(
+            fields.get(&read_group::tag::PLATFORM_UNIT).unwrap(),
+            "FAQ123"
+        );
+        assert_eq!(fields.get(&read_group::tag::PLATFORM).unwrap(), "ONT");
+        assert_eq!(
+            fields.get(&read_group::tag::DESCRIPTION).unwrap(),
+            "basecall_model=dna_r10.4.1_sup runid=abc"
+        );
+        assert_eq!(fields.get(&read_group::tag::SAMPLE).unwrap(), "s1");
+    }
+
+    #[test]
+    fn sam_fields_to_tags() {
+        let comment = Comment(b"st:Z:2022-12-12T12:00:00Z\tRG:Z:run1\tML:B:C,200,3\tMM:Z:C+m?,0;\tqs:f:9.5\tstart_time=2000-01-01T00:00:00Z\tnot:a:field\trunid=abc");
+
+        assert_eq!(
+            tags(&comment.data()),
+            vec![
+                ("st".to_string(), Value::from("2022-12-12T12:00:00Z")),
+                ("RG".to_string(), Value::from("run1")),
+                ("ML".to_string(), Value::from(vec![200u8, 3])),
+                ("MM".to_string(), Value::from("C+m?,0;")),
+                ("qs".to_string(), Value::Float(9.5)),
+            ]
+        );
+        assert_eq!(comment.read_group_id(), Some(BString::from("run1")));
+        assert_eq!(
+            comment.read_group(),
+            Some((BString::from("run1"), Map::default()))
+        );
+        assert_eq!(parse_sam_field(b"ch:i:x"), None);
+        assert_eq!(parse_sam_field(b"ML:B:X,1"), None);
+    }
+
+    #[test]
+    fn read_groups_round_trip() {
+        let fastq = b"@r0 runid=abc ch=7 start_time=2022-12-12T12:00:00Z\nA\n+\n!\n@r1 RG:Z:other\nA\n+\n!\n@r2 runid=abc\nA\n+\n!\n@r3\nA\n+\n!\n";
+        let mut read_groups = ReadGroups::default();
+        let mut reader = parse_fastx_reader(&fastq[..]).unwrap();
+        while let Some(record) = reader.next() {
+            read_groups.add(&record.unwrap());
+        }
+        assert_eq!(
+            read_groups.0.keys().collect::<Vec<_>>(),
+            vec![&BString::from("abc"), &BString::from("other")]
+        );
+
+        let mut buf = vec![];
+        read_groups.write_to(&mut buf).unwrap();
+        buf.extend_from_slice(b"rest");
+        let mut reader = &buf[..];
+        assert_eq!(ReadGroups::read_from(&mut reader).unwrap(), read_groups);
+        assert_eq!(reader, b"rest");
+    }
+
+    #[test]
+    fn fastq_to_sam() {
+        let fastq = b"@r0 runid=abc ch=7 start_time=2022-12-12T12:00:00Z\nACGT\n+\n!\"#$\n@r1\tst:Z:2022-12-12T12:00:01Z\tRG:Z:other\nAC\n+\n!!\n";
+        let mut read_groups = ReadGroups::default();
+        let mut reader = parse_fastx_reader(&fastq[..]).unwrap();
+        while let Some(record) = reader.next() {
+            read_groups.add(&record.unwrap());
+        }
+
+        let output = tempfile::NamedTempFile::new().unwrap();
+        let writer = writer::Builder::default()
+            .set_format(noodles_util::alignment::io::Format::Sam)
+            .build_from_writer(output.reopen().unwrap())
+            .unwrap();
+        let mut writer =
+            UnalignedRecordWriter::new(writer, unaligned_header(&read_groups), "earliest=x", 2)
+                .unwrap();
+        // records can be written a part at a time
+        for part in fastq.chunks(7) {
+            writer.write_all(part).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let sam = std::fs::read_to_string(output.path()).unwrap();
+        let (pg, rest): (Vec<_>, Vec<_>) = sam.lines().partition(|l| l.starts_with("@PG"));
+        assert_eq!(pg.len(), 1);
+        assert!(pg[0].starts_with("@PG\tID:ontime\tPN:ontime\tVN:"));
+        assert!(pg[0].ends_with("\tDS:earliest=x"));
+        assert_eq!(
+            rest,
+            vec![
+                "@HD\tVN:1.6",
+                "@RG\tID:abc\tPL:ONT\tDS:runid=abc",
+                "@RG\tID:other",
+                "@CO\tontime: kept 2 reads",
+                "r0\t4\t*\t0\t0\t*\t*\t0\t0\tACGT\t!\"#$\tst:Z:2022-12-12T12:00:00Z\tch:i:7\tRG:Z:abc",
+                "r1\t4\t*\t0\t0\t*\t*\t0\t0\tAC\t!!\tst:Z:2022-12-12T12:00:01Z\tRG:Z:other",
+            ]
+        );
+    }
+
+    #[test]
+    fn records_are_converted_as_they_are_written() {
+        let output = tempfile::NamedTempFile::new().unwrap();
+        let writer = writer::Builder::default()
+            .set_format(noodles_util::alignment::io::Format::Sam)
+            .build_from_writer(output.reopen().unwrap())
+            .unwrap();
+        let nb_records = 2 * BUFFER_SIZE / 40;
+        let mut writer = UnalignedRecordWriter::new(
+            writer,
+            unaligned_header(&ReadGroups::default()),
+            "",
+            nb_records,
+        )
+        .unwrap();
+        for i in 0..nb_records {
+            write!(writer, ">r{i} start_time=2022-12-12T12:00:00Z\nACGT\n").unwrap();
+            if i == nb_records / 2 {
+                // whole records are converted once the buffer is full
+                assert!(writer.buffer.len() < BUFFER_SIZE);
+            }
+        }
+        writer.finish().unwrap();
+
+        let sam = std::fs::read_to_string(output.path()).unwrap();
+        let records = sam
+            .lines()
+            .filter(|l| !l.starts_with('@'))
+            .collect::<Vec<_>>();
+        assert_eq!(records.len(), nb_records);
+        assert!(sam.contains(&format!("@CO\tontime: kept {} reads", nb_records)));
+        assert!(records[nb_records - 1].starts_with(&format!("r{}\t4\t", nb_records - 1)));
+    }
+}
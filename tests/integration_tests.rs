@@ -1,13 +1,36 @@
 use assert_cmd::Command;
 use bstr::ByteSlice;
 use indoc::indoc;
-use std::io::Write;
+use std::io::{Read, Write};
 
 const BIN: &str = "ontime";
 
+/// The ontime command, which does not write start time indices next to the test inputs
+fn ontime() -> Command {
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    cmd.arg("--no-index");
+    cmd
+}
+
+/// The split subcommand, which does not write start time indices next to the test inputs
+fn ontime_split() -> Command {
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    cmd.args(["split", "--no-index"]);
+    cmd
+}
+
+/// The output without the @PG lines of ontime, whose command line differs between runs
+fn without_ontime_programs(output: &[u8]) -> String {
+    output
+        .lines_with_terminator()
+        .filter(|l| !l.starts_with(b"@PG\tID:ontime"))
+        .map(|l| l.to_str().unwrap())
+        .collect()
+}
+
 #[test]
 fn input_file_does_not_exist() -> Result<(), Box<dyn std::error::Error>> {
-    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    let mut cmd = ontime();
     let err_msg = cmd.arg("nonexistent.fa").unwrap_err().to_string();
 
     assert!(err_msg.contains("does not exist"));
@@ -20,7 +43,7 @@ fn trying_to_create_output_in_nonexistent_dir() -> Result<(), Box<dyn std::error
     let text = ">s0 start_time=2022-12-12T18:00:00Z\nACGT\n";
     let mut file = tempfile::Builder::new().suffix(".fa").tempfile().unwrap();
     file.write_all(text.as_bytes()).unwrap();
-    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    let mut cmd = ontime();
     let p = "foo/bar/aln.fa";
 
     let err_msg = cmd
@@ -46,7 +69,7 @@ fn input_has_no_start_times() -> Result<(), Box<dyn std::error::Error>> {
     "};
     let mut file = tempfile::Builder::new().suffix(".fq").tempfile().unwrap();
     file.write_all(text).unwrap();
-    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    let mut cmd = ontime();
     let err_msg = cmd.args([file.path()]).unwrap_err().to_string();
 
     assert!(err_msg.contains("Missing start_time in fastq record start at line 1"));
@@ -67,7 +90,7 @@ fn input_has_one_read_with_no_start_time() -> Result<(), Box<dyn std::error::Err
     "};
     let mut file = tempfile::Builder::new().suffix(".fq").tempfile().unwrap();
     file.write_all(text).unwrap();
-    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    let mut cmd = ontime();
     let err_msg = cmd.args([file.path()]).unwrap_err().to_string();
 
     assert!(err_msg.contains("Missing start_time in fastq record start at line 5"));
@@ -88,7 +111,7 @@ fn input_has_one_read_with_no_valid_start_time() -> Result<(), Box<dyn std::erro
     "};
     let mut file = tempfile::Builder::new().suffix(".fq").tempfile().unwrap();
     file.write_all(text).unwrap();
-    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    let mut cmd = ontime();
     let err_msg = cmd.args([file.path()]).unwrap_err().to_string();
 
     assert!(err_msg.contains("Missing start_time in fastq record start at line 5"));
@@ -109,7 +132,7 @@ fn no_from_and_to_gets_all_reads() -> Result<(), Box<dyn std::error::Error>> {
     "};
     let mut file = tempfile::Builder::new().suffix(".fq").tempfile().unwrap();
     file.write_all(text).unwrap();
-    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    let mut cmd = ontime();
     let output = cmd.args([file.path()]).unwrap().stdout;
     let expected = text;
 
@@ -131,7 +154,7 @@ fn timeframe_excludes_all_times() -> Result<(), Box<dyn std::error::Error>> {
     "};
     let mut file = tempfile::Builder::new().suffix(".fq").tempfile().unwrap();
     file.write_all(text).unwrap();
-    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    let mut cmd = ontime();
     let output = cmd
         .args(["-f", "400h", "-t", "500h", file.path().to_str().unwrap()])
         .unwrap()
@@ -155,7 +178,7 @@ fn timeframe_includes_only_earliest() -> Result<(), Box<dyn std::error::Error>>
     "};
     let mut file = tempfile::Builder::new().suffix(".fq").tempfile().unwrap();
     file.write_all(text).unwrap();
-    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    let mut cmd = ontime();
     let output = cmd
         .args(["-t", "1m", file.path().to_str().unwrap()])
         .unwrap()
@@ -185,7 +208,7 @@ fn timeframe_includes_only_latest() -> Result<(), Box<dyn std::error::Error>> {
     "};
     let mut file = tempfile::Builder::new().suffix(".fq").tempfile().unwrap();
     file.write_all(text).unwrap();
-    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    let mut cmd = ontime();
     let output = cmd
         .args(["-f", "1m", file.path().to_str().unwrap()])
         .unwrap()
@@ -219,7 +242,7 @@ fn timeframe_excludes_earliest_and_latest() -> Result<(), Box<dyn std::error::Er
     "};
     let mut file = tempfile::Builder::new().suffix(".fq").tempfile().unwrap();
     file.write_all(text).unwrap();
-    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    let mut cmd = ontime();
     let output = cmd
         .args(["-f", "1m", "-t", "-2min", file.path().to_str().unwrap()])
         .unwrap()
@@ -254,7 +277,7 @@ fn timeframe_excludes_earliest_and_latest_using_timestamp() -> Result<(), Box<dy
     "};
     let mut file = tempfile::Builder::new().suffix(".fq").tempfile().unwrap();
     file.write_all(text).unwrap();
-    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    let mut cmd = ontime();
     let output = cmd
         .args([
             "-f",
@@ -290,7 +313,7 @@ fn earliest_is_after_latest() -> Result<(), Box<dyn std::error::Error>> {
     "};
     let mut file = tempfile::Builder::new().suffix(".fq").tempfile().unwrap();
     file.write_all(text).unwrap();
-    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    let mut cmd = ontime();
     let output = cmd
         .args(["-f", "1w", file.path().to_str().unwrap()])
         .unwrap_err()
@@ -314,7 +337,7 @@ fn latest_is_before_earliest() -> Result<(), Box<dyn std::error::Error>> {
     "};
     let mut file = tempfile::Builder::new().suffix(".fq").tempfile().unwrap();
     file.write_all(text).unwrap();
-    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    let mut cmd = ontime();
     let output = cmd
         .args(["-t", "-1w", file.path().to_str().unwrap()])
         .unwrap_err()
@@ -338,7 +361,7 @@ fn output_is_gzip_compressed() -> Result<(), Box<dyn std::error::Error>> {
     "};
     let mut file = tempfile::Builder::new().suffix(".fq").tempfile().unwrap();
     file.write_all(text).unwrap();
-    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    let mut cmd = ontime();
     let output = cmd
         .args(["-O", "g", file.path().to_str().unwrap()])
         .unwrap()
@@ -364,7 +387,7 @@ fn output_is_bzip2_compressed() -> Result<(), Box<dyn std::error::Error>> {
     "};
     let mut file = tempfile::Builder::new().suffix(".fq").tempfile().unwrap();
     file.write_all(text).unwrap();
-    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    let mut cmd = ontime();
     let output = cmd
         .args(["-O", "b", file.path().to_str().unwrap()])
         .unwrap()
@@ -390,7 +413,7 @@ fn output_is_lzma_compressed() -> Result<(), Box<dyn std::error::Error>> {
     "};
     let mut file = tempfile::Builder::new().suffix(".fq").tempfile().unwrap();
     file.write_all(text).unwrap();
-    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    let mut cmd = ontime();
     let output = cmd
         .args(["-O", "l", file.path().to_str().unwrap()])
         .unwrap()
@@ -420,7 +443,7 @@ fn show_earliest_and_latest() -> Result<(), Box<dyn std::error::Error>> {
     "};
     let mut file = tempfile::Builder::new().suffix(".fq").tempfile().unwrap();
     file.write_all(text).unwrap();
-    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    let mut cmd = ontime();
     let output = cmd
         .args(["--show", file.path().to_str().unwrap()])
         .unwrap()
@@ -438,7 +461,7 @@ fn show_earliest_and_latest() -> Result<(), Box<dyn std::error::Error>> {
 #[test]
 fn sam_input() -> Result<(), Box<dyn std::error::Error>> {
     let input = "tests/cases/test.sam";
-    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    let mut cmd = ontime();
     let output = cmd.args(["-t", "-4h", input]).unwrap().stdout;
 
     let expected_n_records = 8;
@@ -455,3 +478,2295 @@ fn sam_input() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+#[test]
+fn show_normalises_offsets_to_utc() -> Result<(), Box<dyn std::error::Error>> {
+    let text = indoc! {b"@s0 start_time=2022-12-12T18:00:00+10:00
+    A
+    +
+    1
+    @s1 start_time=2022-12-12T12:00:00Z
+    C
+    +
+    1
+    "};
+    let mut file = tempfile::Builder::new().suffix(".fq").tempfile().unwrap();
+    file.write_all(text).unwrap();
+    let mut cmd = ontime();
+    let output = cmd
+        .args(["--show", file.path().to_str().unwrap()])
+        .unwrap()
+        .stdout;
+
+    let expected = indoc! {b"Earliest: 2022-12-12T08:00:00.0Z
+    Latest  : 2022-12-12T12:00:00.0Z
+    "};
+
+    assert_eq!(output, expected);
+
+    Ok(())
+}
+
+#[test]
+fn show_in_chosen_utc_offset() -> Result<(), Box<dyn std::error::Error>> {
+    let text = indoc! {b"@s0 start_time=2022-12-12T18:00:00Z
+    A
+    +
+    1
+    @s1 start_time=2022-12-12T12:00:00Z
+    C
+    +
+    1
+    "};
+    let mut file = tempfile::Builder::new().suffix(".fq").tempfile().unwrap();
+    file.write_all(text).unwrap();
+    let mut cmd = ontime();
+    let output = cmd
+        .args(["--show", "-z", "+10:00", file.path().to_str().unwrap()])
+        .unwrap()
+        .stdout;
+
+    let expected = indoc! {b"Earliest: 2022-12-12T22:00:00.0+10:00
+    Latest  : 2022-12-13T04:00:00.0+10:00
+    "};
+
+    assert_eq!(output, expected);
+
+    Ok(())
+}
+
+#[test]
+fn timestamp_with_offset_compared_in_utc() -> Result<(), Box<dyn std::error::Error>> {
+    let text = indoc! {b"@s0 start_time=2022-12-12T18:00:00Z
+    A
+    +
+    1
+    @s2 start_time=2022-12-13T00:00:00+10:00
+    G
+    +
+    4
+    @s1 start_time=2022-12-12T12:00:00Z
+    C
+    +
+    1
+    "};
+    let mut file = tempfile::Builder::new().suffix(".fq").tempfile().unwrap();
+    file.write_all(text).unwrap();
+    let mut cmd = ontime();
+    let output = cmd
+        .args([
+            "-f",
+            "2022-12-12T23:00:00+10:00",
+            "-t",
+            "2022-12-12T15:00:00Z",
+            file.path().to_str().unwrap(),
+        ])
+        .unwrap()
+        .stdout;
+
+    let expected = indoc! {b"@s2 start_time=2022-12-13T00:00:00+10:00
+    G
+    +
+    4
+    "};
+
+    assert_eq!(output, expected);
+
+    Ok(())
+}
+
+#[test]
+fn multiple_cumulative_windows() -> Result<(), Box<dyn std::error::Error>> {
+    let text = indoc! {b"@s0 start_time=2022-12-12T18:00:00Z
+    A
+    +
+    1
+    @s2 start_time=2022-12-12T14:00:00Z
+    G
+    +
+    4
+    @s1 start_time=2022-12-12T12:00:00Z
+    C
+    +
+    1
+    "};
+    let mut file = tempfile::Builder::new().suffix(".fq").tempfile().unwrap();
+    file.write_all(text).unwrap();
+    let outdir = tempfile::tempdir().unwrap();
+    let template = outdir.path().join("{stem}.{window}.fq");
+    let stem = file
+        .path()
+        .file_stem()
+        .unwrap()
+        .to_string_lossy()
+        .to_string();
+    let mut cmd = ontime();
+    cmd.args([
+        "--windows",
+        "3h,1h",
+        "-o",
+        template.to_str().unwrap(),
+        file.path().to_str().unwrap(),
+    ])
+    .unwrap();
+
+    let one_hour = std::fs::read(outdir.path().join(format!("{stem}.1h.fq"))).unwrap();
+    let expected = indoc! {b"@s1 start_time=2022-12-12T12:00:00Z
+    C
+    +
+    1
+    "};
+    assert_eq!(one_hour, expected);
+
+    let three_hours = std::fs::read(outdir.path().join(format!("{stem}.3h.fq"))).unwrap();
+    let expected = indoc! {b"@s2 start_time=2022-12-12T14:00:00Z
+    G
+    +
+    4
+    @s1 start_time=2022-12-12T12:00:00Z
+    C
+    +
+    1
+    "};
+    assert_eq!(three_hours, expected);
+
+    Ok(())
+}
+
+#[test]
+fn windows_template_without_window_placeholder() -> Result<(), Box<dyn std::error::Error>> {
+    let text = ">s0 start_time=2022-12-12T18:00:00Z\nACGT\n";
+    let mut file = tempfile::Builder::new().suffix(".fa").tempfile().unwrap();
+    file.write_all(text.as_bytes()).unwrap();
+    let outdir = tempfile::tempdir().unwrap();
+    let template = outdir.path().join("out.fa");
+    let mut cmd = ontime();
+    let err_msg = cmd
+        .args([
+            "-w",
+            "1h,2h",
+            "-o",
+            template.to_str().unwrap(),
+            file.path().to_str().unwrap(),
+        ])
+        .unwrap_err()
+        .to_string();
+
+    assert!(err_msg.contains("must contain {window}"));
+
+    Ok(())
+}
+
+#[test]
+fn sam_input_multiple_windows() -> Result<(), Box<dyn std::error::Error>> {
+    let input = "tests/cases/test.sam";
+    let outdir = tempfile::tempdir().unwrap();
+    let template = outdir.path().join("{stem}.{window}.sam");
+    let mut cmd = ontime();
+    cmd.args(["-w", "-4h,-1s", "-o", template.to_str().unwrap(), input])
+        .unwrap();
+
+    let count_records = |p: std::path::PathBuf| {
+        std::fs::read(p)
+            .unwrap()
+            .lines()
+            .filter(|l| !l.starts_with(b"@"))
+            .count()
+    };
+
+    assert_eq!(count_records(outdir.path().join("test.-4h.sam")), 8);
+    assert!(count_records(outdir.path().join("test.-1s.sam")) > 8);
+
+    Ok(())
+}
+
+#[test]
+fn split_into_bins() -> Result<(), Box<dyn std::error::Error>> {
+    let text = indoc! {b"@s0 start_time=2022-12-12T13:30:00Z
+    A
+    +
+    1
+    @s2 start_time=2022-12-12T13:00:00Z
+    G
+    +
+    4
+    @s1 start_time=2022-12-12T12:30:00Z
+    C
+    +
+    1
+    "};
+    let outdir = tempfile::tempdir().unwrap();
+    let input = outdir.path().join("in.fq");
+    std::fs::write(&input, text).unwrap();
+    let template = outdir.path().join("{stem}.{bin}.fq");
+    let mut cmd = ontime_split();
+    cmd.args([
+        "--every",
+        "30m",
+        "-o",
+        template.to_str().unwrap(),
+        input.to_str().unwrap(),
+    ])
+    .unwrap();
+
+    let bin0 = std::fs::read(outdir.path().join("in.0.fq")).unwrap();
+    assert_eq!(bin0, b"@s1 start_time=2022-12-12T12:30:00Z\nC\n+\n1\n");
+    // bins are half-open, so the read starting on the boundary is in the later bin
+    let bin1 = std::fs::read(outdir.path().join("in.1.fq")).unwrap();
+    assert_eq!(bin1, b"@s2 start_time=2022-12-12T13:00:00Z\nG\n+\n4\n");
+    let bin2 = std::fs::read(outdir.path().join("in.2.fq")).unwrap();
+    assert_eq!(bin2, b"@s0 start_time=2022-12-12T13:30:00Z\nA\n+\n1\n");
+
+    Ok(())
+}
+
+#[test]
+fn split_into_wall_clock_bins() -> Result<(), Box<dyn std::error::Error>> {
+    let text = indoc! {b"@s0 start_time=2022-12-12T13:30:00Z
+    A
+    +
+    1
+    @s2 start_time=2022-12-12T13:00:00Z
+    G
+    +
+    4
+    @s1 start_time=2022-12-12T12:30:00Z
+    C
+    +
+    1
+    "};
+    let outdir = tempfile::tempdir().unwrap();
+    let input = outdir.path().join("in.fq");
+    std::fs::write(&input, text).unwrap();
+    let template = outdir.path().join("{stem}.{bin}.fq");
+    let mut cmd = ontime_split();
+    cmd.args([
+        "--every",
+        "1h",
+        "--wall-clock",
+        "-o",
+        template.to_str().unwrap(),
+        input.to_str().unwrap(),
+    ])
+    .unwrap();
+
+    let bin0 = std::fs::read(outdir.path().join("in.0.fq")).unwrap();
+    assert_eq!(bin0, b"@s1 start_time=2022-12-12T12:30:00Z\nC\n+\n1\n");
+    let bin1 = std::fs::read(outdir.path().join("in.1.fq")).unwrap();
+    let expected = indoc! {b"@s0 start_time=2022-12-12T13:30:00Z
+    A
+    +
+    1
+    @s2 start_time=2022-12-12T13:00:00Z
+    G
+    +
+    4
+    "};
+    assert_eq!(bin1, expected);
+
+    Ok(())
+}
+
+#[test]
+fn split_writes_and_reads_start_time_index() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let input = dir.path().join("in.fq");
+    std::fs::write(&input, "@r0 start_time=2022-12-12T12:00:00Z\nA\n+\n1\n")?;
+    let template = dir.path().join("{stem}.{bin}.fq");
+    let index = dir.path().join("in.fq.ontime");
+
+    let mut cmd = ontime_split();
+    cmd.args(["-e", "1h", "-o", template.to_str().unwrap()])
+        .arg(&input)
+        .unwrap();
+    assert!(!index.exists());
+
+    for _ in 0..2 {
+        let mut cmd = Command::cargo_bin(BIN).unwrap();
+        cmd.args(["split", "-e", "1h", "-o", template.to_str().unwrap()])
+            .arg(&input)
+            .unwrap();
+        assert!(index.exists());
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("in.0.fq"))?,
+            "@r0 start_time=2022-12-12T12:00:00Z\nA\n+\n1\n"
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn split_sam_copies_header() -> Result<(), Box<dyn std::error::Error>> {
+    let input = "tests/cases/test.sam";
+    let outdir = tempfile::tempdir().unwrap();
+    let template = outdir.path().join("{stem}.{bin}.sam");
+    let mut cmd = ontime_split();
+    cmd.args(["-e", "1w", "-o", template.to_str().unwrap(), input])
+        .unwrap();
+
+    let output = std::fs::read(outdir.path().join("test.0.sam")).unwrap();
+    let input = std::fs::read(input).unwrap();
+    // noodles may reorder the header lines, so compare them as a set
+    let header = |b: &[u8]| {
+        b.lines()
+            .filter(|l| l.starts_with(b"@"))
+            .map(|l| l.to_vec())
+            .collect::<std::collections::BTreeSet<_>>()
+    };
+    let nb_records = |b: &[u8]| b.lines().filter(|l| !l.starts_with(b"@")).count();
+
+    let mut expected = header(&input);
+    expected.insert(format!("@CO\tontime: kept {} reads", nb_records(&input)).into_bytes());
+    assert_eq!(
+        header(without_ontime_programs(&output).as_bytes()),
+        expected
+    );
+    assert_eq!(nb_records(&output), nb_records(&input));
+
+    Ok(())
+}
+
+#[test]
+fn secondary_and_supplementary_records_follow_their_read() -> Result<(), Box<dyn std::error::Error>>
+{
+    let dir = tempfile::tempdir()?;
+    let sam = dir.path().join("in.sam");
+    // the secondary and supplementary records have no start time, and the supplementary record of
+    // r2 comes before its primary record
+    std::fs::write(
+        &sam,
+        indoc! {"@HD\tVN:1.6\tSO:coordinate
+        @SQ\tSN:chr1\tLN:24
+        r2\t2048\tchr1\t1\t60\t4M\t*\t0\t0\tAAAA\tIIII
+        r1\t0\tchr1\t1\t60\t8M\t*\t0\t0\tACGTACGT\tIIIIIIII\tst:Z:2022-12-12T12:00:00Z
+        r2\t0\tchr1\t9\t60\t8M\t*\t0\t0\tAAAACCCC\tIIIIIIII\tst:Z:2022-12-12T14:00:00Z
+        r1\t256\tchr1\t17\t0\t4M\t*\t0\t0\t*\t*
+        "},
+    )?;
+    let bam = dir.path().join("in.bam");
+    let mut cmd = ontime();
+    cmd.args([sam.to_str().unwrap(), "-o", bam.to_str().unwrap()])
+        .assert()
+        .success();
+
+    let r1 = indoc! {"r1\t0\tchr1\t1\t60\t8M\t*\t0\t0\tACGTACGT\tIIIIIIII\tst:Z:2022-12-12T12:00:00Z
+    r1\t256\tchr1\t17\t0\t4M\t*\t0\t0\t*\t*
+    "};
+    let r2 = indoc! {"r2\t2048\tchr1\t1\t60\t4M\t*\t0\t0\tAAAA\tIIII
+    r2\t0\tchr1\t9\t60\t8M\t*\t0\t0\tAAAACCCC\tIIIIIIII\tst:Z:2022-12-12T14:00:00Z
+    "};
+    let r1_primary = r1.lines().next().unwrap().to_string() + "\n";
+
+    for (args, reads) in [
+        (vec!["-t", "1h", sam.to_str().unwrap()], r1),
+        (
+            vec!["-t", "2022-12-12T13:00:00Z", sam.to_str().unwrap()],
+            r1,
+        ),
+        (vec!["-f", "1h", sam.to_str().unwrap()], r2),
+        (
+            vec!["-f", "1h", "--output-format", "sam", bam.to_str().unwrap()],
+            r2,
+        ),
+        (vec!["-f", "1h", "--sort-output", sam.to_str().unwrap()], r2),
+        (
+            vec!["-t", "1h", "--primary-only", sam.to_str().unwrap()],
+            &r1_primary,
+        ),
+    ] {
+        let mut cmd = ontime();
+        let assert = cmd.args(&args).assert().success();
+        let output = assert.get_output();
+        let records: String = output
+            .stdout
+            .lines_with_terminator()
+            .filter(|l| !l.starts_with(b"@"))
+            .map(|l| l.to_str().unwrap())
+            .collect();
+        assert_eq!(records, reads, "{:?}", args);
+        assert!(output.stdout.contains_str("@CO\tontime: kept 1 reads\n"));
+    }
+    // secondary and supplementary records are not counted as reads
+    let mut cmd = ontime();
+    let stderr = cmd
+        .args(["-t", "1h", sam.to_str().unwrap()])
+        .unwrap()
+        .stderr;
+    assert!(stderr.contains_str("Gathered start times for 2 reads"));
+
+    // a coordinate-sorted stream also takes two passes, even with timestamps
+    for (args, reads) in [
+        (["-t", "2022-12-12T13:00:00Z", "-"], r1),
+        (["-f", "2022-12-12T13:00:00Z", "-"], r2),
+    ] {
+        let mut cmd = ontime();
+        let output = cmd
+            .args(args)
+            .write_stdin(std::fs::read(&sam)?)
+            .unwrap()
+            .stdout;
+        let records: String = output
+            .lines_with_terminator()
+            .filter(|l| !l.starts_with(b"@"))
+            .map(|l| l.to_str().unwrap())
+            .collect();
+        assert_eq!(records, reads, "{:?}", args);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn records_of_a_read_are_kept_together_in_either_pass() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let sam = dir.path().join("in.sam");
+    // not grouped by name: the secondary record of r1 is not next to its primary record
+    std::fs::write(
+        &sam,
+        indoc! {"@HD\tVN:1.6\tSO:unsorted
+        @SQ\tSN:chr1\tLN:24
+        r1\t0\tchr1\t1\t60\t4M\t*\t0\t0\tACGT\tIIII\tst:Z:2022-12-12T12:00:00Z
+        r2\t0\tchr1\t9\t60\t4M\t*\t0\t0\tCCCC\tIIII\tst:Z:2022-12-12T13:00:00Z
+        r1\t256\tchr1\t17\t0\t4M\t*\t0\t0\t*\t*
+        "},
+    )?;
+    let r1 = indoc! {"r1\t0\tchr1\t1\t60\t4M\t*\t0\t0\tACGT\tIIII\tst:Z:2022-12-12T12:00:00Z
+    r1\t256\tchr1\t17\t0\t4M\t*\t0\t0\t*\t*
+    "};
+    let records = |output: &[u8]| -> String {
+        output
+            .lines_with_terminator()
+            .filter(|l| !l.starts_with(b"@"))
+            .map(|l| l.to_str().unwrap())
+            .collect()
+    };
+
+    for to in ["30m", "2022-12-12T12:30:00Z"] {
+        let mut cmd = ontime();
+        let output = cmd.args(["-t", to, sam.to_str().unwrap()]).unwrap();
+        assert_eq!(records(&output.stdout), r1, "--to {to}");
+        assert!(!output.stderr.contains_str("skipped"));
+
+        let mut cmd = ontime();
+        let output = cmd
+            .args(["-t", to, "-"])
+            .write_stdin(std::fs::read(&sam)?)
+            .unwrap();
+        assert_eq!(records(&output.stdout), r1, "--to {to} from stdin");
+    }
+
+    // only input grouped by name - or --primary-only - is read in a single pass
+    let fastq = "@r1\tst:Z:2022-12-12T12:00:00Z\nACGT\n+\nIIII\n";
+    for (args, single_pass) in [(vec![], false), (vec!["--primary-only"], true)] {
+        let mut cmd = ontime();
+        let output = cmd
+            .args(["--output-format", "fastx", "-t", "2022-12-12T12:30:00Z"])
+            .args(&args)
+            .arg(sam.to_str().unwrap())
+            .unwrap();
+        assert_eq!(output.stdout.to_str()?, fastq, "{:?}", args);
+        assert_eq!(
+            output.stderr.contains_str("in a single pass"),
+            single_pass,
+            "{:?}",
+            args
+        );
+    }
+    let grouped = dir.path().join("grouped.sam");
+    std::fs::write(
+        &grouped,
+        std::fs::read_to_string(&sam)?.replace("SO:unsorted", "SO:unsorted\tGO:query"),
+    )?;
+    let mut cmd = ontime();
+    let output = cmd
+        .args(["--output-format", "fastx", "-t", "2022-12-12T12:30:00Z"])
+        .arg(grouped.to_str().unwrap())
+        .unwrap();
+    assert!(output.stderr.contains_str("in a single pass"));
+
+    Ok(())
+}
+
+#[test]
+fn records_before_their_primary_are_kept_with_it() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let sam = dir.path().join("in.sam");
+    // the supplementary record of r1 comes before its primary record, and neither r2 nor the
+    // unnamed record has a primary record
+    std::fs::write(
+        &sam,
+        indoc! {"@HD	VN:1.6	SO:unknown
+        @SQ	SN:chr1	LN:24
+        r0	0	chr1	1	60	4M	*	0	0	ACGT	IIII	st:Z:2022-12-12T12:00:00Z
+        r1	2048	chr1	1	60	4M	*	0	0	AAAA	IIII
+        r1	0	chr1	9	60	8M	*	0	0	AAAACCCC	IIIIIIII	st:Z:2022-12-12T14:00:00Z
+        r1	256	chr1	17	0	4M	*	0	0	*	*
+        r2	2048	chr1	1	60	4M	*	0	0	GGGG	IIII
+        *	2048	chr1	1	60	4M	*	0	0	TTTT	IIII
+        r3	0	chr1	1	60	4M	*	0	0	CCCC	IIII	st:Z:2022-12-12T15:00:00Z
+        "},
+    )?;
+
+    let mut cmd = ontime();
+    let assert = cmd
+        .args([
+            "-f",
+            "2022-12-12T13:00:00Z",
+            "-t",
+            "2022-12-12T14:30:00Z",
+            "-",
+        ])
+        .write_stdin(std::fs::read(&sam)?)
+        .assert()
+        .success();
+    let output = assert.get_output();
+    let records: String = output
+        .stdout
+        .lines_with_terminator()
+        .filter(|l| !l.starts_with(b"@"))
+        .map(|l| l.to_str().unwrap())
+        .collect();
+    assert_eq!(
+        records,
+        indoc! {"r1	2048	chr1	1	60	4M	*	0	0	AAAA	IIII
+        r1	0	chr1	9	60	8M	*	0	0	AAAACCCC	IIIIIIII	st:Z:2022-12-12T14:00:00Z
+        r1	256	chr1	17	0	4M	*	0	0	*	*
+        "}
+    );
+    assert!(output
+        .stderr
+        .contains_str("2 secondary/supplementary records in "));
+    assert!(output.stderr.contains_str("have no primary record"));
+
+    Ok(())
+}
+
+#[test]
+fn until_bases_keeps_reads_until_target_reached() -> Result<(), Box<dyn std::error::Error>> {
+    let text = indoc! {b"@s0 start_time=2022-12-12T18:00:00Z
+    AAAA
+    +
+    1111
+    @s2 start_time=2022-12-12T14:00:00Z
+    GGG
+    +
+    444
+    @s1 start_time=2022-12-12T12:00:00Z
+    CC
+    +
+    11
+    "};
+    let mut file = tempfile::Builder::new().suffix(".fq").tempfile().unwrap();
+    file.write_all(text).unwrap();
+    let mut cmd = ontime();
+    let assert = cmd
+        .args(["--until-bases", "4b", file.path().to_str().unwrap()])
+        .assert()
+        .success();
+    let output = assert.get_output();
+
+    let expected = indoc! {b"@s2 start_time=2022-12-12T14:00:00Z
+    GGG
+    +
+    444
+    @s1 start_time=2022-12-12T12:00:00Z
+    CC
+    +
+    11
+    "};
+
+    assert_eq!(output.stdout, expected);
+    assert!(output
+        .stderr
+        .contains_str("Reached 4 bases at 2022-12-12T14:00:00.0Z"));
+
+    Ok(())
+}
+
+#[test]
+fn until_bases_not_reached_keeps_reads_ending_after_last_start(
+) -> Result<(), Box<dyn std::error::Error>> {
+    let text = indoc! {b"@s0 start_time=2022-12-12T12:00:00Z duration=10
+    AAAA
+    +
+    1111
+    @s1 start_time=2022-12-12T13:00:00Z duration=600
+    CC
+    +
+    11
+    "};
+    let mut file = tempfile::Builder::new().suffix(".fq").tempfile().unwrap();
+    file.write_all(text).unwrap();
+    let mut cmd = ontime();
+    let assert = cmd
+        .args([
+            "--match",
+            "end",
+            "--until-bases",
+            "1kb",
+            file.path().to_str().unwrap(),
+        ])
+        .assert()
+        .success();
+    let output = assert.get_output();
+
+    assert_eq!(output.stdout, text);
+    assert!(output
+        .stderr
+        .contains_str("Target not reached; keeping all reads"));
+
+    Ok(())
+}
+
+#[test]
+fn show_depth_table_and_target() -> Result<(), Box<dyn std::error::Error>> {
+    let text = indoc! {b"@s0 start_time=2022-12-12T18:00:00Z
+    AAAAAAAAAA
+    +
+    1111111111
+    @s2 start_time=2022-12-12T14:00:00Z
+    GGGGGGGGGG
+    +
+    4444444444
+    @s1 start_time=2022-12-12T12:00:00Z
+    CCCCCCCCCC
+    +
+    1111111111
+    "};
+    let mut file = tempfile::Builder::new().suffix(".fq").tempfile().unwrap();
+    file.write_all(text).unwrap();
+    let mut cmd = ontime();
+    let output = cmd
+        .args([
+            "--show",
+            "--until-depth",
+            "15x",
+            "--genome-size",
+            "1",
+            file.path().to_str().unwrap(),
+        ])
+        .unwrap()
+        .stdout;
+
+    let expected = indoc! {b"Earliest: 2022-12-12T12:00:00.0Z
+    Latest  : 2022-12-12T18:00:00.0Z
+    Target  : 2022-12-12T14:00:00.0Z
+    Depth\tBases\tTime\tElapsed
+    10x\t10\t2022-12-12T12:00:00.0Z\t0s
+    20x\t20\t2022-12-12T14:00:00.0Z\t2h
+    30x\t30\t2022-12-12T18:00:00.0Z\t6h
+    "};
+
+    assert_eq!(output, expected);
+
+    Ok(())
+}
+
+#[test]
+fn until_depth_requires_genome_size() -> Result<(), Box<dyn std::error::Error>> {
+    let text = ">s0 start_time=2022-12-12T18:00:00Z\nACGT\n";
+    let mut file = tempfile::Builder::new().suffix(".fa").tempfile().unwrap();
+    file.write_all(text.as_bytes()).unwrap();
+    let mut cmd = ontime();
+    let err_msg = cmd
+        .args(["--until-depth", "50x", file.path().to_str().unwrap()])
+        .unwrap_err()
+        .to_string();
+
+    assert!(err_msg.contains("--genome-size"));
+
+    Ok(())
+}
+
+#[test]
+fn multiple_ranges() -> Result<(), Box<dyn std::error::Error>> {
+    let text = indoc! {b"@s0 start_time=2022-12-12T18:00:00Z
+    A
+    +
+    1
+    @s2 start_time=2022-12-12T14:00:00Z
+    G
+    +
+    4
+    @s3 start_time=2022-12-12T16:00:00Z
+    T
+    +
+    5
+    @s1 start_time=2022-12-12T12:00:00Z
+    C
+    +
+    1
+    "};
+    let mut file = tempfile::Builder::new().suffix(".fq").tempfile().unwrap();
+    file.write_all(text).unwrap();
+    let mut cmd = ontime();
+    let output = cmd
+        .args([
+            "--range",
+            "..30m",
+            "-r",
+            "3h..-1h",
+            "-r",
+            "-90m..-1h30m",
+            file.path().to_str().unwrap(),
+        ])
+        .unwrap()
+        .stdout;
+
+    let expected = indoc! {b"@s3 start_time=2022-12-12T16:00:00Z
+    T
+    +
+    5
+    @s1 start_time=2022-12-12T12:00:00Z
+    C
+    +
+    1
+    "};
+
+    assert_eq!(output, expected);
+
+    Ok(())
+}
+
+#[test]
+fn range_conflicts_with_from() -> Result<(), Box<dyn std::error::Error>> {
+    let text = ">s0 start_time=2022-12-12T18:00:00Z\nACGT\n";
+    let mut file = tempfile::Builder::new().suffix(".fa").tempfile().unwrap();
+    file.write_all(text.as_bytes()).unwrap();
+    let mut cmd = ontime();
+    let err_msg = cmd
+        .args(["-r", "..1h", "-f", "1h", file.path().to_str().unwrap()])
+        .unwrap_err()
+        .to_string();
+
+    assert!(err_msg.contains("cannot be used with"));
+
+    Ok(())
+}
+
+#[test]
+fn invert_excludes_timeframe() -> Result<(), Box<dyn std::error::Error>> {
+    let text = indoc! {b"@s0 start_time=2022-12-12T18:00:00Z
+    A
+    +
+    1
+    @s2 start_time=2022-12-12T14:00:00Z
+    G
+    +
+    4
+    @s1 start_time=2022-12-12T12:00:00Z
+    C
+    +
+    1
+    "};
+    let mut file = tempfile::Builder::new().suffix(".fq").tempfile().unwrap();
+    file.write_all(text).unwrap();
+    let mut cmd = ontime();
+    let output = cmd
+        .args([
+            "--invert",
+            "-f",
+            "1h",
+            "-t",
+            "3h",
+            file.path().to_str().unwrap(),
+        ])
+        .unwrap()
+        .stdout;
+
+    let expected = indoc! {b"@s0 start_time=2022-12-12T18:00:00Z
+    A
+    +
+    1
+    @s1 start_time=2022-12-12T12:00:00Z
+    C
+    +
+    1
+    "};
+
+    assert_eq!(output, expected);
+
+    Ok(())
+}
+
+#[test]
+fn exclude_multiple_ranges_from_sam() -> Result<(), Box<dyn std::error::Error>> {
+    let input = "tests/cases/test.sam";
+    let count_records = |output: Vec<u8>| output.lines().filter(|l| !l.starts_with(b"@")).count();
+
+    let mut cmd = ontime();
+    let kept = count_records(
+        cmd.args(["-r", "..2h", "-r", "-4h..", input])
+            .unwrap()
+            .stdout,
+    );
+    let mut cmd = ontime();
+    let excluded = count_records(
+        cmd.args(["--exclude", "-r", "..2h", "-r", "-4h..", input])
+            .unwrap()
+            .stdout,
+    );
+    let mut cmd = ontime();
+    let total = count_records(cmd.args([input]).unwrap().stdout);
+
+    assert!(kept > 0);
+    assert!(excluded > 0);
+    assert_eq!(kept + excluded, total);
+
+    Ok(())
+}
+
+#[test]
+fn match_reads_by_end_time() -> Result<(), Box<dyn std::error::Error>> {
+    let text = indoc! {b"@s0 start_time=2022-12-12T12:00:00Z duration=10
+    A
+    +
+    1
+    @s1 start_time=2022-12-12T12:59:00Z duration=120
+    C
+    +
+    1
+    @s2 start_time=2022-12-12T14:00:00Z duration=10
+    G
+    +
+    4
+    "};
+    let mut file = tempfile::Builder::new().suffix(".fq").tempfile().unwrap();
+    file.write_all(text).unwrap();
+
+    let mut cmd = ontime();
+    let output = cmd
+        .args(["-t", "1h", file.path().to_str().unwrap()])
+        .unwrap()
+        .stdout;
+    assert_eq!(output.lines().filter(|l| l.starts_with(b"@")).count(), 2);
+
+    let mut cmd = ontime();
+    let output = cmd
+        .args([
+            "--match",
+            "contained",
+            "-t",
+            "1h",
+            file.path().to_str().unwrap(),
+        ])
+        .unwrap()
+        .stdout;
+    let expected = indoc! {b"@s0 start_time=2022-12-12T12:00:00Z duration=10
+    A
+    +
+    1
+    "};
+    assert_eq!(output, expected);
+
+    let mut cmd = ontime();
+    let output = cmd
+        .args(["--match", "end", "-f", "1h", file.path().to_str().unwrap()])
+        .unwrap()
+        .stdout;
+    let expected = indoc! {b"@s1 start_time=2022-12-12T12:59:00Z duration=120
+    C
+    +
+    1
+    @s2 start_time=2022-12-12T14:00:00Z duration=10
+    G
+    +
+    4
+    "};
+    assert_eq!(output, expected);
+
+    Ok(())
+}
+
+#[test]
+fn match_overlaps_with_sam_duration_tag() -> Result<(), Box<dyn std::error::Error>> {
+    let input = "tests/cases/test.sam";
+    let count_records = |output: Vec<u8>| output.lines().filter(|l| !l.starts_with(b"@")).count();
+
+    let mut cmd = ontime();
+    let starts = count_records(cmd.args(["-f", "2h", "-t", "4h", input]).unwrap().stdout);
+    let mut cmd = ontime();
+    let overlaps = count_records(
+        cmd.args(["-m", "overlaps", "-f", "2h", "-t", "4h", input])
+            .unwrap()
+            .stdout,
+    );
+    let mut cmd = ontime();
+    let contained = count_records(
+        cmd.args(["-m", "contained", "-f", "2h", "-t", "4h", input])
+            .unwrap()
+            .stdout,
+    );
+
+    assert!(contained <= starts);
+    assert!(starts <= overlaps);
+
+    Ok(())
+}
+
+#[test]
+fn sorted_input_stops_after_timeframe() -> Result<(), Box<dyn std::error::Error>> {
+    // the last record has no start time, so it is only reached if reading does not stop early
+    let text = indoc! {b"@s0 start_time=2022-12-12T12:00:00Z
+    A
+    +
+    1
+    @s1 start_time=2022-12-12T13:00:00Z
+    C
+    +
+    1
+    @s2 start_time=2022-12-12T15:00:00Z
+    G
+    +
+    4
+    @s3
+    T
+    +
+    1
+    "};
+    let mut file = tempfile::Builder::new().suffix(".fq").tempfile().unwrap();
+    file.write_all(text).unwrap();
+    let mut cmd = ontime();
+    let output = cmd
+        .args(["--sorted", "-t", "1h", file.path().to_str().unwrap()])
+        .unwrap()
+        .stdout;
+
+    let expected = indoc! {b"@s0 start_time=2022-12-12T12:00:00Z
+    A
+    +
+    1
+    @s1 start_time=2022-12-12T13:00:00Z
+    C
+    +
+    1
+    "};
+
+    assert_eq!(output, expected);
+
+    let mut cmd = ontime();
+    cmd.args(["-t", "1h", file.path().to_str().unwrap()])
+        .assert()
+        .failure();
+
+    Ok(())
+}
+
+#[test]
+fn single_pass_with_timestamps_matches_two_pass() -> Result<(), Box<dyn std::error::Error>> {
+    let input = "tests/cases/test.sam";
+
+    // timestamps only - no first pass is needed for fastq output of the primary records
+    let mut cmd = ontime();
+    let single_pass = cmd
+        .args([
+            "--output-format",
+            "fastx",
+            "--primary-only",
+            "-f",
+            "2023-09-22T22:54:22.039Z",
+            "-t",
+            "2023-09-23T20:54:22.039Z",
+            input,
+        ])
+        .unwrap();
+    assert!(single_pass.stderr.contains_str("in a single pass"));
+    let single_pass = single_pass.stdout;
+    // durations from the first timestamp need the start times of all reads
+    let mut cmd = ontime();
+    let two_pass = cmd
+        .args(["--output-format", "fastx", "-f", "2h", "-t", "24h", input])
+        .unwrap()
+        .stdout;
+
+    assert_eq!(single_pass, two_pass);
+
+    Ok(())
+}
+
+#[test]
+fn fastq_from_stdin() -> Result<(), Box<dyn std::error::Error>> {
+    let text = indoc! {b"@s0 start_time=2022-12-12T18:00:00Z
+    A
+    +
+    1
+    @s2 start_time=2022-12-12T14:00:00Z
+    G
+    +
+    4
+    @s1 start_time=2022-12-12T12:00:00Z
+    C
+    +
+    1
+    "};
+    // durations need the first start time, so stdin is copied to a temporary file
+    let mut cmd = ontime();
+    let output = cmd
+        .args(["-", "-f", "1h", "-t", "3h"])
+        .write_stdin(text.as_slice())
+        .unwrap()
+        .stdout;
+
+    let expected = indoc! {b"@s2 start_time=2022-12-12T14:00:00Z
+    G
+    +
+    4
+    "};
+
+    assert_eq!(output, expected);
+
+    // timestamps are extracted straight from stdin
+    let mut cmd = ontime();
+    let output = cmd
+        .args(["-", "-t", "2022-12-12T15:00:00Z"])
+        .write_stdin(text.as_slice())
+        .unwrap()
+        .stdout;
+
+    let expected = indoc! {b"@s2 start_time=2022-12-12T14:00:00Z
+    G
+    +
+    4
+    @s1 start_time=2022-12-12T12:00:00Z
+    C
+    +
+    1
+    "};
+
+    assert_eq!(output, expected);
+
+    Ok(())
+}
+
+#[test]
+fn sam_from_stdin() -> Result<(), Box<dyn std::error::Error>> {
+    let input = "tests/cases/test.sam";
+    let sam = std::fs::read(input)?;
+
+    let mut cmd = ontime();
+    let from_stdin = cmd
+        .args(["-", "-f", "2h", "-t", "-4h"])
+        .write_stdin(sam)
+        .unwrap()
+        .stdout;
+    let mut cmd = ontime();
+    let from_file = cmd.args([input, "-f", "2h", "-t", "-4h"]).unwrap().stdout;
+
+    assert!(from_stdin.lines().any(|l| !l.starts_with(b"@")));
+    assert_eq!(
+        without_ontime_programs(&from_stdin),
+        without_ontime_programs(&from_file)
+    );
+
+    Ok(())
+}
+
+#[test]
+fn split_from_stdin() -> Result<(), Box<dyn std::error::Error>> {
+    let text = indoc! {b"@s0 start_time=2022-12-12T12:00:00Z
+    A
+    +
+    1
+    @s1 start_time=2022-12-12T14:30:00Z
+    C
+    +
+    1
+    "};
+    let dir = tempfile::tempdir()?;
+    let template = dir.path().join("{stem}.{bin}.fq");
+    let mut cmd = ontime_split();
+    cmd.args(["-e", "1h", "-o", template.to_str().unwrap(), "-"])
+        .write_stdin(text.as_slice())
+        .assert()
+        .success();
+
+    assert!(dir.path().join("stdin.0.fq").exists());
+    assert!(!dir.path().join("stdin.1.fq").exists());
+    assert!(dir.path().join("stdin.2.fq").exists());
+
+    Ok(())
+}
+
+#[test]
+fn stdin_with_unknown_format() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = ontime();
+    cmd.args(["-", "-t", "1h"])
+        .write_stdin("not a fastq\n")
+        .assert()
+        .failure();
+
+    Ok(())
+}
+
+#[test]
+fn fastq_format_detected_from_content() -> Result<(), Box<dyn std::error::Error>> {
+    let text = indoc! {b"@s0 start_time=2022-12-12T18:00:00Z
+    A
+    +
+    1
+    @s1 start_time=2022-12-12T12:00:00Z
+    C
+    +
+    1
+    "};
+    // an extensionless, zstd-compressed file - like a MinKNOW chunk
+    let file = tempfile::Builder::new().tempfile().unwrap();
+    {
+        let mut writer = niffler::to_path(file.path(), niffler::Format::Zstd, niffler::Level::One)?;
+        writer.write_all(text)?;
+    }
+    let mut cmd = ontime();
+    let output = cmd
+        .args(["-t", "1h", file.path().to_str().unwrap()])
+        .unwrap()
+        .stdout;
+
+    let expected = indoc! {b"@s1 start_time=2022-12-12T12:00:00Z
+    C
+    +
+    1
+    "};
+
+    assert_eq!(output, expected);
+
+    Ok(())
+}
+
+#[test]
+fn input_format_overrides_detection() -> Result<(), Box<dyn std::error::Error>> {
+    let file = tempfile::Builder::new().tempfile().unwrap();
+
+    let mut cmd = ontime();
+    let err_msg = cmd
+        .args([file.path().to_str().unwrap()])
+        .unwrap_err()
+        .to_string();
+    assert!(err_msg.contains("Use --input-format"));
+
+    let mut cmd = ontime();
+    let output = cmd
+        .args(["--input-format", "fastq", file.path().to_str().unwrap()])
+        .unwrap()
+        .stdout;
+    assert!(output.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn cram_round_trip() -> Result<(), Box<dyn std::error::Error>> {
+    let input = "tests/cases/test.sam";
+    let dir = tempfile::tempdir()?;
+    let cram = dir.path().join("out.cram");
+
+    let mut cmd = ontime();
+    cmd.args([input, "-o", cram.to_str().unwrap()])
+        .assert()
+        .success();
+
+    let mut cmd = ontime();
+    let from_cram = cmd
+        .args(["-f", "2h", "-t", "-4h", cram.to_str().unwrap()])
+        .unwrap()
+        .stdout;
+    let mut cmd = ontime();
+    let from_sam = cmd.args(["-f", "2h", "-t", "-4h", input]).unwrap().stdout;
+
+    // CRAM does not store the mapping quality of unmapped reads, so only compare the names
+    let records = |output: &[u8]| {
+        output
+            .lines()
+            .filter(|l| !l.starts_with(b"@"))
+            .map(|l| l.split(|c| *c == b'\t').next().unwrap().to_owned())
+            .collect::<Vec<_>>()
+    };
+    assert!(!records(&from_cram).is_empty());
+    assert_eq!(records(&from_cram), records(&from_sam));
+
+    Ok(())
+}
+
+#[test]
+fn cram_with_reference() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let reference = dir.path().join("ref.fa");
+    std::fs::write(&reference, ">chr1\nACGTACGTAAAACCCCGGGGTTTT\n")?;
+    let sam = dir.path().join("in.sam");
+    std::fs::write(
+        &sam,
+        indoc! {"@HD\tVN:1.6\tSO:coordinate
+        @SQ\tSN:chr1\tLN:24
+        r1\t0\tchr1\t1\t60\t8M\t*\t0\t0\tACGTACGT\tIIIIIIII\tst:Z:2022-12-12T12:00:00Z
+        r2\t0\tchr1\t9\t60\t8M\t*\t0\t0\tAAAACCCC\tIIIIIIII\tst:Z:2022-12-12T14:00:00Z
+        "},
+    )?;
+    let cram = dir.path().join("out.cram");
+
+    let mut cmd = ontime();
+    cmd.args([
+        "-T",
+        reference.to_str().unwrap(),
+        "-o",
+        cram.to_str().unwrap(),
+        sam.to_str().unwrap(),
+    ])
+    .assert()
+    .success();
+
+    let mut cmd = ontime();
+    let output = cmd
+        .args([
+            "-T",
+            reference.to_str().unwrap(),
+            "-t",
+            "1h",
+            cram.to_str().unwrap(),
+        ])
+        .unwrap()
+        .stdout;
+
+    let expected = indoc! {"@HD\tVN:1.6\tSO:coordinate
+    @SQ\tSN:chr1\tLN:24\tM5:372b50e2de3a934f354dbecb2b16960b
+    @CO\tontime: kept 1 reads
+    r1\t0\tchr1\t1\t60\t8M\t*\t0\t0\tACGTACGT\tIIIIIIII\tst:Z:2022-12-12T12:00:00Z
+    "};
+
+    assert_eq!(without_ontime_programs(&output), expected);
+
+    Ok(())
+}
+
+#[test]
+fn bam_with_threads() -> Result<(), Box<dyn std::error::Error>> {
+    let input = "tests/cases/test.sam";
+    let dir = tempfile::tempdir()?;
+    let single = dir.path().join("single.bam");
+    let multi = dir.path().join("multi.bam");
+
+    let mut cmd = ontime();
+    cmd.args([input, "-o", single.to_str().unwrap()])
+        .assert()
+        .success();
+    let mut cmd = ontime();
+    cmd.args(["--threads", "4", input, "-o", multi.to_str().unwrap()])
+        .assert()
+        .success();
+
+    let as_sam = |bam: &std::path::Path| {
+        let mut cmd = ontime();
+        let sam = cmd
+            .args(["--output-format", "sam", bam.to_str().unwrap()])
+            .unwrap()
+            .stdout;
+        without_ontime_programs(&sam)
+    };
+    assert_eq!(as_sam(&single), as_sam(&multi));
+
+    let mut cmd = ontime();
+    let from_single = cmd
+        .args(["-f", "2h", "-t", "-4h", single.to_str().unwrap()])
+        .unwrap()
+        .stdout;
+    let mut cmd = ontime();
+    let from_multi = cmd
+        .args(["-@", "4", "-f", "2h", "-t", "-4h", multi.to_str().unwrap()])
+        .unwrap()
+        .stdout;
+
+    assert!(from_multi.lines().any(|l| !l.starts_with(b"@")));
+    assert_eq!(
+        without_ontime_programs(&from_single),
+        without_ontime_programs(&from_multi)
+    );
+
+    Ok(())
+}
+
+#[test]
+fn compressed_fastq_with_threads() -> Result<(), Box<dyn std::error::Error>> {
+    let text = indoc! {b"@s0 start_time=2022-12-12T18:00:00Z
+    A
+    +
+    1
+    @s1 start_time=2022-12-12T12:00:00Z
+    C
+    +
+    1
+    "};
+    let mut file = tempfile::Builder::new().suffix(".fq").tempfile().unwrap();
+    file.write_all(text).unwrap();
+    let dir = tempfile::tempdir()?;
+
+    for ext in ["gz", "zst"] {
+        let output = dir.path().join(format!("out.fq.{ext}"));
+        let mut cmd = ontime();
+        cmd.args([
+            "-@",
+            "4",
+            "-o",
+            output.to_str().unwrap(),
+            file.path().to_str().unwrap(),
+        ])
+        .assert()
+        .success();
+
+        let (mut reader, _) = niffler::from_path(&output)?;
+        let mut actual = vec![];
+        reader.read_to_end(&mut actual)?;
+        assert_eq!(actual, text);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn compression_level_with_threads() -> Result<(), Box<dyn std::error::Error>> {
+    // reads whose bases are not all alike, so the compression level makes a difference
+    let mut seed = 42u64;
+    let text = (0..300)
+        .map(|i| {
+            let seq = (0..100)
+                .map(|_| {
+                    seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+                    b"ACGT"[(seed >> 62) as usize] as char
+                })
+                .collect::<String>();
+            format!(
+                "@r{i} start_time=2022-12-12T12:00:00Z\n{seq}\n+\n{}\n",
+                "5".repeat(seq.len())
+            )
+        })
+        .collect::<String>();
+    let dir = tempfile::tempdir()?;
+    let input = dir.path().join("in.fq");
+    std::fs::write(&input, &text)?;
+
+    for ext in ["gz", "bgz"] {
+        let output_size = |level: &str, threads: &str| {
+            let output = dir.path().join(format!("out.{level}.{threads}.fq.{ext}"));
+            let mut cmd = ontime();
+            cmd.args(["-L", level, "-@", threads, "-o"])
+                .arg(&output)
+                .arg(&input)
+                .assert()
+                .success();
+            std::fs::metadata(&output).unwrap().len()
+        };
+        let fast = output_size("1", "4");
+        assert_eq!(fast, output_size("1", "1"));
+        assert!(fast > output_size("9", "4"));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn multiple_fastq_inputs() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let chunk0 = dir.path().join("chunk_0.fastq");
+    std::fs::write(
+        &chunk0,
+        indoc! {"@s0 start_time=2022-12-12T12:00:00Z
+        A
+        +
+        1
+        @s1 start_time=2022-12-12T15:00:00Z
+        C
+        +
+        1
+        "},
+    )?;
+    let chunk1 = dir.path().join("chunk_1.fastq");
+    std::fs::write(
+        &chunk1,
+        indoc! {"@s2 start_time=2022-12-12T13:00:00Z
+        G
+        +
+        1
+        @s3 start_time=2022-12-12T18:00:00Z
+        T
+        +
+        1
+        "},
+    )?;
+
+    // the last timestamp is in the second chunk
+    let mut cmd = ontime();
+    let output = cmd
+        .args([
+            "-t",
+            "-4h",
+            chunk0.to_str().unwrap(),
+            chunk1.to_str().unwrap(),
+        ])
+        .unwrap()
+        .stdout;
+
+    let expected = indoc! {b"@s0 start_time=2022-12-12T12:00:00Z
+    A
+    +
+    1
+    @s2 start_time=2022-12-12T13:00:00Z
+    G
+    +
+    1
+    "};
+
+    assert_eq!(output, expected);
+
+    Ok(())
+}
+
+#[test]
+fn directory_input_with_glob() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let pass = dir.path().join("fastq_pass").join("barcode01");
+    std::fs::create_dir_all(&pass)?;
+    std::fs::write(
+        pass.join("chunk_0.fastq"),
+        "@s0 start_time=2022-12-12T12:00:00Z\nA\n+\n1\n",
+    )?;
+    std::fs::write(
+        dir.path().join("fastq_pass").join("chunk_1.fastq"),
+        "@s1 start_time=2022-12-12T13:00:00Z\nC\n+\n1\n",
+    )?;
+    std::fs::write(dir.path().join("sequencing_summary.txt"), "not reads\n")?;
+    std::fs::write(
+        dir.path().join("chunk_2.fq"),
+        "@s2 start_time=2022-12-12T14:00:00Z\nG\n+\n1\n",
+    )?;
+
+    // by default, every file with a recognised extension is used
+    let mut cmd = ontime();
+    let output = cmd.args([dir.path().to_str().unwrap()]).unwrap().stdout;
+    assert_eq!(output.lines().filter(|l| l.starts_with(b"@")).count(), 3);
+
+    let mut cmd = ontime();
+    let output = cmd
+        .args(["--glob", "*.fastq", dir.path().to_str().unwrap()])
+        .unwrap()
+        .stdout;
+
+    let expected = indoc! {b"@s0 start_time=2022-12-12T12:00:00Z
+    A
+    +
+    1
+    @s1 start_time=2022-12-12T13:00:00Z
+    C
+    +
+    1
+    "};
+
+    assert_eq!(output, expected);
+
+    Ok(())
+}
+
+#[test]
+fn error_names_the_input_file() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let good = dir.path().join("good.fq");
+    std::fs::write(&good, "@s0 start_time=2022-12-12T12:00:00Z\nA\n+\n1\n")?;
+    let bad = dir.path().join("bad.fq");
+    std::fs::write(&bad, "@s1\nA\n+\n1\n")?;
+
+    let mut cmd = ontime();
+    let err_msg = cmd
+        .args(["-t", "1h", good.to_str().unwrap(), bad.to_str().unwrap()])
+        .unwrap_err()
+        .to_string();
+
+    assert!(err_msg.contains("bad.fq"));
+
+    Ok(())
+}
+
+#[test]
+fn multiple_sam_inputs_merge_headers() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let sam0 = dir.path().join("a.sam");
+    std::fs::write(
+        &sam0,
+        indoc! {"@HD\tVN:1.6\tSO:unknown
+        @RG\tID:run0
+        r0\t4\t*\t0\t0\t*\t*\t0\t0\tA\t1\tRG:Z:run0\tst:Z:2022-12-12T12:00:00Z
+        "},
+    )?;
+    let sam1 = dir.path().join("b.sam");
+    std::fs::write(
+        &sam1,
+        indoc! {"@HD\tVN:1.6\tSO:unknown
+        @RG\tID:run1
+        r1\t4\t*\t0\t0\t*\t*\t0\t0\tC\t1\tRG:Z:run1\tst:Z:2022-12-12T13:00:00Z
+        "},
+    )?;
+
+    let mut cmd = ontime();
+    let output = cmd
+        .args([sam0.to_str().unwrap(), sam1.to_str().unwrap()])
+        .unwrap()
+        .stdout;
+
+    let expected = indoc! {"@HD\tVN:1.6\tSO:unknown
+    @RG\tID:run0
+    @RG\tID:run1
+    @CO\tontime: kept 2 reads
+    r0\t4\t*\t0\t0\t*\t*\t0\t0\tA\t1\tRG:Z:run0\tst:Z:2022-12-12T12:00:00Z
+    r1\t4\t*\t0\t0\t*\t*\t0\t0\tC\t1\tRG:Z:run1\tst:Z:2022-12-12T13:00:00Z
+    "};
+
+    assert_eq!(without_ontime_programs(&output), expected);
+
+    // the same, with a timeframe relative to the first start time of both inputs
+    let mut cmd = ontime();
+    let output = cmd
+        .args(["-f", "30m", sam0.to_str().unwrap(), sam1.to_str().unwrap()])
+        .unwrap()
+        .stdout;
+
+    assert!(String::from_utf8(output)?.ends_with("st:Z:2022-12-12T13:00:00Z\n"));
+
+    Ok(())
+}
+
+#[test]
+fn inputs_with_different_formats() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let fastq = dir.path().join("a.fq");
+    std::fs::write(&fastq, "@s0 start_time=2022-12-12T12:00:00Z\nA\n+\n1\n")?;
+
+    let mut cmd = ontime();
+    let err_msg = cmd
+        .args([fastq.to_str().unwrap(), "tests/cases/test.sam"])
+        .unwrap_err()
+        .to_string();
+
+    assert!(err_msg.contains("is not the same format as"));
+
+    Ok(())
+}
+
+#[test]
+fn sort_reads_from_multiple_inputs() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let chunk0 = dir.path().join("chunk_0.fastq");
+    std::fs::write(
+        &chunk0,
+        indoc! {"@s0 start_time=2022-12-12T15:00:00Z
+        A
+        +
+        1
+        @s1 start_time=2022-12-12T12:00:00Z
+        C
+        +
+        1
+        "},
+    )?;
+    let chunk1 = dir.path().join("chunk_1.fastq");
+    std::fs::write(
+        &chunk1,
+        indoc! {"@s2 start_time=2022-12-12T18:00:00Z
+        G
+        +
+        1
+        @s3 start_time=2022-12-12T13:00:00+01:00
+        T
+        +
+        1
+        "},
+    )?;
+
+    let expected = indoc! {b"@s1 start_time=2022-12-12T12:00:00Z
+    C
+    +
+    1
+    @s3 start_time=2022-12-12T13:00:00+01:00
+    T
+    +
+    1
+    @s0 start_time=2022-12-12T15:00:00Z
+    A
+    +
+    1
+    "};
+
+    // sorted in memory, and with each read in its own temporary file
+    for memory in ["1G", "1"] {
+        let mut cmd = ontime();
+        let output = cmd
+            .args([
+                "--sort-output",
+                "--sort-memory",
+                memory,
+                "-t",
+                "-1h",
+                chunk0.to_str().unwrap(),
+                chunk1.to_str().unwrap(),
+            ])
+            .unwrap()
+            .stdout;
+
+        assert_eq!(output, expected);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn sort_sam_sets_subsort_order() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let sam = dir.path().join("in.sam");
+    std::fs::write(
+        &sam,
+        indoc! {"@HD\tVN:1.6\tSO:unsorted
+        r0\t4\t*\t0\t0\t*\t*\t0\t0\tA\t1\tst:Z:2022-12-12T13:00:00Z
+        r1\t4\t*\t0\t0\t*\t*\t0\t0\tC\t1\tst:Z:2022-12-12T12:00:00Z
+        r2\t4\t*\t0\t0\t*\t*\t0\t0\tG\t1\tst:Z:2022-12-12T12:30:00Z
+        "},
+    )?;
+    let bam = dir.path().join("out.bam");
+
+    let mut cmd = ontime();
+    cmd.args([
+        "--sort-output",
+        "--sort-memory",
+        "1",
+        sam.to_str().unwrap(),
+        "-o",
+        bam.to_str().unwrap(),
+    ])
+    .unwrap();
+
+    let mut cmd = ontime();
+    let output = cmd.args([bam.to_str().unwrap()]).unwrap().stdout;
+
+    let expected = indoc! {"@HD\tVN:1.6\tSO:unknown\tSS:unknown:start_time
+    @CO\tontime: kept 3 reads
+    r1\t4\t*\t0\t0\t*\t*\t0\t0\tC\t1\tst:Z:2022-12-12T12:00:00Z
+    r2\t4\t*\t0\t0\t*\t*\t0\t0\tG\t1\tst:Z:2022-12-12T12:30:00Z
+    r0\t4\t*\t0\t0\t*\t*\t0\t0\tA\t1\tst:Z:2022-12-12T13:00:00Z
+    "};
+
+    assert_eq!(without_ontime_programs(&output), expected);
+
+    Ok(())
+}
+
+#[test]
+fn sort_keeps_records_without_qualities() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let sam = dir.path().join("in.sam");
+    std::fs::write(
+        &sam,
+        indoc! {"@HD\tVN:1.6\tSO:unsorted
+        r0\t4\t*\t0\t0\t*\t*\t0\t0\tACGT\t*\tst:Z:2022-12-12T13:00:00Z
+        r1\t4\t*\t0\t0\t*\t*\t0\t0\t*\t*\tst:Z:2022-12-12T12:00:00Z
+        r2\t4\t*\t0\t0\t*\t*\t0\t0\tGT\t12\tst:Z:2022-12-12T12:30:00Z
+        "},
+    )?;
+    let expected = indoc! {"@HD\tVN:1.6\tSO:unknown\tSS:unknown:start_time
+    @CO\tontime: kept 3 reads
+    r1\t4\t*\t0\t0\t*\t*\t0\t0\t*\t*\tst:Z:2022-12-12T12:00:00Z
+    r2\t4\t*\t0\t0\t*\t*\t0\t0\tGT\t12\tst:Z:2022-12-12T12:30:00Z
+    r0\t4\t*\t0\t0\t*\t*\t0\t0\tACGT\t*\tst:Z:2022-12-12T13:00:00Z
+    "};
+
+    for ext in ["sam", "bam"] {
+        let out = dir.path().join(format!("out.{ext}"));
+        let mut cmd = ontime();
+        cmd.args([
+            "--sort-output",
+            sam.to_str().unwrap(),
+            "-o",
+            out.to_str().unwrap(),
+        ])
+        .unwrap();
+
+        let mut cmd = ontime();
+        let output = cmd.args([out.to_str().unwrap()]).unwrap().stdout;
+
+        assert_eq!(without_ontime_programs(&output), expected, "{ext} output");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn start_time_index_is_written_and_reused() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let fastq = dir.path().join("reads.fq");
+    std::fs::write(
+        &fastq,
+        indoc! {"@s0 start_time=2022-12-12T18:00:00Z
+        A
+        +
+        1
+        @s1 start_time=2022-12-12T12:00:00Z
+        C
+        +
+        1
+        "},
+    )?;
+    let index = dir.path().join("reads.fq.ontime");
+
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    cmd.args(["--no-index", "--show", fastq.to_str().unwrap()])
+        .unwrap();
+    assert!(!index.exists());
+
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    cmd.args(["--show", fastq.to_str().unwrap()]).unwrap();
+    assert!(index.exists());
+
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    let output = cmd
+        .args(["--to", "1h", fastq.to_str().unwrap()])
+        .unwrap()
+        .stdout;
+    assert_eq!(
+        output,
+        b"@s1 start_time=2022-12-12T12:00:00Z\nC\n+\n1\n".to_vec()
+    );
+
+    // the index is rebuilt when the input changes
+    std::fs::write(&fastq, "@s2 start_time=2022-12-12T10:00:00Z\nGT\n+\n12\n")?;
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    let output = cmd
+        .args(["--show", fastq.to_str().unwrap()])
+        .unwrap()
+        .stdout;
+    let expected = indoc! {b"Earliest: 2022-12-12T10:00:00.0Z
+    Latest  : 2022-12-12T10:00:00.0Z
+    "};
+    assert_eq!(output, expected);
+
+    Ok(())
+}
+
+#[test]
+fn index_subcommand() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let sam = dir.path().join("in.sam");
+    std::fs::copy("tests/cases/test.sam", &sam)?;
+    let bam = dir.path().join("in.bam");
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    cmd.args([
+        "--no-index",
+        sam.to_str().unwrap(),
+        "-o",
+        bam.to_str().unwrap(),
+    ])
+    .unwrap();
+
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    let stderr = cmd
+        .args(["index", dir.path().to_str().unwrap()])
+        .unwrap()
+        .stderr;
+    let stderr = String::from_utf8(stderr)?;
+
+    assert!(stderr.contains("in.bam.ontime"));
+    assert!(stderr.contains("in.sam.ontime"));
+
+    // the indices of SAM and BAM give the same start times
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    let from_sam = cmd.args(["--show", sam.to_str().unwrap()]).unwrap().stdout;
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    let from_bam = cmd.args(["--show", bam.to_str().unwrap()]).unwrap().stdout;
+    assert_eq!(from_sam, from_bam);
+
+    Ok(())
+}
+
+#[test]
+fn indexed_bam_extraction_matches_scan() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let bam = dir.path().join("in.bam");
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    cmd.args([
+        "--no-index",
+        "tests/cases/test.sam",
+        "-o",
+        bam.to_str().unwrap(),
+    ])
+    .unwrap();
+
+    let args = ["-r", "..1h", "-r", "3h..5h", bam.to_str().unwrap()];
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    let scanned = cmd.arg("--no-index").args(args).unwrap().stdout;
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    let seeked = cmd.args(args).unwrap().stdout;
+
+    assert!(dir.path().join("in.bam.ontime").exists());
+    assert!(scanned.lines().any(|l| !l.starts_with(b"@")));
+    assert_eq!(
+        without_ontime_programs(&seeked),
+        without_ontime_programs(&scanned)
+    );
+
+    Ok(())
+}
+
+#[test]
+fn indexed_bam_extraction_keeps_records_without_qualities() -> Result<(), Box<dyn std::error::Error>>
+{
+    let dir = tempfile::tempdir()?;
+    let sam = dir.path().join("in.sam");
+    std::fs::write(
+        &sam,
+        indoc! {"@HD\tVN:1.6\tSO:unsorted
+        r0\t4\t*\t0\t0\t*\t*\t0\t0\tACGT\t*\tst:Z:2022-12-12T13:00:00Z
+        r1\t4\t*\t0\t0\t*\t*\t0\t0\tAC\t12\tst:Z:2022-12-12T12:00:00Z
+        "},
+    )?;
+    let bam = dir.path().join("in.bam");
+    let mut cmd = ontime();
+    cmd.args([sam.to_str().unwrap(), "-o", bam.to_str().unwrap()])
+        .unwrap();
+
+    let args = ["-f", "30m", bam.to_str().unwrap()];
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    let seeked = cmd.args(args).unwrap().stdout;
+    assert!(dir.path().join("in.bam.ontime").exists());
+    assert!(seeked.ends_with(b"r0\t4\t*\t0\t0\t*\t*\t0\t0\tACGT\t*\tst:Z:2022-12-12T13:00:00Z\n"));
+
+    let fastq = dir.path().join("out.fq");
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    cmd.args(args)
+        .args(["-o", fastq.to_str().unwrap()])
+        .unwrap();
+    let expected = "@r0\tst:Z:2022-12-12T13:00:00Z\nACGT\n+\n!!!!\n";
+    assert_eq!(std::fs::read_to_string(fastq)?, expected);
+
+    Ok(())
+}
+
+#[test]
+fn indexed_bgzf_fastq_extraction_matches_scan() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let fastq = dir.path().join("in.fq");
+    // enough reads for the compressed file to have several BGZF blocks
+    let mut text = String::new();
+    for i in 0..3000 {
+        let hour = [13, 12, 15, 14][i % 4];
+        text.push_str(&format!(
+            "@r{} start_time=2022-12-12T{}:{:02}:00Z\n{}\n+\n{}\n",
+            i,
+            hour,
+            i % 60,
+            "ACGT".repeat(25),
+            "I".repeat(100)
+        ));
+    }
+    std::fs::write(&fastq, text)?;
+    // gzip output is written as BGZF with more than one thread
+    let bgzf = dir.path().join("in.fq.gz");
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    cmd.args([
+        "--no-index",
+        "-@",
+        "2",
+        fastq.to_str().unwrap(),
+        "-o",
+        bgzf.to_str().unwrap(),
+    ])
+    .unwrap();
+
+    let args = ["-f", "30m", "-t", "1h30m", bgzf.to_str().unwrap()];
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    let scanned = cmd.arg("--no-index").args(args).unwrap().stdout;
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    let seeked = cmd.args(args).unwrap().stdout;
+
+    assert!(!scanned.is_empty());
+    assert_eq!(seeked, scanned);
+
+    Ok(())
+}
+
+#[test]
+fn parallel_parsing_matches_single_thread() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let fastq = dir.path().join("in.fq");
+    let mut text = String::new();
+    for i in 0..3000 {
+        let hour = [13, 12, 15, 14][i % 4];
+        text.push_str(&format!(
+            "@r{} start_time=2022-12-12T{}:{:02}:00Z\n{}\n+\n{}\n",
+            i,
+            hour,
+            i % 60,
+            "ACGT".repeat(i % 10 + 1),
+            "I".repeat(4 * (i % 10 + 1))
+        ));
+    }
+    std::fs::write(&fastq, text)?;
+
+    let args = [
+        "--no-index",
+        "-f",
+        "30m",
+        "-t",
+        "1h30m",
+        fastq.to_str().unwrap(),
+    ];
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    let single = cmd.args(args).unwrap().stdout;
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    let parallel = cmd.args(["-@", "4"]).args(args).unwrap().stdout;
+
+    assert!(!single.is_empty());
+    assert_eq!(parallel, single);
+
+    Ok(())
+}
+
+#[test]
+fn bgzf_fastq_output_with_indices() -> Result<(), Box<dyn std::error::Error>> {
+    let text = indoc! {b"@s0 start_time=2022-12-12T18:00:00Z
+    ACGT
+    +
+    1234
+    @s1 start_time=2022-12-12T12:00:00Z
+    C
+    +
+    1
+    "};
+    let dir = tempfile::tempdir()?;
+    let input = dir.path().join("in.fq");
+    std::fs::write(&input, text)?;
+
+    for (output_name, extra_args) in [("out.fq.bgz", vec![]), ("out.fq.gz", vec!["-O", "bgzf"])] {
+        let output = dir.path().join(output_name);
+        let mut cmd = ontime();
+        cmd.args(extra_args)
+            .args(["--write-index", "-o", output.to_str().unwrap()])
+            .arg(&input)
+            .assert()
+            .success();
+
+        let mut prefix = [0; 16];
+        std::fs::File::open(&output)?.read_exact(&mut prefix)?;
+        assert_eq!(&prefix[12..14], b"BC");
+        let (mut reader, _) = niffler::from_path(&output)?;
+        let mut actual = vec![];
+        reader.read_to_end(&mut actual)?;
+        assert_eq!(actual, text);
+
+        let fqi = std::fs::read_to_string(dir.path().join(format!("{output_name}.fqi")))?;
+        assert_eq!(fqi, "s0\t4\t36\t4\t5\t43\ns1\t1\t84\t1\t2\t88\n");
+        assert!(dir.path().join(format!("{output_name}.gzi")).exists());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn write_index_needs_indexable_output() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let sam = dir.path().join("out.sam");
+    let mut cmd = ontime();
+    cmd.args(["--write-index", "-o", sam.to_str().unwrap()])
+        .arg("tests/cases/test.sam")
+        .assert()
+        .failure();
+
+    let output = dir.path().join("out.fq.gz");
+
+    let input = dir.path().join("in.fq");
+    std::fs::write(&input, "@s0 start_time=2022-12-12T18:00:00Z\nA\n+\n1\n")?;
+    let mut cmd = ontime();
+    let stderr = cmd
+        .args(["--write-index", "-o", output.to_str().unwrap()])
+        .arg(&input)
+        .assert()
+        .failure()
+        .get_output()
+        .stderr
+        .clone();
+    assert!(stderr.contains_str("only uncompressed or BGZF"));
+    // the output is rejected before any reads are extracted
+    assert!(!output.exists());
+
+    Ok(())
+}
+
+#[test]
+fn write_index_for_coordinate_sorted_bam() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let sam = dir.path().join("in.sam");
+    std::fs::write(
+        &sam,
+        indoc! {"@HD\tVN:1.6\tSO:coordinate
+        @SQ\tSN:chr1\tLN:24
+        r1\t0\tchr1\t1\t60\t8M\t*\t0\t0\tACGTACGT\tIIIIIIII\tst:Z:2022-12-12T12:00:00Z
+        r2\t0\tchr1\t9\t60\t8M\t*\t0\t0\tAAAACCCC\tIIIIIIII\tst:Z:2022-12-12T14:00:00Z
+        r3\t0\tchr1\t17\t60\t8M\t*\t0\t0\tGGGGTTTT\tIIIIIIII\tst:Z:2022-12-12T13:00:00Z
+        "},
+    )?;
+
+    for (flag, extension, magic) in [
+        ("--write-index", "bai", b"BAI\x01"),
+        ("--write-index=bai", "bai", b"BAI\x01"),
+        ("--write-index=csi", "csi", b"CSI\x01"),
+    ] {
+        let bam = dir.path().join(format!("{extension}.bam"));
+        let mut cmd = ontime();
+        cmd.args([flag, "-t", "1h", "-o", bam.to_str().unwrap()])
+            .arg(&sam)
+            .assert()
+            .success();
+
+        let (mut reader, _) =
+            niffler::from_path(dir.path().join(format!("{extension}.bam.{extension}")))?;
+        let mut index = vec![];
+        reader.read_to_end(&mut index)?;
+        assert_eq!(&index[..4], magic);
+
+        let mut cmd = ontime();
+        let output = cmd
+            .args(["--output-format", "sam", bam.to_str().unwrap()])
+            .unwrap()
+            .stdout;
+        let names = output
+            .lines()
+            .filter(|l| !l.starts_with(b"@"))
+            .map(|l| {
+                l.split_str("\t")
+                    .next()
+                    .unwrap()
+                    .to_str()
+                    .unwrap()
+                    .to_string()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["r1", "r3"]);
+    }
+
+    // output that is not coordinate-sorted is written without an index
+    let bam = dir.path().join("sorted_by_time.bam");
+    let mut cmd = ontime();
+    let stderr = cmd
+        .args([
+            "--write-index",
+            "--sort-output",
+            "-o",
+            bam.to_str().unwrap(),
+        ])
+        .arg(&sam)
+        .unwrap()
+        .stderr;
+    assert!(stderr.contains_str("not coordinate-sorted"));
+    assert!(bam.exists());
+    assert!(!dir.path().join("sorted_by_time.bam.bai").exists());
+
+    Ok(())
+}
+
+#[test]
+fn sam_input_fastq_output() -> Result<(), Box<dyn std::error::Error>> {
+    let input = "tests/cases/test.sam";
+    let dir = tempfile::tempdir()?;
+    let fastq = dir.path().join("out.fq");
+    let mut cmd = ontime();
+    cmd.args(["-t", "-4h", "-o", fastq.to_str().unwrap(), input])
+        .unwrap();
+
+    let text = std::fs::read(&fastq)?;
+    let lines = text.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 8 * 4);
+    let header = lines[0].to_str()?;
+    assert!(header.starts_with('@'));
+    assert!(header.contains("\tst:Z:"));
+    assert!(header.contains("\tch:i:"));
+    assert!(header.contains("\tqs:i:"));
+    assert_eq!(lines[1].len(), lines[3].len());
+
+    // the start times survive the conversion
+    let all = dir.path().join("all.fq");
+    let mut cmd = ontime();
+    cmd.args(["-o", all.to_str().unwrap(), input]).unwrap();
+    let mut cmd = ontime();
+    let from_sam = cmd.args(["-s", input]).unwrap().stdout;
+    let mut cmd = ontime();
+    let from_fastq = cmd.args(["-s", all.to_str().unwrap()]).unwrap().stdout;
+    assert_eq!(from_fastq, from_sam);
+
+    // stdout needs the output format, and a fasta extension gives fasta
+    let mut cmd = ontime();
+    let stdout = cmd
+        .args(["--output-format", "fastx", "-t", "-4h", input])
+        .unwrap()
+        .stdout;
+    assert_eq!(stdout, text);
+    let fasta = dir.path().join("out.fa");
+    let mut cmd = ontime();
+    cmd.args(["-t", "-4h", "-o", fasta.to_str().unwrap(), input])
+        .unwrap();
+    let fasta = std::fs::read(&fasta)?;
+    assert_eq!(fasta.lines().count(), 8 * 2);
+    assert!(fasta.starts_with(b">"));
+
+    Ok(())
+}
+
+#[test]
+fn fastq_input_unaligned_output() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let input = dir.path().join("in.fq");
+    std::fs::write(
+        &input,
+        indoc! {"
+            @s0 runid=r1 ch=7 start_time=2022-12-12T18:00:00Z flow_cell_id=FAQ1 basecall_model_version_id=m1
+            ACGT
+            +
+            !\"#$
+            @s1 runid=r2 ch=8 start_time=2022-12-12T18:01:00Z
+            AC
+            +
+            !!
+            @s2\tst:Z:2022-12-12T18:02:00Z\tRG:Z:r3
+            A
+            +
+            5
+        "},
+    )?;
+    let input = input.to_str().unwrap();
+
+    let output = dir.path().join("out.sam");
+    let mut cmd = ontime();
+    cmd.args(["-t", "-1m", "-o", output.to_str().unwrap(), input])
+        .unwrap();
+    assert_eq!(
+        without_ontime_programs(&std::fs::read(&output)?),
+        indoc! {"
+            @HD\tVN:1.6
+            @RG\tID:r1_m1\tPU:FAQ1\tPL:ONT\tDS:basecall_model=m1 runid=r1
+            @RG\tID:r2\tPL:ONT\tDS:runid=r2
+            @RG\tID:r3
+            @CO\tontime: kept 2 reads
+            s0\t4\t*\t0\t0\t*\t*\t0\t0\tACGT\t!\"#$\tst:Z:2022-12-12T18:00:00Z\tch:i:7\tRG:Z:r1_m1
+            s1\t4\t*\t0\t0\t*\t*\t0\t0\tAC\t!!\tst:Z:2022-12-12T18:01:00Z\tch:i:8\tRG:Z:r2
+        "}
+    );
+
+    // the start times survive the conversion to BAM, in one pass or two
+    let mut cmd = ontime();
+    let from_fastq = cmd.args(["-s", input]).unwrap().stdout;
+    for args in [
+        vec![],
+        vec!["--from", "2022-12-12T18:00:00Z"],
+        vec!["--sort-output"],
+    ] {
+        let bam = dir.path().join("out.bam");
+        let mut cmd = ontime();
+        cmd.args(&args)
+            .args(["-o", bam.to_str().unwrap(), input])
+            .unwrap();
+        let mut cmd = ontime();
+        let from_bam = cmd.args(["-s", bam.to_str().unwrap()]).unwrap().stdout;
+        assert_eq!(from_bam, from_fastq);
+    }
+
+    // stdout needs the output format, and the header has the read groups of all reads
+    let mut cmd = ontime();
+    let stdout = cmd
+        .args([
+            "--output-format",
+            "sam",
+            "--from",
+            "2022-12-12T18:02:00Z",
+            input,
+        ])
+        .unwrap()
+        .stdout;
+    assert_eq!(
+        without_ontime_programs(&stdout),
+        "@HD\tVN:1.6\n@RG\tID:r1_m1\tPU:FAQ1\tPL:ONT\tDS:basecall_model=m1 runid=r1\n@RG\tID:r2\tPL:ONT\tDS:runid=r2\n@RG\tID:r3\n@CO\tontime: kept 1 reads\ns2\t4\t*\t0\t0\t*\t*\t0\t0\tA\t5\tst:Z:2022-12-12T18:02:00Z\tRG:Z:r3\n"
+    );
+
+    // split writes a header in each bin
+    let template = dir.path().join("bin_{bin}.sam");
+    let mut cmd = ontime_split();
+    cmd.args(["--every", "1m", "-o", template.to_str().unwrap(), input])
+        .unwrap();
+    let bin = std::fs::read_to_string(dir.path().join("bin_1.sam"))?;
+    assert!(bin.starts_with("@HD\tVN:1.6\n@RG\tID:r1_m1\t"));
+    assert!(bin.contains("\n@RG\tID:r3\n"));
+    assert!(bin.contains("\n@CO\tontime: kept 1 reads\n"));
+    assert_eq!(bin.lines().filter(|l| !l.starts_with('@')).count(), 1);
+
+    Ok(())
+}
+
+#[test]
+fn alignment_output_records_provenance() -> Result<(), Box<dyn std::error::Error>> {
+    let input = "tests/cases/test.sam";
+    let dir = tempfile::tempdir()?;
+    let template = dir.path().join("{window}.sam");
+    let mut cmd = ontime();
+    cmd.args(["-w", "2h,24h", "-o", template.to_str().unwrap(), input])
+        .unwrap();
+
+    let programs = |sam: &str| {
+        sam.lines()
+            .filter(|l| l.starts_with("@PG\tID:ontime"))
+            .map(|l| l.to_string())
+            .collect::<Vec<_>>()
+    };
+    let comments = |sam: &str| {
+        sam.lines()
+            .filter(|l| l.starts_with("@CO"))
+            .map(|l| l.to_string())
+            .collect::<Vec<_>>()
+    };
+    let first = std::fs::read_to_string(dir.path().join("2h.sam"))?;
+    let pg = programs(&first);
+    assert_eq!(pg.len(), 1);
+    let prefix = format!(
+        "@PG\tID:ontime\tPN:ontime\tPP:samtools\tVN:{}\tCL:",
+        env!("CARGO_PKG_VERSION")
+    );
+    assert!(pg[0].starts_with(&prefix));
+    assert!(pg[0].contains(" -w 2h,24h -o "));
+    assert!(
+        pg[0].ends_with("\tDS:earliest=2023-09-22T20:54:22.039Z latest=2023-09-22T22:54:22.039Z")
+    );
+    assert_eq!(comments(&first), vec!["@CO\tontime: kept 1 reads"]);
+    let second = std::fs::read_to_string(dir.path().join("24h.sam"))?;
+    assert!(programs(&second)[0]
+        .ends_with("\tDS:earliest=2023-09-22T20:54:22.039Z latest=2023-09-23T20:54:22.039Z"));
+    assert_eq!(comments(&second), vec!["@CO\tontime: kept 4 reads"]);
+
+    // another run is chained to the first, and its count replaces the first one
+    let output = dir.path().join("out.sam");
+    let mut cmd = ontime();
+    cmd.args([
+        "-o",
+        output.to_str().unwrap(),
+        dir.path().join("24h.sam").to_str().unwrap(),
+    ])
+    .unwrap();
+    let output = std::fs::read_to_string(output)?;
+    let pg = programs(&output);
+    assert_eq!(pg.len(), 2);
+    assert!(pg[1].starts_with("@PG\tID:ontime.1\tPN:ontime\tPP:ontime\t"));
+    assert!(
+        pg[1].ends_with("\tDS:earliest=2023-09-22T20:54:22.039Z latest=2023-09-23T18:29:18.557Z")
+    );
+    assert_eq!(comments(&output), vec!["@CO\tontime: kept 4 reads"]);
+
+    // a timeframe of timestamps is resolved against the start times too
+    let mut cmd = ontime();
+    let output = cmd
+        .args(["-t", "2023-09-22T22:00:00Z", input])
+        .unwrap()
+        .stdout;
+    let output = String::from_utf8(output)?;
+    assert!(programs(&output)[0]
+        .ends_with("\tDS:earliest=2023-09-22T20:54:22.039Z latest=2023-09-22T22:00:00.0Z"));
+    assert_eq!(comments(&output), vec!["@CO\tontime: kept 1 reads"]);
+
+    Ok(())
+}